@@ -0,0 +1,65 @@
+use azerothvm::bytecode::class::Class;
+use std::env;
+use std::fs::File;
+use std::hint::black_box;
+use std::io::Read;
+use std::time::Instant;
+
+// The cost of parsing every class of a jar. The classes are inflated up front so only the
+// parser is measured, the jar is the argument or `$JAVA_HOME/jre/lib/rt.jar`.
+//
+// cargo bench --bench classload -- /path/to/rt.jar
+
+const ROUNDS: u32 = 5;
+
+fn classes(jar: &str) -> Vec<Vec<u8>> {
+    let file = File::open(jar).expect("jar_not_found");
+    let mut archive = zip::ZipArchive::new(file).expect("illegal_jar");
+    let mut classes = vec![];
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).unwrap();
+        if entry.name().ends_with(".class") {
+            let mut buf = Vec::<u8>::with_capacity(entry.size() as usize);
+            entry.read_to_end(&mut buf).unwrap();
+            classes.push(buf);
+        }
+    }
+    classes
+}
+
+fn main() {
+    // `cargo bench` passes `--bench` along
+    let jar = env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .or_else(|| {
+            env::var("JAVA_HOME")
+                .ok()
+                .map(|home| format!("{}/jre/lib/rt.jar", home))
+        })
+        .expect("no jar to load, pass one or set JAVA_HOME");
+    let classes = classes(&jar);
+    let bytes = classes.iter().map(|class| class.len()).sum::<usize>();
+    let mut malformed = 0;
+    let start = Instant::now();
+    for _ in 0..ROUNDS {
+        malformed = 0;
+        for class in &classes {
+            match Class::from_slice(class) {
+                Ok(class) => {
+                    black_box(class);
+                }
+                Err(_) => malformed = malformed + 1,
+            }
+        }
+    }
+    let elapsed = start.elapsed() / ROUNDS;
+    println!(
+        "{} classes, {} bytes, {} malformed: {:>8.2} ms, {:>8.2} MB/s",
+        classes.len(),
+        bytes,
+        malformed,
+        elapsed.as_secs_f64() * 1000.0,
+        bytes as f64 / elapsed.as_secs_f64() / 1_000_000.0
+    );
+}
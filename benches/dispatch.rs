@@ -0,0 +1,109 @@
+use azerothvm::bytecode::{
+    class::Class,
+    constant_pool::{ConstantItem, ConstantPool},
+    method::Method,
+};
+use azerothvm::mem::{klass::Klass, metaspace::ROOT_CLASSLOADER, pool::RuntimeConstantPool};
+use std::hint::black_box;
+use std::sync::{Arc, RwLock};
+use std::time::Instant;
+
+// The cost of selecting the method of an invokevirtual instruction. Before vtables were
+// indexed every call looked the method up by its name and descriptor, now the method is
+// resolved to a slot once and the receiver's vtable is indexed by it.
+//
+// cargo bench --bench dispatch
+
+const ITERATIONS: u32 = 10_000_000;
+
+fn class(name: &str, superclass: &str, methods: &[String]) -> Arc<Class> {
+    Arc::new(Class {
+        constant_pool: ConstantPool::new(vec![ConstantItem::NIL]),
+        access_flag: 0x0021,
+        this_class_name: name.to_owned(),
+        super_class_name: superclass.to_owned(),
+        interfaces: vec![],
+        fields: vec![],
+        methods: methods
+            .iter()
+            .map(|name| {
+                Arc::new(Method {
+                    access_flag: 0x0001,
+                    name: name.clone(),
+                    descriptor: "()V".to_owned(),
+                    attributes: vec![],
+                    decoded: RwLock::new(None),
+                })
+            })
+            .collect(),
+        attributes: vec![],
+        runtime_pool: RuntimeConstantPool::new(),
+    })
+}
+
+// three levels of 16 methods each, the leaf overriding half of its superclass's
+fn hierarchy() -> Klass {
+    let names = |prefix: &str| {
+        (0..16)
+            .map(|i| format!("{}{}", prefix, i))
+            .collect::<Vec<_>>()
+    };
+    let base = Klass::new(
+        class("Base", "java/lang/Object", &names("base")),
+        ROOT_CLASSLOADER,
+        None,
+        vec![],
+    );
+    let middle = Klass::new(
+        class("Middle", "Base", &names("middle")),
+        ROOT_CLASSLOADER,
+        Some(Arc::new(base)),
+        vec![],
+    );
+    let mut leaf = names("middle")[..8].to_vec();
+    leaf.extend(names("leaf"));
+    Klass::new(
+        class("Leaf", "Middle", &leaf),
+        ROOT_CLASSLOADER,
+        Some(Arc::new(middle)),
+        vec![],
+    )
+}
+
+fn measure<F: FnMut() -> usize>(name: &str, mut f: F) {
+    let start = Instant::now();
+    let mut sum = 0usize;
+    for _ in 0..ITERATIONS {
+        sum = sum.wrapping_add(f());
+    }
+    let elapsed = start.elapsed();
+    black_box(sum);
+    println!(
+        "{:<8} {:>8.2} ns/call",
+        name,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    let klass = hierarchy();
+    let calls = ["base3", "middle5", "leaf12"];
+    let slots = calls
+        .iter()
+        .map(|m| klass.get_vtable_slot(m, "()V").unwrap())
+        .collect::<Vec<_>>();
+    let mut i = 0;
+    measure("before", || {
+        i = (i + 1) % calls.len();
+        let (_, method) = klass
+            .get_method_in_vtable(black_box(calls[i]), black_box("()V"))
+            .unwrap();
+        *method as usize
+    });
+    let mut i = 0;
+    measure("after", || {
+        i = (i + 1) % slots.len();
+        let (_, method) = klass.vtable[black_box(slots[i])];
+        method as usize
+    });
+}
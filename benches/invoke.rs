@@ -0,0 +1,194 @@
+use azerothvm::{
+    bytecode::{class::Class, code::InlineCache},
+    gc,
+    interpreter::{callsite::CallSites, thread::ThreadGroup},
+    mem::{
+        heap::Heap,
+        klass::Klass,
+        metaspace::{ClassArena, ROOT_CLASSLOADER},
+        strings::Strings,
+    },
+};
+use std::hint::black_box;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// The cost of an invokevirtual or invokeinterface run by the interpreter, from resolving the
+// method through the runtime constant pool to selecting it by the inline cache or the vtable
+// and itable slots. Each method makes a million calls, a loop without calls is subtracted.
+// The selection alone is measured apart, since it is a small part of an interpreted call.
+//
+// cargo bench --bench invoke
+
+const CALLS: u32 = 1_000_000;
+
+const ROUNDS: u32 = 20;
+
+const SELECTIONS: u32 = 10_000_000;
+
+// a java.lang.Object with nothing but its constructor, so no JDK is needed
+const OBJECT: &'static str = "yv66vgAAADQABgEAEGphdmEvbGFuZy9PYmplY3QHAAEBAAY8aW5pdD4BAAMoKVYBAARDb2RlACEAAgAAAAAAAAABAAEAAwAEAAEABQAAAA0AAAABAAAAAbEAAAAAAAA=";
+
+// javac --release 8 -g:none
+//
+// public interface InvokeShape { int area(); }
+// public abstract class InvokeBench implements InvokeShape {
+//     public abstract int area();
+//     // `if (shapes[i & 7] != null) total++;` a million times over `mono()`
+//     public static void loop(String[] args) { ... }
+//     // `total += shapes[i & 7].area();` a million times over `mono()` or `mega()`, through
+//     // an `InvokeBench[]` or an `InvokeShape[]`
+//     public static void virtualMono(String[] args) { ... }
+//     public static void virtualMega(String[] args) { ... }
+//     public static void interfaceMono(String[] args) { ... }
+//     public static void interfaceMega(String[] args) { ... }
+//     // eight times the same InvokeOne
+//     static InvokeBench[] mono() { ... }
+//     // InvokeOne to InvokeFive, then InvokeOne to InvokeThree
+//     static InvokeBench[] mega() { ... }
+// }
+// class InvokeOne extends InvokeBench { public int area() { return 1; } }
+// and InvokeTwo to InvokeFive returning 2 to 5
+
+const INVOKE_SHAPE: &'static str = "yv66vgAAADQABwcAAgEAC0ludm9rZVNoYXBlBwAEAQAQamF2YS9sYW5nL09iamVjdAEABGFyZWEBAAMoKUkGAQABAAMAAAAAAAEEAQAFAAYAAAAA";
+
+const INVOKE_BENCH: &'static str = "yv66vgAAADQAMwoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWCgAIAAkHAAoMAAsADAEAC0ludm9rZUJlbmNoAQAEbW9ubwEAECgpW0xJbnZva2VCZW5jaDsDAA9CQAoACAAPDAAQABEBAARhcmVhAQADKClJCgAIABMMABQADAEABG1lZ2ELABYADwcAFwEAC0ludm9rZVNoYXBlBwAZAQAJSW52b2tlT25lCgAYAAMHABwBAAlJbnZva2VUd28KABsAAwcAHwEAC0ludm9rZVRocmVlCgAeAAMHACIBAApJbnZva2VGb3VyCgAhAAMHACUBAApJbnZva2VGaXZlCgAkAAMBAARDb2RlAQAEbG9vcAEAFihbTGphdmEvbGFuZy9TdHJpbmc7KVYBAA1TdGFja01hcFRhYmxlBwAsAQAOW0xJbnZva2VCZW5jaDsBAAt2aXJ0dWFsTW9ubwEAC3ZpcnR1YWxNZWdhAQANaW50ZXJmYWNlTW9ubwcAMQEADltMSW52b2tlU2hhcGU7AQANaW50ZXJmYWNlTWVnYQQhAAgAAgABABYAAAAJAAEABQAGAAEAJwAAABEAAQABAAAABSq3AAGxAAAAAAQBABAAEQAAAAkAKAApAAEAJwAAAEEAAwAEAAAAIbgAB0wDPQM+HRINogAVKx0QB34yxgAGhAIBhAMBp//rsQAAAAEAKgAAAA4AA/4ACAcAKwEBEfoABQAJAC0AKQABACcAAABAAAQABAAAACG4AAdMAz0DPh0SDaIAFRwrHRAHfjK2AA5gPYQDAaf/67EAAAABACoAAAANAAL+AAgHACsBAfoAFwAJAC4AKQABACcAAABAAAQABAAAACG4ABJMAz0DPh0SDaIAFRwrHRAHfjK2AA5gPYQDAaf/67EAAAABACoAAAANAAL+AAgHACsBAfoAFwAJAC8AKQABACcAAABCAAQABAAAACO4AAdMAz0DPh0SDaIAFxwrHRAHfjK5ABUBAGA9hAMBp//psQAAAAEAKgAAAA0AAv4ACAcAMAEB+gAZAAkAMgApAAEAJwAAAEIABAAEAAAAI7gAEkwDPQM+HRINogAXHCsdEAd+MrkAFQEAYD2EAwGn/+mxAAAAAQAqAAAADQAC/gAIBwAwAQH6ABkACAALAAwAAQAnAAAAPAAEAAEAAAAwuwAYWbcAGksQCL0ACFkDKlNZBCpTWQUqU1kGKlNZBypTWQgqU1kQBipTWRAHKlOwAAAAAAAIABQADAABACcAAABkAAUAAAAAAFgQCL0ACFkDuwAYWbcAGlNZBLsAG1m3AB1TWQW7AB5ZtwAgU1kGuwAhWbcAI1NZB7sAJFm3ACZTWQi7ABhZtwAaU1kQBrsAG1m3AB1TWRAHuwAeWbcAIFOwAAAAAAAA";
+
+const INVOKE_ONE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAtJbnZva2VCZW5jaAEABjxpbml0PgEAAygpVgcACAEACUludm9rZU9uZQEABENvZGUBAARhcmVhAQADKClJACAABwACAAAAAAACAAAABQAGAAEACQAAABEAAQABAAAABSq3AAGxAAAAAAABAAoACwABAAkAAAAOAAEAAQAAAAIErAAAAAAAAA==";
+
+const INVOKE_TWO: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAtJbnZva2VCZW5jaAEABjxpbml0PgEAAygpVgcACAEACUludm9rZVR3bwEABENvZGUBAARhcmVhAQADKClJACAABwACAAAAAAACAAAABQAGAAEACQAAABEAAQABAAAABSq3AAGxAAAAAAABAAoACwABAAkAAAAOAAEAAQAAAAIFrAAAAAAAAA==";
+
+const INVOKE_THREE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAtJbnZva2VCZW5jaAEABjxpbml0PgEAAygpVgcACAEAC0ludm9rZVRocmVlAQAEQ29kZQEABGFyZWEBAAMoKUkAIAAHAAIAAAAAAAIAAAAFAAYAAQAJAAAAEQABAAEAAAAFKrcAAbEAAAAAAAEACgALAAEACQAAAA4AAQABAAAAAgasAAAAAAAA";
+
+const INVOKE_FOUR: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAtJbnZva2VCZW5jaAEABjxpbml0PgEAAygpVgcACAEACkludm9rZUZvdXIBAARDb2RlAQAEYXJlYQEAAygpSQAgAAcAAgAAAAAAAgAAAAUABgABAAkAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAKAAsAAQAJAAAADgABAAEAAAACB6wAAAAAAAA=";
+
+const INVOKE_FIVE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAtJbnZva2VCZW5jaAEABjxpbml0PgEAAygpVgcACAEACkludm9rZUZpdmUBAARDb2RlAQAEYXJlYQEAAygpSQAgAAcAAgAAAAAAAgAAAAUABgABAAkAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAKAAsAAQAJAAAADgABAAEAAAACCKwAAAAAAAA=";
+
+fn define(
+    bytecode: &str,
+    superclass: Option<&Arc<Klass>>,
+    interfaces: &[&Arc<Klass>],
+) -> Arc<Klass> {
+    let class = Class::from_vec(base64::decode(bytecode).unwrap()).unwrap();
+    ClassArena::define(Klass::new(
+        Arc::new(class),
+        ROOT_CLASSLOADER,
+        superclass.cloned(),
+        interfaces.iter().map(|i| Arc::clone(i)).collect(),
+    ))
+}
+
+fn run(method: &str) -> Duration {
+    let start = Instant::now();
+    ThreadGroup::new_thread(
+        ROOT_CLASSLOADER,
+        "InvokeBench",
+        method,
+        "([Ljava/lang/String;)V",
+        true,
+    );
+    start.elapsed()
+}
+
+// the fastest round of each method, the methods take turns so a busier machine in between
+// weighs on all of them, the first run resolves and initializes everything
+fn measure(methods: &[&str]) -> Vec<Duration> {
+    let mut fastest = methods.iter().map(|method| run(method)).collect::<Vec<_>>();
+    for _ in 0..ROUNDS {
+        for (method, elapsed) in methods.iter().zip(fastest.iter_mut()) {
+            *elapsed = run(method).min(*elapsed);
+        }
+    }
+    fastest
+}
+
+// the method of `slot` for the receivers in turn, by the inline cache or else by the vtable as
+// invokevirtual selects it
+fn select(cache: &InlineCache, receivers: &[*const Klass], slot: usize) -> Duration {
+    let start = Instant::now();
+    for i in 0..SELECTIONS {
+        let receiver = black_box(receivers[i as usize & 7]);
+        let selected = match cache.get(receiver) {
+            Some(selected) => selected,
+            None => {
+                let selected = unsafe { &*receiver }.vtable[slot];
+                cache.put(receiver, selected);
+                selected
+            }
+        };
+        black_box(selected);
+    }
+    start.elapsed()
+}
+
+fn main() {
+    ClassArena::init(vec![], vec![]);
+    Heap::init(10 * 1024 * 1024, 1024 * 1024, 1024 * 1024);
+    Strings::init();
+    CallSites::init();
+    ThreadGroup::init();
+    gc::init();
+    let object = define(OBJECT, None, &[]);
+    let shape = define(INVOKE_SHAPE, Some(&object), &[]);
+    let bench = define(INVOKE_BENCH, Some(&object), &[&shape]);
+    let shapes = [
+        INVOKE_ONE,
+        INVOKE_TWO,
+        INVOKE_THREE,
+        INVOKE_FOUR,
+        INVOKE_FIVE,
+    ]
+    .iter()
+    .map(|bytecode| define(bytecode, Some(&bench), &[]))
+    .collect::<Vec<_>>();
+    let methods = [
+        "loop",
+        "virtualMono",
+        "virtualMega",
+        "interfaceMono",
+        "interfaceMega",
+    ];
+    let elapsed = measure(&methods);
+    let empty = elapsed[0];
+    println!(
+        "{:<16} {:>8.2} ns/iteration",
+        "loop",
+        empty.as_nanos() as f64 / CALLS as f64
+    );
+    for (method, elapsed) in methods.iter().zip(&elapsed).skip(1) {
+        let elapsed = elapsed.checked_sub(empty).unwrap_or_default();
+        println!(
+            "{:<16} {:>8.2} ns/call",
+            method,
+            elapsed.as_nanos() as f64 / CALLS as f64
+        );
+    }
+    // the receivers of mono() and mega()
+    let mono = [Arc::as_ptr(&shapes[0]); 8];
+    let mut mega = [Arc::as_ptr(&shapes[0]); 8];
+    for (i, receiver) in mega.iter_mut().enumerate() {
+        *receiver = Arc::as_ptr(&shapes[i % shapes.len()]);
+    }
+    let slot = bench
+        .vtable
+        .iter()
+        .position(|(_, method)| unsafe { &**method }.name == "area")
+        .unwrap();
+    let (mono_cache, mega_cache) = (InlineCache::default(), InlineCache::default());
+    mono_cache.put(mono[0], shapes[0].vtable[slot]);
+    for shape in &shapes {
+        mega_cache.put(Arc::as_ptr(shape), shape.vtable[slot]);
+    }
+    let mut fastest = [Duration::MAX; 2];
+    for _ in 0..ROUNDS {
+        fastest[0] = select(&mono_cache, &mono, slot).min(fastest[0]);
+        fastest[1] = select(&mega_cache, &mega, slot).min(fastest[1]);
+    }
+    for (name, elapsed) in ["selectMono", "selectMega"].iter().zip(&fastest) {
+        println!(
+            "{:<16} {:>8.2} ns/selection",
+            name,
+            elapsed.as_nanos() as f64 / SELECTIONS as f64
+        );
+    }
+}
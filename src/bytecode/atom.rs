@@ -1,5 +1,4 @@
-use super::{constant_pool::ConstantPool, Traveler};
-use std::mem::transmute;
+use super::{constant_pool::ConstantPool, ClassFormatError, Reader, Traveler};
 
 pub type U1 = u8;
 pub type U2 = u16;
@@ -7,54 +6,25 @@ pub type U4 = u32;
 pub type U8 = u64;
 
 impl Traveler<U1> for U1 {
-    fn read<I>(seq: &mut I, _constants: Option<&ConstantPool>) -> U1
-    where
-        I: Iterator<Item = u8>,
-    {
-        seq.next().unwrap()
+    fn read(seq: &mut Reader, _constants: Option<&ConstantPool>) -> Result<U1, ClassFormatError> {
+        Ok(U1::from_be_bytes(seq.array()?))
     }
 }
 
 impl Traveler<U2> for U2 {
-    fn read<I>(seq: &mut I, _constants: Option<&ConstantPool>) -> U2
-    where
-        I: Iterator<Item = u8>,
-    {
-        let u0 = seq.next().unwrap();
-        let u1 = seq.next().unwrap();
-        let u = [u1, u0];
-        unsafe { transmute::<[u8; 2], u16>(u) }
+    fn read(seq: &mut Reader, _constants: Option<&ConstantPool>) -> Result<U2, ClassFormatError> {
+        Ok(U2::from_be_bytes(seq.array()?))
     }
 }
 
 impl Traveler<U4> for U4 {
-    fn read<I>(seq: &mut I, _constants: Option<&ConstantPool>) -> U4
-    where
-        I: Iterator<Item = u8>,
-    {
-        let u0 = seq.next().unwrap();
-        let u1 = seq.next().unwrap();
-        let u2 = seq.next().unwrap();
-        let u3 = seq.next().unwrap();
-        let u = [u3, u2, u1, u0];
-        unsafe { transmute::<[u8; 4], u32>(u) }
+    fn read(seq: &mut Reader, _constants: Option<&ConstantPool>) -> Result<U4, ClassFormatError> {
+        Ok(U4::from_be_bytes(seq.array()?))
     }
 }
 
 impl Traveler<U8> for U8 {
-    fn read<I>(seq: &mut I, _constants: Option<&ConstantPool>) -> U8
-    where
-        I: Iterator<Item = u8>,
-    {
-        let u0 = seq.next().unwrap();
-        let u1 = seq.next().unwrap();
-        let u2 = seq.next().unwrap();
-        let u3 = seq.next().unwrap();
-        let u4 = seq.next().unwrap();
-        let u5 = seq.next().unwrap();
-        let u6 = seq.next().unwrap();
-        let u7 = seq.next().unwrap();
-        let u = [u7, u6, u5, u4, u3, u2, u1, u0];
-        unsafe { transmute::<[u8; 8], u64>(u) }
+    fn read(seq: &mut Reader, _constants: Option<&ConstantPool>) -> Result<U8, ClassFormatError> {
+        Ok(U8::from_be_bytes(seq.array()?))
     }
 }
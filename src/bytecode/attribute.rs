@@ -1,5 +1,5 @@
-use super::{atom::*, constant_pool::ConstantPool, Traveler};
-use std::sync::Arc;
+use super::{atom::*, constant_pool::ConstantPool, ClassFormatError, Reader, Traveler};
+use std::sync::{Arc, RwLock};
 
 pub type Attributes = Vec<Attribute>;
 
@@ -10,8 +10,136 @@ pub struct ExceptionHandler {
     pub catch_type: Option<String>,
 }
 
+pub struct BootstrapMethod {
+    pub method_ref: U2,
+    pub arguments: Vec<U2>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineNumber {
+    pub start_pc: U2,
+    pub line_number: U2,
+}
+
+pub type LineNumberTable = Vec<LineNumber>;
+
+/// a local variable in `index` from `start_pc` until `start_pc + length`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariable {
+    pub start_pc: U2,
+    pub length: U2,
+    pub name: String,
+    pub descriptor: String,
+    pub index: U2,
+}
+
+pub type LocalVariableTable = Vec<LocalVariable>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VerificationType {
+    Top,
+    Integer,
+    Float,
+    Double,
+    Long,
+    Null,
+    UninitializedThis,
+    /// the name of the class, arrays are named by their descriptors
+    Object(String),
+    /// the offset of the `new` creating the object
+    Uninitialized(U2),
+}
+
+impl VerificationType {
+    /// the type of a value of the field `descriptor`
+    pub fn of(descriptor: &str) -> VerificationType {
+        match &descriptor[..1] {
+            "I" | "S" | "B" | "C" | "Z" => VerificationType::Integer,
+            "F" => VerificationType::Float,
+            "J" => VerificationType::Long,
+            "D" => VerificationType::Double,
+            "L" => VerificationType::Object(descriptor[1..descriptor.len() - 1].to_owned()),
+            _ => VerificationType::Object(descriptor.to_owned()),
+        }
+    }
+}
+
+/// a frame of a StackMapTable as it's written, the first field is the `offset_delta`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StackMapFrame {
+    Same(U2),
+    SameLocals1StackItem(U2, VerificationType),
+    /// the number of locals chopped
+    Chop(U2, usize),
+    Append(U2, Vec<VerificationType>),
+    Full(U2, Vec<VerificationType>, Vec<VerificationType>),
+}
+
+pub type StackMapTable = Vec<StackMapFrame>;
+
+/// the types of the locals and the operand stack at `offset`, a long or a double is one entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub offset: U2,
+    /// the index of the instruction at `offset` in the decoded code
+    pub pc: usize,
+    pub locals: Vec<VerificationType>,
+    pub stack: Vec<VerificationType>,
+}
+
+/// an attribute kept as its bytes until it's first asked for
+///
+/// The bytes are copied out of the class file, which is dropped once the class is parsed. The
+/// lazy attributes take a small share of it, borrowing them would keep the whole file alive for
+/// as long as the class.
+pub struct Lazy<T> {
+    // where the bytes start in the class file
+    offset: usize,
+    bytes: Vec<u8>,
+    decoded: RwLock<Option<Arc<T>>>,
+}
+
+impl<T> Lazy<T> {
+    fn new(offset: usize, bytes: &[u8]) -> Self {
+        Lazy {
+            offset: offset,
+            bytes: bytes.to_vec(),
+            decoded: RwLock::new(None),
+        }
+    }
+
+    /// the attribute decoded by the first call, `constants` is the constant pool of the class
+    pub(crate) fn get(&self, constants: Option<&ConstantPool>) -> Result<Arc<T>, ClassFormatError>
+    where
+        T: Traveler<T>,
+    {
+        if let Some(decoded) = self.decoded.read().unwrap().as_ref() {
+            return Ok(Arc::clone(decoded));
+        }
+        let seq = &mut Reader::at(&self.bytes, self.offset);
+        let decoded = T::read(seq, constants)?;
+        if !seq.is_end() {
+            return Err(ClassFormatError::new(
+                seq.offset(),
+                "Extra bytes at the end of attribute".to_owned(),
+            ));
+        }
+        let mut cached = self.decoded.write().unwrap();
+        Ok(Arc::clone(cached.get_or_insert_with(|| Arc::new(decoded))))
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
 pub enum Attribute {
-    ConstantValue(Arc<Vec<U1>>),
+    /// the index of the constant
+    ConstantValue(Lazy<U2>),
     Code(
         U2,
         U2,
@@ -19,9 +147,10 @@ pub enum Attribute {
         Arc<Vec<ExceptionHandler>>,
         Arc<Attributes>,
     ),
-    StackMapTable(Arc<Vec<U1>>),
-    Exceptions(Arc<Vec<U1>>),
-    BootstrapMethods(Arc<Vec<U1>>),
+    StackMapTable(Lazy<StackMapTable>),
+    /// the names of the exception classes, listed the way interfaces are
+    Exceptions(Lazy<Vec<String>>),
+    BootstrapMethods(Arc<Vec<BootstrapMethod>>),
     // above for JVM
     InnerClasses(Vec<U1>),
     EnclosingMethod(Vec<U1>),
@@ -36,6 +165,12 @@ pub enum Attribute {
     AnnotationDefault(Vec<U1>),
     MethodParameters(Vec<U1>),
     // above for Java SE
+    LineNumberTable(Lazy<LineNumberTable>),
+    LocalVariableTable(Lazy<LocalVariableTable>),
+    /// the descriptors are the generic signatures
+    LocalVariableTypeTable(Lazy<LocalVariableTable>),
+    SourceFile(String),
+    // above for tools
 }
 
 pub const CONSTANT_VALUE: &'static str = "ConstantValue";
@@ -57,74 +192,377 @@ pub const RUNTIME_VISIBLE_TYPE_ANNOTATIONS: &'static str = "RuntimeVisibleTypeAn
 pub const RUNTIME_INVISIBLE_TYPE_ANNOTATIONS: &'static str = "RuntimeInvisibleTypeAnnotations";
 pub const ANNOTATION_DEFAULT: &'static str = "AnnotationDefault";
 pub const METHOD_PARAMETERS: &'static str = "MethodParameters";
+pub const LINE_NUMBER_TABLE: &'static str = "LineNumberTable";
+pub const LOCAL_VARIABLE_TABLE: &'static str = "LocalVariableTable";
+pub const LOCAL_VARIABLE_TYPE_TABLE: &'static str = "LocalVariableTypeTable";
+pub const SOURCE_FILE: &'static str = "SourceFile";
 
 impl Traveler<Attributes> for Attributes {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> Attributes
-    where
-        I: Iterator<Item = u8>,
-    {
-        let attribute_count = U2::read(seq, None) as usize;
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<Attributes, ClassFormatError> {
+        let attribute_count = U2::read(seq, None)? as usize;
         let mut attributes = Vec::<Attribute>::with_capacity(attribute_count);
         for _x in 0..attribute_count {
-            attributes.push(Attribute::read(seq, constants));
+            if let Some(attribute) = read_attribute(seq, constants)? {
+                attributes.push(attribute);
+            }
         }
-        attributes
+        Ok(attributes)
     }
 }
 
-impl Traveler<Attribute> for Attribute {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> Attribute
-    where
-        I: Iterator<Item = u8>,
-    {
-        let name_idx = U2::read(seq, None);
-        let length = U4::read(seq, None) as usize;
-        if let Some(pool) = constants {
-            match pool.get_str(name_idx) {
-                CODE => {
-                    let max_stacks = U2::read(seq, None);
-                    let max_locals = U2::read(seq, None);
-                    let code_length = U4::read(seq, None);
-                    let mut code = Vec::<u8>::with_capacity(code_length as usize);
-                    for _x in 0..code_length {
-                        code.push(U1::read(seq, None));
-                    }
-                    let exception_handler_count = U2::read(seq, None);
-                    let mut exception_handlers =
-                        Vec::<ExceptionHandler>::with_capacity(exception_handler_count as usize);
-                    for _x in 0..exception_handler_count {
-                        let start_pc = U2::read(seq, None);
-                        let end_pc = U2::read(seq, None);
-                        let handler_pc = U2::read(seq, None);
-                        let catch_type_idx = U2::read(seq, Some(pool));
-                        let catch_type = match catch_type_idx {
-                            0 => None,
-                            _ => Some(pool.get_str(catch_type_idx).to_string()),
-                        };
-                        exception_handlers.push(ExceptionHandler {
-                            start_pc: start_pc,
-                            end_pc: end_pc,
-                            handler_pc: handler_pc,
-                            catch_type: catch_type,
-                        })
-                    }
-                    return Attribute::Code(
-                        max_stacks,
-                        max_locals,
-                        Arc::new(code),
-                        Arc::new(exception_handlers),
-                        Arc::new(Attributes::read(seq, Some(pool))),
-                    );
-                }
-                _ => {
-                    let mut content = Vec::<U1>::with_capacity(length);
-                    for _x in 0..length {
-                        content.push(U1::read(seq, None));
-                    }
-                    return Attribute::ConstantValue(Arc::new(content));
+// attributes unknown to the VM are skipped by their declared length
+fn read_attribute(
+    seq: &mut Reader,
+    constants: Option<&ConstantPool>,
+) -> Result<Option<Attribute>, ClassFormatError> {
+    let pool = constants.expect("need constant pool to resolve attributes");
+    let name = seq.utf8(pool)?;
+    let length = U4::read(seq, None)? as usize;
+    let start = seq.offset();
+    let attribute = match name {
+        CODE => {
+            let max_stacks = U2::read(seq, None)?;
+            let max_locals = U2::read(seq, None)?;
+            let at = seq.offset();
+            let code_length = U4::read(seq, None)? as usize;
+            if code_length == 0 || code_length >= 0x10000 {
+                return Err(ClassFormatError::new(
+                    at,
+                    format!("Illegal code length {}", code_length),
+                ));
+            }
+            let code = seq.bytes(code_length)?.to_vec();
+            let exception_handler_count = U2::read(seq, None)?;
+            let mut exception_handlers =
+                Vec::<ExceptionHandler>::with_capacity(exception_handler_count as usize);
+            for _x in 0..exception_handler_count {
+                let start_pc = U2::read(seq, None)?;
+                let end_pc = U2::read(seq, None)?;
+                let handler_pc = U2::read(seq, None)?;
+                let catch_type = seq.class(pool, true)?.map(|c| c.to_string());
+                exception_handlers.push(ExceptionHandler {
+                    start_pc: start_pc,
+                    end_pc: end_pc,
+                    handler_pc: handler_pc,
+                    catch_type: catch_type,
+                })
+            }
+            Attribute::Code(
+                max_stacks,
+                max_locals,
+                Arc::new(code),
+                Arc::new(exception_handlers),
+                Arc::new(Attributes::read(seq, Some(pool))?),
+            )
+        }
+        BOOTSTRAP_METHODS => {
+            let bootstrap_method_count = U2::read(seq, None)?;
+            let mut bootstrap_methods =
+                Vec::<BootstrapMethod>::with_capacity(bootstrap_method_count as usize);
+            for _x in 0..bootstrap_method_count {
+                let method_ref = U2::read(seq, None)?;
+                let argument_count = U2::read(seq, None)?;
+                let mut arguments = Vec::<U2>::with_capacity(argument_count as usize);
+                for _y in 0..argument_count {
+                    arguments.push(U2::read(seq, None)?);
                 }
+                bootstrap_methods.push(BootstrapMethod {
+                    method_ref: method_ref,
+                    arguments: arguments,
+                });
             }
+            Attribute::BootstrapMethods(Arc::new(bootstrap_methods))
+        }
+        CONSTANT_VALUE => Attribute::ConstantValue(Lazy::new(start, seq.bytes(length)?)),
+        STACK_MAP_TABLE => Attribute::StackMapTable(Lazy::new(start, seq.bytes(length)?)),
+        EXCEPTIONS => Attribute::Exceptions(Lazy::new(start, seq.bytes(length)?)),
+        LINE_NUMBER_TABLE => Attribute::LineNumberTable(Lazy::new(start, seq.bytes(length)?)),
+        LOCAL_VARIABLE_TABLE => Attribute::LocalVariableTable(Lazy::new(start, seq.bytes(length)?)),
+        LOCAL_VARIABLE_TYPE_TABLE => {
+            Attribute::LocalVariableTypeTable(Lazy::new(start, seq.bytes(length)?))
+        }
+        SOURCE_FILE => Attribute::SourceFile(seq.utf8(pool)?.to_string()),
+        _ => {
+            seq.bytes(length)?;
+            return Ok(None);
+        }
+    };
+    if seq.offset() - start != length {
+        return Err(ClassFormatError::new(
+            start,
+            format!(
+                "{} attribute of length {} takes {} bytes",
+                name,
+                length,
+                seq.offset() - start
+            ),
+        ));
+    }
+    Ok(Some(attribute))
+}
+
+impl Traveler<LineNumberTable> for LineNumberTable {
+    fn read(
+        seq: &mut Reader,
+        _constants: Option<&ConstantPool>,
+    ) -> Result<LineNumberTable, ClassFormatError> {
+        let size = U2::read(seq, None)?;
+        let mut lines = Vec::<LineNumber>::with_capacity(size as usize);
+        for _x in 0..size {
+            lines.push(LineNumber {
+                start_pc: U2::read(seq, None)?,
+                line_number: U2::read(seq, None)?,
+            });
+        }
+        Ok(lines)
+    }
+}
+
+impl Traveler<LocalVariableTable> for LocalVariableTable {
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<LocalVariableTable, ClassFormatError> {
+        let pool = constants.expect("need constant pool to resolve local variables");
+        let size = U2::read(seq, None)?;
+        let mut variables = Vec::<LocalVariable>::with_capacity(size as usize);
+        for _x in 0..size {
+            variables.push(LocalVariable {
+                start_pc: U2::read(seq, None)?,
+                length: U2::read(seq, None)?,
+                name: seq.utf8(pool)?.to_string(),
+                descriptor: seq.utf8(pool)?.to_string(),
+                index: U2::read(seq, None)?,
+            });
         }
-        panic!("need constant pool to resolve attributes");
+        Ok(variables)
+    }
+}
+
+impl Traveler<VerificationType> for VerificationType {
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<VerificationType, ClassFormatError> {
+        let at = seq.offset();
+        Ok(match U1::read(seq, None)? {
+            0 => VerificationType::Top,
+            1 => VerificationType::Integer,
+            2 => VerificationType::Float,
+            3 => VerificationType::Double,
+            4 => VerificationType::Long,
+            5 => VerificationType::Null,
+            6 => VerificationType::UninitializedThis,
+            7 => {
+                let pool = constants.expect("need constant pool to resolve verification types");
+                VerificationType::Object(seq.class(pool, false)?.unwrap().to_string())
+            }
+            8 => VerificationType::Uninitialized(U2::read(seq, None)?),
+            tag => {
+                return Err(ClassFormatError::new(
+                    at,
+                    format!("Unknown verification type {}", tag),
+                ))
+            }
+        })
+    }
+}
+
+fn read_verification_types(
+    seq: &mut Reader,
+    constants: Option<&ConstantPool>,
+    size: usize,
+) -> Result<Vec<VerificationType>, ClassFormatError> {
+    let mut types = Vec::<VerificationType>::with_capacity(size);
+    for _x in 0..size {
+        types.push(VerificationType::read(seq, constants)?);
+    }
+    Ok(types)
+}
+
+impl Traveler<StackMapTable> for StackMapTable {
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<StackMapTable, ClassFormatError> {
+        let size = U2::read(seq, None)?;
+        let mut frames = Vec::<StackMapFrame>::with_capacity(size as usize);
+        for _x in 0..size {
+            let at = seq.offset();
+            let frame = match U1::read(seq, None)? {
+                t @ 0..=63 => StackMapFrame::Same(t as U2),
+                t @ 64..=127 => StackMapFrame::SameLocals1StackItem(
+                    t as U2 - 64,
+                    VerificationType::read(seq, constants)?,
+                ),
+                247 => StackMapFrame::SameLocals1StackItem(
+                    U2::read(seq, None)?,
+                    VerificationType::read(seq, constants)?,
+                ),
+                t @ 248..=250 => StackMapFrame::Chop(U2::read(seq, None)?, 251 - t as usize),
+                251 => StackMapFrame::Same(U2::read(seq, None)?),
+                t @ 252..=254 => {
+                    let offset_delta = U2::read(seq, None)?;
+                    let locals = read_verification_types(seq, constants, t as usize - 251)?;
+                    StackMapFrame::Append(offset_delta, locals)
+                }
+                255 => {
+                    let offset_delta = U2::read(seq, None)?;
+                    let locals = U2::read(seq, None)? as usize;
+                    let locals = read_verification_types(seq, constants, locals)?;
+                    let stack = U2::read(seq, None)? as usize;
+                    let stack = read_verification_types(seq, constants, stack)?;
+                    StackMapFrame::Full(offset_delta, locals, stack)
+                }
+                t => {
+                    return Err(ClassFormatError::new(
+                        at,
+                        format!("Unknown stack map frame type {}", t),
+                    ))
+                }
+            };
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+}
+
+/// JVMS 4.7.4, the frames at their offsets in the code with the locals each one implies,
+/// `initial` is the implicit frame of the method and `pcs` the offsets of the instructions
+pub fn expand_frames(
+    frames: &[StackMapFrame],
+    initial: Vec<VerificationType>,
+    pcs: &[U2],
+) -> Result<Vec<Frame>, String> {
+    let mut expanded = Vec::<Frame>::with_capacity(frames.len());
+    let mut locals = initial;
+    let mut offset: Option<usize> = None;
+    for frame in frames {
+        let (offset_delta, stack) = match frame {
+            StackMapFrame::Same(delta) => (delta, vec![]),
+            StackMapFrame::SameLocals1StackItem(delta, item) => (delta, vec![item.clone()]),
+            StackMapFrame::Chop(delta, k) => {
+                if *k > locals.len() {
+                    return Err(format!("Chop of {} from {} locals", k, locals.len()));
+                }
+                locals.truncate(locals.len() - k);
+                (delta, vec![])
+            }
+            StackMapFrame::Append(delta, appended) => {
+                locals.extend(appended.iter().cloned());
+                (delta, vec![])
+            }
+            StackMapFrame::Full(delta, full, stack) => {
+                locals = full.clone();
+                (delta, stack.clone())
+            }
+        };
+        // each frame but the first is at least one byte after the frame before
+        let at = offset.map_or(0, |offset| offset + 1) + *offset_delta as usize;
+        let pc = match pcs.binary_search_by(|pc| (*pc as usize).cmp(&at)) {
+            Ok(pc) => pc,
+            Err(_) => return Err(format!("Frame offset {} is not an instruction", at)),
+        };
+        offset = Some(at);
+        expanded.push(Frame {
+            offset: at as U2,
+            pc: pc,
+            locals: locals.clone(),
+            stack: stack,
+        });
+    }
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bytecode::constant_pool::ConstantItem;
+
+    #[test]
+    pub fn test_lazy() {
+        let pool = ConstantPool::new(vec![
+            ConstantItem::NIL,
+            ConstantItem::UTF8(EXCEPTIONS.to_owned()),
+            ConstantItem::UTF8("java/io/IOException".to_owned()),
+            ConstantItem::Class(2),
+        ]);
+        let read = |bytes: &[u8]| {
+            let mut attributes =
+                Attributes::read(&mut Reader::at(bytes, 100), Some(&pool)).unwrap();
+            match attributes.pop() {
+                Some(Attribute::Exceptions(exceptions)) => exceptions,
+                _ => panic!("Exceptions attribute not read"),
+            }
+        };
+        let exceptions = read(&[0, 1, 0, 1, 0, 0, 0, 4, 0, 1, 0, 3]);
+        assert_eq!(&[0, 1, 0, 3], exceptions.bytes());
+        let decoded = exceptions.get(Some(&pool)).unwrap();
+        assert_eq!(vec!["java/io/IOException".to_owned()], *decoded);
+        assert!(Arc::ptr_eq(&decoded, &exceptions.get(Some(&pool)).unwrap()));
+        // a malformed attribute is only found when it's decoded, at its offset in the class file
+        let exceptions = read(&[0, 1, 0, 1, 0, 0, 0, 4, 0, 1, 0, 1]);
+        assert_eq!(
+            ClassFormatError::new(110, "Invalid class constant #1".to_owned()),
+            exceptions.get(Some(&pool)).err().unwrap()
+        );
+    }
+
+    #[test]
+    pub fn test_stack_map_frames() {
+        let pool = ConstantPool::new(vec![
+            ConstantItem::NIL,
+            ConstantItem::UTF8("[I".to_owned()),
+            ConstantItem::Class(1),
+        ]);
+        let bytes = [
+            0, 6, // the frames
+            251, 0, 10, // same_frame_extended
+            247, 0, 0, 5, // same_locals_1_stack_item_extended
+            254, 0, 0, 0, 2, 7, 0, 2, // append
+            248, 0, 1, // chop
+            255, 0, 2, 0, 1, 3, 0, 2, 8, 0, 4, 0, // full_frame
+            0, // same
+        ];
+        let frames = StackMapTable::read(&mut Reader::new(&bytes), Some(&pool)).unwrap();
+        // a 3-byte instruction at 2, the others take a byte
+        let pcs = [0, 1, 2].iter().cloned().chain(5..20).collect::<Vec<U2>>();
+        let expanded = expand_frames(&frames, vec![VerificationType::Integer], &pcs).unwrap();
+        let offsets = expanded
+            .iter()
+            .map(|f| (f.offset, f.pc))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![(10, 8), (11, 9), (12, 10), (14, 12), (17, 15), (18, 16)],
+            offsets
+        );
+        assert_eq!(vec![VerificationType::Null], expanded[1].stack);
+        assert_eq!(
+            vec![
+                VerificationType::Integer,
+                VerificationType::Top,
+                VerificationType::Float,
+                VerificationType::Object("[I".to_owned()),
+            ],
+            expanded[2].locals
+        );
+        assert_eq!(vec![VerificationType::Integer], expanded[3].locals);
+        assert_eq!(
+            vec![VerificationType::Uninitialized(4), VerificationType::Top],
+            expanded[4].stack
+        );
+        assert_eq!(vec![VerificationType::Double], expanded[5].locals);
+        assert!(expanded[5].stack.is_empty());
+        assert!(expand_frames(&frames, vec![VerificationType::Integer], &pcs[..16]).is_err());
+        let inside = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 13, 14, 15, 16, 17, 18, 19];
+        assert!(expand_frames(&frames, vec![VerificationType::Integer], &inside).is_err());
+        let tag = StackMapTable::read(&mut Reader::at(&[0, 1, 128], 10), Some(&pool));
+        assert_eq!(12, tag.err().unwrap().offset);
+        let chop = [StackMapFrame::Chop(0, 2)];
+        assert!(expand_frames(&chop, vec![VerificationType::Integer], &pcs).is_err());
     }
 }
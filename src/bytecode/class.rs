@@ -1,6 +1,18 @@
 use super::{atom::*, attribute::*, constant_pool::*, field::*, interface::*, method::*, *};
+use crate::mem::pool::RuntimeConstantPool;
 use std::sync::Arc;
 
+const ACC_SUPER: U2 = 0x0020; // Treat superclass methods specially when invoked by the invokespecial instruction.
+
+const ACC_INTERFACE: U2 = 0x0200; // Is an interface, not a class.
+
+const MAGIC: U4 = 0xCAFEBABE;
+
+// JDK 1.1 up to JDK 17
+const MIN_MAJOR_VERSION: U2 = 45;
+
+const MAX_MAJOR_VERSION: U2 = 61;
+
 pub struct Class {
     pub constant_pool: ConstantPool,
     pub access_flag: U2,
@@ -10,25 +22,47 @@ pub struct Class {
     pub fields: Fields,
     pub methods: Methods,
     pub attributes: Attributes,
+    /// resolved by the interpreter as instructions use the constant pool
+    pub runtime_pool: RuntimeConstantPool,
 }
 
 impl Class {
-    pub fn from_vec(bytes: Vec<u8>) -> Class {
-        let seq = &mut bytes.into_iter();
-        U4::read(seq, None);
-        U2::read(seq, None);
-        U2::read(seq, None);
-        let constants = ConstantPool::read(seq, None);
-        let access_flag = U2::read(seq, None);
-        let this_class = U2::read(seq, None);
-        let super_class = U2::read(seq, None);
-        let this_class_name = constants.get_str(this_class).to_string();
-        let super_class_name = constants.get_str(super_class).to_string();
-        let interfaces = Interfaces::read(seq, Some(&constants));
-        let fields = Fields::read(seq, Some(&constants));
-        let methods = Methods::read(seq, Some(&constants));
-        let attributes = Attributes::read(seq, Some(&constants));
-        Class {
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Class, ClassFormatError> {
+        Self::from_slice(&bytes)
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<Class, ClassFormatError> {
+        let seq = &mut Reader::new(bytes);
+        let magic = U4::read(seq, None)?;
+        if magic != MAGIC {
+            return Err(ClassFormatError::new(
+                0,
+                format!("Incompatible magic value {:#x}", magic),
+            ));
+        }
+        let _minor_version = U2::read(seq, None)?;
+        let major_version = U2::read(seq, None)?;
+        if major_version < MIN_MAJOR_VERSION || major_version > MAX_MAJOR_VERSION {
+            return Err(ClassFormatError::new(
+                6,
+                format!("Unsupported major version {}", major_version),
+            ));
+        }
+        let constants = ConstantPool::read(seq, None)?;
+        let access_flag = U2::read(seq, None)?;
+        let this_class_name = seq.class(&constants, false)?.unwrap().to_string();
+        let super_class_name = seq.class(&constants, true)?.unwrap_or("").to_string();
+        let interfaces = Interfaces::read(seq, Some(&constants))?;
+        let fields = Fields::read(seq, Some(&constants))?;
+        let methods = Methods::read(seq, Some(&constants))?;
+        let attributes = Attributes::read(seq, Some(&constants))?;
+        if !seq.is_end() {
+            return Err(ClassFormatError::new(
+                seq.offset(),
+                "Extra bytes at the end of class file".to_owned(),
+            ));
+        }
+        Ok(Class {
             constant_pool: constants,
             access_flag: access_flag,
             this_class_name: this_class_name,
@@ -37,7 +71,8 @@ impl Class {
             fields: fields,
             methods: methods,
             attributes: attributes,
-        }
+            runtime_pool: RuntimeConstantPool::new(),
+        })
     }
 
     pub fn get_method(&self, method_name: &str, method_descriptor: &str) -> Option<Arc<Method>> {
@@ -58,6 +93,32 @@ impl Class {
         None
     }
 
+    pub fn get_bootstrap_method(&self, idx: U2) -> Option<&BootstrapMethod> {
+        for attr in &self.attributes {
+            if let Attribute::BootstrapMethods(methods) = attr {
+                return methods.get(idx as usize);
+            }
+        }
+        None
+    }
+
+    pub fn source_file(&self) -> Option<&str> {
+        for attr in &self.attributes {
+            if let Attribute::SourceFile(name) = attr {
+                return Some(name);
+            }
+        }
+        None
+    }
+
+    pub fn is_super(&self) -> bool {
+        self.access_flag & ACC_SUPER == ACC_SUPER
+    }
+
+    pub fn is_interface(&self) -> bool {
+        self.access_flag & ACC_INTERFACE == ACC_INTERFACE
+    }
+
     pub fn get_name(&self) -> &str {
         self.this_class_name.as_ref()
     }
@@ -70,3 +131,270 @@ impl Class {
         self.interfaces.as_ref()
     }
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    fn utf8(s: &str) -> Vec<u8> {
+        let mut entry = vec![1, 0, s.len() as u8];
+        entry.extend_from_slice(s.as_bytes());
+        entry
+    }
+
+    // `public class T` with `public static void m()` carrying an attribute unknown to the VM
+    fn class_file(pool: &[Vec<u8>], code_length: u8, unknown_length: u8) -> Vec<u8> {
+        let mut bytes = vec![0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x34];
+        bytes.extend_from_slice(&[0, pool.len() as u8 + 1]);
+        pool.iter().for_each(|entry| bytes.extend_from_slice(entry));
+        bytes.extend_from_slice(&[0x00, 0x21, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1]);
+        bytes.extend_from_slice(&[0x00, 0x09, 0, 3, 0, 4, 0, 2]);
+        bytes.extend_from_slice(&[0, 5, 0, 0, 0, code_length, 0, 0, 0, 0, 0, 0, 0, 1, 0xb1]);
+        bytes.extend_from_slice(&[0, 0, 0, 0]);
+        bytes.extend_from_slice(&[0, 6, 0, 0, 0, unknown_length, 1, 2, 3]);
+        bytes.extend_from_slice(&[0, 0]);
+        bytes
+    }
+
+    fn pool() -> Vec<Vec<u8>> {
+        vec![
+            utf8("T"),
+            vec![7, 0, 1],
+            utf8("m"),
+            utf8("()V"),
+            utf8("Code"),
+            utf8("Unknown"),
+        ]
+    }
+
+    fn error(bytes: Vec<u8>) -> (usize, String) {
+        match Class::from_vec(bytes) {
+            Ok(_) => panic!("malformed class parsed"),
+            Err(error) => (error.offset, error.message),
+        }
+    }
+
+    #[test]
+    pub fn test_parse() {
+        let class = Class::from_vec(class_file(&pool(), 13, 3)).unwrap();
+        assert_eq!("T", class.get_name());
+        assert_eq!("", class.get_super_class());
+        let method = class.get_method("m", "()V").unwrap();
+        // the unknown attribute is skipped
+        assert_eq!(1, method.attributes.len());
+        let (_, _, code, _, _) = method.get_code().unwrap();
+        assert_eq!(vec![0xb1], *code);
+    }
+
+    #[test]
+    pub fn test_string_constants() {
+        let mut pool = pool();
+        // #8 is the string of #7 with an unpaired high surrogate, #10 the string of #9
+        pool.push(vec![1, 0, 4, 0xed, 0xa0, 0xbd, 0x61]);
+        pool.push(vec![8, 0, 7]);
+        pool.push(vec![1, 0, 3, 0x68, 0xc3, 0xa9]);
+        pool.push(vec![8, 0, 9]);
+        let class = Class::from_vec(class_file(&pool, 13, 3)).unwrap();
+        let constants = &class.constant_pool;
+        assert_eq!("\u{fffd}a", constants.get_str(8));
+        assert_eq!(vec![0xd83d, 0x61], constants.get_utf16(8));
+        assert_eq!(vec![0x68, 0xe9], constants.get_utf16(10));
+    }
+
+    // javac --release 8 -g
+    //
+    // public class Debug {
+    //     static int sum(List<Integer> xs) {
+    //         int total = 0;
+    //         for (int x : xs) {
+    //             total += x;
+    //         }
+    //         return total;
+    //     }
+    // }
+    const DEBUG: &'static str = "yv66vgAAADQAMgoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWCwAIAAkHAAoMAAsADAEADmphdmEvdXRpbC9MaXN0AQAIaXRlcmF0b3IBABYoKUxqYXZhL3V0aWwvSXRlcmF0b3I7CwAOAA8HABAMABEAEgEAEmphdmEvdXRpbC9JdGVyYXRvcgEAB2hhc05leHQBAAMoKVoLAA4AFAwAFQAWAQAEbmV4dAEAFCgpTGphdmEvbGFuZy9PYmplY3Q7BwAYAQARamF2YS9sYW5nL0ludGVnZXIKABcAGgwAGwAcAQAIaW50VmFsdWUBAAMoKUkHAB4BAAVEZWJ1ZwEABENvZGUBAA9MaW5lTnVtYmVyVGFibGUBABJMb2NhbFZhcmlhYmxlVGFibGUBAAR0aGlzAQAHTERlYnVnOwEAA3N1bQEAEyhMamF2YS91dGlsL0xpc3Q7KUkBAAF4AQABSQEAAnhzAQAQTGphdmEvdXRpbC9MaXN0OwEABXRvdGFsAQAWTG9jYWxWYXJpYWJsZVR5cGVUYWJsZQEAJUxqYXZhL3V0aWwvTGlzdDxMamF2YS9sYW5nL0ludGVnZXI7PjsBAA1TdGFja01hcFRhYmxlAQAJU2lnbmF0dXJlAQAoKExqYXZhL3V0aWwvTGlzdDxMamF2YS9sYW5nL0ludGVnZXI7PjspSQEAClNvdXJjZUZpbGUBAApEZWJ1Zy5qYXZhACEAHQACAAAAAAACAAEABQAGAAEAHwAAAC8AAQABAAAABSq3AAGxAAAAAgAgAAAABgABAAAAAwAhAAAADAABAAAABQAiACMAAAAIACQAJQACAB8AAACaAAIABAAAACgDPCq5AAcBAE0suQANAQCZABcsuQATAQDAABe2ABk+Gx1gPKf/5husAAAABAAgAAAAFgAFAAAABQACAAYAHwAHACMACAAmAAkAIQAAACAAAwAfAAQAJgAnAAMAAAAoACgAKQAAAAIAJgAqACcAAQArAAAADAABAAAAKAAoACwAAAAtAAAADAAC/QAJAQcADvoAHAAuAAAAAgAvAAEAMAAAAAIAMQ==";
+
+    #[test]
+    pub fn test_debug_attributes() {
+        let class = Class::from_vec(base64::decode(DEBUG).unwrap()).unwrap();
+        assert_eq!(Some("Debug.java"), class.source_file());
+        let method = class.get_method("sum", "(Ljava/util/List;)I").unwrap();
+        let pool = &class.constant_pool;
+        // the bytecode offsets of the 20 instructions
+        let offsets = [
+            0, 1, 2, 3, 8, 9, 10, 15, 18, 19, 24, 27, 30, 31, 32, 33, 34, 35, 38, 39,
+        ];
+        let lines = offsets.iter().map(|pc| method.line_for_pc(*pc).unwrap());
+        let lines = lines.collect::<Vec<_>>();
+        assert_eq!(vec![5, 5, 6, 6, 6], lines[..5].to_vec());
+        assert_eq!((6, 7, 8, 9), (lines[12], lines[13], lines[17], lines[19]));
+        assert_eq!(None, method.line_for_pc(40));
+        let local = |slot, pc| method.local_variable(pool, slot, pc).map(|v| v.name);
+        assert_eq!(Some("xs".to_owned()), local(0, 0));
+        assert_eq!(None, local(1, 1));
+        assert_eq!(Some("total".to_owned()), local(1, 2));
+        assert_eq!(None, local(3, 30));
+        assert_eq!(Some("x".to_owned()), local(3, 31));
+        assert_eq!(None, local(3, 35));
+        assert_eq!(
+            Some(LocalVariable {
+                start_pc: 2,
+                length: 38,
+                name: "total".to_owned(),
+                descriptor: "I".to_owned(),
+                index: 1,
+            }),
+            method.local_variable(pool, 1, 39)
+        );
+        assert_eq!(
+            Some("Ljava/util/List<Ljava/lang/Integer;>;".to_owned()),
+            method.local_variable_signature(pool, 0, 10)
+        );
+        assert_eq!(None, method.local_variable_signature(pool, 1, 10));
+        let init = class.get_method("<init>", "()V").unwrap();
+        assert_eq!(Some(3), init.line_for_pc(4));
+    }
+
+    // javac --release 8 -g:none
+    //
+    // public class Frames {
+    //     Frames(Object o) {
+    //     }
+    //
+    //     Frames(boolean b) {
+    //         this(b ? "yes" : null);
+    //     }
+    //
+    //     static long mix(int n, double d) {
+    //         long sum = 0;
+    //         for (int i = 0; i < n; i++) {
+    //             sum += i;
+    //         }
+    //         String s = n > 0 ? "a" : "b";
+    //         if (d > 0) {
+    //             int[] a = new int[n];
+    //             sum += a.length;
+    //         }
+    //         return sum + s.length();
+    //     }
+    //
+    //     static Object make(boolean b) {
+    //         return new Frames(b ? "x" : null);
+    //     }
+    // }
+    const FRAMES: &'static str = "yv66vgAAADQAIQoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWCAAIAQADeWVzCgAKAAsHAAwMAAUADQEABkZyYW1lcwEAFShMamF2YS9sYW5nL09iamVjdDspVggADwEAAWEIABEBAAFiCgATABQHABUMABYAFwEAEGphdmEvbGFuZy9TdHJpbmcBAAZsZW5ndGgBAAMoKUkIABkBAAF4AQAEQ29kZQEABChaKVYBAA1TdGFja01hcFRhYmxlAQADbWl4AQAFKElEKUoBAARtYWtlAQAVKFopTGphdmEvbGFuZy9PYmplY3Q7ACEACgACAAAAAAAEAAAABQANAAEAGgAAABEAAQACAAAABSq3AAGxAAAAAAAAAAUAGwABABoAAAAyAAIAAgAAAA8qG5kACBIHpwAEAbcACbEAAAABABwAAAARAAJKBv8AAAACBgEAAgYHAAIACAAdAB4AAQAaAAAAZgAEAAcAAAA/CUIDNgUVBRqiAA8hFQWFYUKEBQGn//EangAIEg6nAAUSEDoFJw6XngAPGrwKOgYhGQa+hWFCIRkFtgAShWGtAAAAAQAcAAAAFQAF/QAFBAH6ABEIQQcAE/wAEwcAEwAIAB8AIAABABoAAABFAAMAAQAAABK7AApZGpkACBIYpwAEAbcACbAAAAABABwAAAAhAAL/AA0AAQEAAggAAAgAAP8AAAABAQADCAAACAAABwACAAA=";
+
+    #[test]
+    pub fn test_stack_map() {
+        use VerificationType::*;
+        let class = Class::from_vec(base64::decode(FRAMES).unwrap()).unwrap();
+        let frames = |name: &str, descriptor: &str| {
+            let method = class.get_method(name, descriptor).unwrap();
+            method
+                .stack_map(&class)
+                .unwrap()
+                .into_iter()
+                .map(|frame| (frame.offset, frame.locals, frame.stack))
+                .collect::<Vec<_>>()
+        };
+        assert!(frames("<init>", "(Ljava/lang/Object;)V").is_empty());
+        let object = || Object("java/lang/Object".to_owned());
+        assert_eq!(
+            vec![
+                (
+                    10,
+                    vec![UninitializedThis, Integer],
+                    vec![UninitializedThis]
+                ),
+                (
+                    11,
+                    vec![UninitializedThis, Integer],
+                    vec![UninitializedThis, object()]
+                ),
+            ],
+            frames("<init>", "(Z)V")
+        );
+        let string = || Object("java/lang/String".to_owned());
+        assert_eq!(
+            vec![
+                (5, vec![Integer, Double, Long, Integer], vec![]),
+                (23, vec![Integer, Double, Long], vec![]),
+                (32, vec![Integer, Double, Long], vec![]),
+                (34, vec![Integer, Double, Long], vec![string()]),
+                (54, vec![Integer, Double, Long, string()], vec![]),
+            ],
+            frames("mix", "(ID)J")
+        );
+        assert_eq!(
+            vec![
+                (13, vec![Integer], vec![Uninitialized(0), Uninitialized(0)]),
+                (
+                    14,
+                    vec![Integer],
+                    vec![Uninitialized(0), Uninitialized(0), object()]
+                ),
+            ],
+            frames("make", "(Z)Ljava/lang/Object;")
+        );
+        // the frames are at instructions of the decoded code
+        let mix = class.get_method("mix", "(ID)J").unwrap();
+        let code = mix.get_decoded(&class.constant_pool).unwrap().unwrap();
+        let stack_map = mix.stack_map(&class).unwrap();
+        let pcs = stack_map.iter().map(|frame| frame.pc).collect::<Vec<_>>();
+        assert_eq!(vec![4, 14, 18, 19, 33], pcs);
+        for frame in stack_map {
+            assert_eq!(frame.offset, code.pc_of(frame.pc));
+        }
+    }
+
+    #[test]
+    pub fn test_class_format_error() {
+        let bytes = class_file(&pool(), 13, 3);
+        let len = bytes.len();
+        let (offset, message) = error(bytes[..len - 1].to_vec());
+        assert_eq!(
+            (len - 1, "Truncated class file"),
+            (offset, message.as_ref())
+        );
+        let mut magic = bytes.clone();
+        magic[3] = 0xbf;
+        assert_eq!(0, error(magic).0);
+        let mut version = bytes.clone();
+        version[7] = 62;
+        assert_eq!(
+            (6, "Unsupported major version 62".to_owned()),
+            error(version)
+        );
+        let mut extra = bytes.clone();
+        extra.push(0);
+        assert_eq!(len, error(extra).0);
+        // the class entry #2 starts at offset 14
+        let mut tag = pool();
+        tag[1][0] = 2;
+        assert_eq!(
+            (14, "Unknown constant pool tag 2".to_owned()),
+            error(class_file(&tag, 13, 3))
+        );
+        // the class entry names an integer, it starts at offset 15 after the integer
+        let mut reference = pool();
+        reference[0] = vec![3, 0, 0, 0, 0];
+        assert_eq!(
+            (15, "Invalid constant pool entry #2".to_owned()),
+            error(class_file(&reference, 13, 3))
+        );
+        let mut name = pool();
+        name[2] = vec![3, 0, 0, 0, 0];
+        let (_, message) = error(class_file(&name, 13, 3));
+        assert_eq!("Invalid UTF8 constant #3", message);
+        let mut descriptor = pool();
+        descriptor[3] = utf8("(V)V");
+        let (_, message) = error(class_file(&descriptor, 13, 3));
+        assert_eq!("Illegal descriptor (V)V", message);
+        let (_, message) = error(class_file(&pool(), 14, 3));
+        assert_eq!("Code attribute of length 14 takes 13 bytes", message);
+        // an unknown attribute takes its declared length, the attribute count of the class here
+        let (offset, message) = error(class_file(&pool(), 13, 5));
+        assert_eq!((len, "Truncated class file"), (offset, message.as_ref()));
+    }
+}
@@ -0,0 +1,754 @@
+use super::{
+    atom::*,
+    attribute::ExceptionHandler,
+    constant_pool::{ConstantItem, ConstantPool},
+};
+use crate::mem::{
+    klass::{Klass, MethodRef},
+    Slot, WideSlot, NULL,
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+    RwLock,
+};
+
+// the code of a method is decoded once before it runs, operands are read from the bytes and the
+// constants they name are looked up ahead, so the interpreter addresses instructions by index
+// and a branch target is the index of the instruction it jumps to
+
+pub struct Code {
+    pub insns: Vec<Insn>,
+    /// the bytecode offset of each instruction
+    pub pcs: Vec<U2>,
+    /// the exception table with the pcs mapped to instruction indices
+    pub handlers: Vec<ExceptionHandler>,
+}
+
+#[derive(Debug)]
+pub enum Insn {
+    Nop,
+    /// aconst_null, iconst, fconst, bipush, sipush and ldc of an int or a float
+    Push(Slot),
+    /// lconst, dconst and ldc2_w
+    PushWide(WideSlot),
+    /// ldc and ldc_w of a string, a class, a method type or a method handle
+    Ldc(U2),
+    /// the local variable and the slots it takes
+    Load(usize, usize),
+    Store(usize, usize),
+    /// the array loads and stores by opcode
+    ArrayLoad(U1),
+    ArrayStore(U1),
+    Pop,
+    Pop2,
+    /// copies the top `n` slots and inserts them `depth` slots down
+    Dup(usize, usize),
+    Swap,
+    /// the arithmetic, shift and bitwise instructions by opcode
+    Math(U1),
+    Iinc(usize, i32),
+    /// the conversions by opcode
+    Convert(U1),
+    Lcmp,
+    /// fcmpl and fcmpg, with the result of unordered operands
+    Fcmp(i32),
+    Dcmp(i32),
+    /// compares an int with zero
+    If(Cond, usize),
+    /// compares two ints
+    IfCmp(Cond, usize),
+    /// compares two references, either `Eq` or `Ne`
+    IfACmp(Cond, usize),
+    /// compares a reference with null, either `Eq` or `Ne`
+    IfNull(Cond, usize),
+    Goto(usize),
+    Jsr(usize),
+    Ret(usize),
+    TableSwitch {
+        low: i32,
+        default: usize,
+        targets: Vec<usize>,
+    },
+    /// the pairs are sorted by key
+    LookupSwitch {
+        default: usize,
+        pairs: Vec<(i32, usize)>,
+    },
+    Return,
+    GetStatic(U2),
+    PutStatic(U2),
+    /// getfield of a field taking a slot
+    GetField(U2, Offset),
+    /// getfield of a long or a double
+    GetFieldWide(U2, Offset),
+    PutField(U2, Offset),
+    PutFieldWide(U2, Offset),
+    InvokeVirtual(U2, InlineCache),
+    InvokeSpecial(U2),
+    InvokeStatic(U2),
+    InvokeInterface(U2, InlineCache),
+    InvokeDynamic(U2),
+    InvokeAsync(U2),
+    New(U2),
+    /// newarray by the name of the array class
+    NewArray(&'static str),
+    ANewArray(U2),
+    ArrayLength,
+    AThrow,
+    CheckCast(U2),
+    InstanceOf(U2),
+    MonitorEnter,
+    MonitorExit,
+    MultiANewArray(U2, usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Gt,
+    Le,
+}
+
+impl Cond {
+    /// whether `v` compared with zero holds
+    pub fn holds(self, v: i32) -> bool {
+        match self {
+            Cond::Eq => v == 0,
+            Cond::Ne => v != 0,
+            Cond::Lt => v < 0,
+            Cond::Ge => v >= 0,
+            Cond::Gt => v > 0,
+            Cond::Le => v <= 0,
+        }
+    }
+
+    fn of(opcode: U1) -> Cond {
+        match opcode {
+            0 => Cond::Eq,
+            1 => Cond::Ne,
+            2 => Cond::Lt,
+            3 => Cond::Ge,
+            4 => Cond::Gt,
+            _ => Cond::Le,
+        }
+    }
+}
+
+/// the field offset of a getfield or putfield, set by the first execution of the instruction
+#[derive(Debug, Default)]
+pub struct Offset(AtomicUsize);
+
+impl Offset {
+    pub fn get(&self) -> Option<usize> {
+        match self.0.load(Ordering::Relaxed) {
+            0 => None,
+            v => Some(v - 1),
+        }
+    }
+
+    pub fn set(&self, offset: usize) {
+        self.0.store(offset + 1, Ordering::Relaxed);
+    }
+}
+
+// the receiver classes a call site caches before it turns megamorphic
+const POLYMORPHIC_SIZE: usize = 4;
+
+/// the methods selected for the receiver classes of an invokevirtual or invokeinterface, a site
+/// caches its first receiver class, then up to `POLYMORPHIC_SIZE` of them, then none at all
+#[derive(Debug, Default)]
+pub struct InlineCache {
+    // the first receiver class with its method, never replaced so a hit of a monomorphic site
+    // reads it without the lock
+    first: AtomicPtr<(*const Klass, MethodRef)>,
+    cached: RwLock<Cached>,
+    // a megamorphic site caches nothing more, so it skips the lock
+    megamorphic: AtomicBool,
+    #[cfg(debug_assertions)]
+    hits: AtomicUsize,
+    #[cfg(debug_assertions)]
+    misses: AtomicUsize,
+}
+
+#[derive(Debug)]
+enum Cached {
+    Empty,
+    Monomorphic,
+    Polymorphic(Vec<(*const Klass, MethodRef)>),
+    Megamorphic,
+}
+
+impl Default for Cached {
+    fn default() -> Self {
+        Cached::Empty
+    }
+}
+
+impl InlineCache {
+    /// the method selected for `receiver` before, `None` if it is to be looked up
+    pub fn get(&self, receiver: *const Klass) -> Option<MethodRef> {
+        let found = self.lookup(receiver);
+        #[cfg(debug_assertions)]
+        {
+            let counter = if found.is_some() {
+                &self.hits
+            } else {
+                &self.misses
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+        found
+    }
+
+    fn lookup(&self, receiver: *const Klass) -> Option<MethodRef> {
+        if self.megamorphic.load(Ordering::Relaxed) {
+            return None;
+        }
+        let first = self.first.load(Ordering::Acquire);
+        if !first.is_null() {
+            let (klass, method) = unsafe { *first };
+            if klass == receiver {
+                return Some(method);
+            }
+        }
+        match &*self.cached.read().unwrap() {
+            Cached::Polymorphic(entries) => entries
+                .iter()
+                .find(|(klass, _)| *klass == receiver)
+                .map(|(_, method)| *method),
+            _ => None,
+        }
+    }
+
+    /// caches the method looked up for `receiver`
+    pub fn put(&self, receiver: *const Klass, method: MethodRef) {
+        if self.megamorphic.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut cached = self.cached.write().unwrap();
+        let next = match &mut *cached {
+            Cached::Empty => {
+                let first = Box::into_raw(Box::new((receiver, method)));
+                self.first.store(first, Ordering::Release);
+                Cached::Monomorphic
+            }
+            Cached::Monomorphic => {
+                let (klass, cached) = unsafe { *self.first.load(Ordering::Acquire) };
+                // another thread got here first
+                if klass == receiver {
+                    return;
+                }
+                Cached::Polymorphic(vec![(klass, cached), (receiver, method)])
+            }
+            Cached::Polymorphic(entries) if entries.iter().any(|(klass, _)| *klass == receiver) => {
+                return
+            }
+            Cached::Polymorphic(entries) if entries.len() < POLYMORPHIC_SIZE => {
+                entries.push((receiver, method));
+                return;
+            }
+            Cached::Megamorphic => return,
+            _ => {
+                self.megamorphic.store(true, Ordering::Relaxed);
+                Cached::Megamorphic
+            }
+        };
+        *cached = next;
+    }
+
+    /// the state of the site with its hits and misses, which only debug builds count
+    pub fn stats(&self) -> (&'static str, Option<(usize, usize)>) {
+        let state = match &*self.cached.read().unwrap() {
+            Cached::Empty => "uninitialized",
+            Cached::Monomorphic => "monomorphic",
+            Cached::Polymorphic(..) => "polymorphic",
+            Cached::Megamorphic => "megamorphic",
+        };
+        #[cfg(debug_assertions)]
+        let counts = Some((
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        ));
+        #[cfg(not(debug_assertions))]
+        let counts = None;
+        (state, counts)
+    }
+}
+
+impl Drop for InlineCache {
+    fn drop(&mut self) {
+        let first = *self.first.get_mut();
+        if !first.is_null() {
+            drop(unsafe { Box::from_raw(first) });
+        }
+    }
+}
+
+impl Code {
+    /// the instructions of `code`, or the static constraint of JVMS 4.9 it violates, `max_locals`
+    /// bounds the local variables the instructions use
+    pub fn decode(
+        code: &[U1],
+        max_locals: usize,
+        handlers: &[ExceptionHandler],
+        constants: &ConstantPool,
+    ) -> Result<Code, String> {
+        if code.is_empty() {
+            return Err("Empty code".to_owned());
+        }
+        // the index of the instruction starting at each pc, the end of the code included
+        let mut index = vec![usize::MAX; code.len() + 1];
+        let mut pc = 0;
+        let mut count = 0;
+        while pc < code.len() {
+            index[pc] = count;
+            count += 1;
+            pc += length(code, pc)?;
+        }
+        index[code.len()] = count;
+        let target = |pc: usize, offset: i32| {
+            let to = pc as i64 + offset as i64;
+            match index.get(to as usize) {
+                Some(i) if to >= 0 && *i != usize::MAX => Ok(*i),
+                _ => Err(format!("Illegal branch target {} at {}", to, pc)),
+            }
+        };
+        let mut insns = Vec::with_capacity(count);
+        let mut pcs = Vec::with_capacity(count);
+        let mut pc = 0;
+        while pc < code.len() {
+            let insn = decode(code, pc, constants, &target)?;
+            match &insn {
+                Insn::Load(local, slots) | Insn::Store(local, slots)
+                    if local + slots > max_locals =>
+                {
+                    return Err(format!("Illegal local variable {} at {}", local, pc));
+                }
+                Insn::Iinc(local, _) | Insn::Ret(local) if *local >= max_locals => {
+                    return Err(format!("Illegal local variable {} at {}", local, pc));
+                }
+                _ => {}
+            }
+            insns.push(insn);
+            pcs.push(pc as U2);
+            pc += length(code, pc)?;
+        }
+        let mut mapped = Vec::with_capacity(handlers.len());
+        for handler in handlers {
+            let (start_pc, end_pc) = (handler.start_pc as usize, handler.end_pc as usize);
+            if start_pc >= end_pc {
+                return Err(format!(
+                    "Illegal exception handler range {}..{}",
+                    start_pc, end_pc
+                ));
+            }
+            // only the end may be the end of the code
+            if handler.handler_pc as usize == code.len() || start_pc == code.len() {
+                return Err(format!(
+                    "Illegal exception handler at {}",
+                    handler.handler_pc
+                ));
+            }
+            mapped.push(ExceptionHandler {
+                start_pc: target(start_pc, 0)? as U2,
+                end_pc: target(end_pc, 0)? as U2,
+                handler_pc: target(handler.handler_pc as usize, 0)? as U2,
+                catch_type: handler.catch_type.clone(),
+            });
+        }
+        Ok(Code {
+            insns: insns,
+            pcs: pcs,
+            handlers: mapped,
+        })
+    }
+
+    /// the bytecode offset of the instruction at `index`
+    pub fn pc_of(&self, index: usize) -> U2 {
+        self.pcs[index]
+    }
+
+    /// the index of the instruction starting at bytecode offset `pc`
+    pub fn index_of(&self, pc: U2) -> Option<usize> {
+        self.pcs.binary_search(&pc).ok()
+    }
+}
+
+fn u2_at(code: &[U1], pc: usize) -> U2 {
+    U2::from_be_bytes([code[pc], code[pc + 1]])
+}
+
+fn i16_at(code: &[U1], pc: usize) -> i16 {
+    i16::from_be_bytes([code[pc], code[pc + 1]])
+}
+
+fn i32_at(code: &[U1], pc: usize) -> i32 {
+    i32::from_be_bytes([code[pc], code[pc + 1], code[pc + 2], code[pc + 3]])
+}
+
+// the length of the instruction at `pc`, which must be within the code
+fn length(code: &[U1], pc: usize) -> Result<usize, String> {
+    let length = match code[pc] {
+        0x10 | 0x12 | 0x15..=0x19 | 0x36..=0x3a | 0xa9 | 0xbc => 2,
+        0x11 | 0x13 | 0x14 | 0x84 | 0x99..=0xa8 | 0xb2..=0xb8 | 0xbb | 0xbd | 0xc0 | 0xc1 => 3,
+        0xc6 | 0xc7 | 0xd3 => 3,
+        0xc5 => 4,
+        0xb9 | 0xba | 0xc8 | 0xc9 => 5,
+        0xc4 if code.get(pc + 1) == Some(&0x84) => 6,
+        0xc4 => 4,
+        // operands are 4-byte aligned from the start of the code
+        0xaa => {
+            let base = (pc + 4) & !3;
+            match (i32_get(code, base + 4), i32_get(code, base + 8)) {
+                (Some(low), Some(high)) if low <= high => {
+                    base + 12 + (high as i64 - low as i64 + 1) as usize * 4 - pc
+                }
+                _ => return Err(format!("Illegal tableswitch at {}", pc)),
+            }
+        }
+        0xab => {
+            let base = (pc + 4) & !3;
+            match i32_get(code, base + 4) {
+                Some(npairs) if npairs >= 0 => base + 8 + npairs as usize * 8 - pc,
+                _ => return Err(format!("Illegal lookupswitch at {}", pc)),
+            }
+        }
+        _ => 1,
+    };
+    if pc + length > code.len() {
+        return Err(format!("Truncated instruction at {}", pc));
+    }
+    Ok(length)
+}
+
+fn i32_get(code: &[U1], pc: usize) -> Option<i32> {
+    code.get(pc..pc + 4).map(|_| i32_at(code, pc))
+}
+
+fn decode<F>(code: &[U1], pc: usize, constants: &ConstantPool, target: &F) -> Result<Insn, String>
+where
+    F: Fn(usize, i32) -> Result<usize, String>,
+{
+    let opcode = code[pc];
+    // the constant pool index following the opcode, naming a constant of the kind `kind` accepts
+    let constant = |kind: fn(&ConstantItem) -> bool| {
+        let idx = u2_at(code, pc + 1);
+        match constants.entry(idx) {
+            Some(item) if kind(item) => Ok(idx),
+            _ => Err(format!("Illegal constant #{} at {}", idx, pc)),
+        }
+    };
+    let field = |item: &ConstantItem| matches!(item, ConstantItem::FieldRef(..));
+    let method = |item: &ConstantItem| matches!(item, ConstantItem::MethodRef(..));
+    let any_method = |item: &ConstantItem| {
+        matches!(
+            item,
+            ConstantItem::MethodRef(..) | ConstantItem::InterfaceMethodRef(..)
+        )
+    };
+    let class = |item: &ConstantItem| matches!(item, ConstantItem::Class(_));
+    let loadable = |item: &ConstantItem| {
+        matches!(
+            item,
+            ConstantItem::Integer(_)
+                | ConstantItem::Float(_)
+                | ConstantItem::String(_)
+                | ConstantItem::Class(_)
+                | ConstantItem::MethodType(_)
+                | ConstantItem::MethodHandle(..)
+        )
+    };
+    let insn = match opcode {
+        0x00 => Insn::Nop,
+        0x01 => Insn::Push(NULL),
+        0x02..=0x08 => Insn::Push((opcode as i32 - 3).to_le_bytes()),
+        0x09..=0x0a => Insn::PushWide((opcode as i64 - 9).to_le_bytes()),
+        0x0b..=0x0d => Insn::Push((opcode as f32 - 11.0).to_le_bytes()),
+        0x0e..=0x0f => Insn::PushWide((opcode as f64 - 14.0).to_le_bytes()),
+        0x10 => Insn::Push((code[pc + 1] as i8 as i32).to_le_bytes()),
+        0x11 => Insn::Push((i16_at(code, pc + 1) as i32).to_le_bytes()),
+        0x12 => match constants.entry(code[pc + 1] as U2) {
+            Some(item) if loadable(item) => ldc(code[pc + 1] as U2, constants),
+            _ => return Err(format!("Illegal constant #{} at {}", code[pc + 1], pc)),
+        },
+        0x13 => ldc(constant(loadable)?, constants),
+        0x14 => match constants.get(constant(|item| {
+            matches!(item, ConstantItem::Long(_) | ConstantItem::Double(_))
+        })?) {
+            ConstantItem::Long(l) => Insn::PushWide(l.to_le_bytes()),
+            ConstantItem::Double(d) => Insn::PushWide(d.to_le_bytes()),
+            _ => unreachable!(),
+        },
+        // iload/fload/aload, lload/dload
+        0x15 | 0x17 | 0x19 => Insn::Load(code[pc + 1] as usize, 1),
+        0x16 | 0x18 => Insn::Load(code[pc + 1] as usize, 2),
+        // <t>load_<n> of int, long, float, double and reference
+        0x1a..=0x2d => {
+            let n = (opcode - 0x1a) as usize;
+            Insn::Load(n % 4, slots(n / 4))
+        }
+        0x2e..=0x35 => Insn::ArrayLoad(opcode),
+        0x36 | 0x38 | 0x3a => Insn::Store(code[pc + 1] as usize, 1),
+        0x37 | 0x39 => Insn::Store(code[pc + 1] as usize, 2),
+        0x3b..=0x4e => {
+            let n = (opcode - 0x3b) as usize;
+            Insn::Store(n % 4, slots(n / 4))
+        }
+        0x4f..=0x56 => Insn::ArrayStore(opcode),
+        0x57 => Insn::Pop,
+        0x58 => Insn::Pop2,
+        // slots are untyped, so every category form of an instruction is the same move
+        0x59 => Insn::Dup(1, 1),
+        0x5a => Insn::Dup(1, 2),
+        0x5b => Insn::Dup(1, 3),
+        0x5c => Insn::Dup(2, 2),
+        0x5d => Insn::Dup(2, 3),
+        0x5e => Insn::Dup(2, 4),
+        0x5f => Insn::Swap,
+        0x60..=0x83 => Insn::Math(opcode),
+        0x84 => Insn::Iinc(code[pc + 1] as usize, code[pc + 2] as i8 as i32),
+        0x85..=0x93 => Insn::Convert(opcode),
+        0x94 => Insn::Lcmp,
+        0x95 => Insn::Fcmp(-1),
+        0x96 => Insn::Fcmp(1),
+        0x97 => Insn::Dcmp(-1),
+        0x98 => Insn::Dcmp(1),
+        0x99..=0x9e => Insn::If(Cond::of(opcode - 0x99), branch(code, pc, target)?),
+        0x9f..=0xa4 => Insn::IfCmp(Cond::of(opcode - 0x9f), branch(code, pc, target)?),
+        0xa5 | 0xa6 => Insn::IfACmp(Cond::of(opcode - 0xa5), branch(code, pc, target)?),
+        0xa7 => Insn::Goto(branch(code, pc, target)?),
+        0xa8 => Insn::Jsr(branch(code, pc, target)?),
+        0xa9 => Insn::Ret(code[pc + 1] as usize),
+        0xaa => {
+            let base = (pc + 4) & !3;
+            let (low, high) = (i32_at(code, base + 4), i32_at(code, base + 8));
+            let targets = (0..(high as i64 - low as i64 + 1) as usize)
+                .map(|i| target(pc, i32_at(code, base + 12 + i * 4)))
+                .collect::<Result<_, _>>()?;
+            Insn::TableSwitch {
+                low: low,
+                default: target(pc, i32_at(code, base))?,
+                targets: targets,
+            }
+        }
+        0xab => {
+            let base = (pc + 4) & !3;
+            let mut pairs = Vec::<(i32, usize)>::new();
+            for pair in (0..i32_at(code, base + 4) as usize).map(|i| base + 8 + i * 8) {
+                let key = i32_at(code, pair);
+                if pairs.last().map_or(false, |(last, _)| *last >= key) {
+                    return Err(format!("Unsorted lookupswitch at {}", pc));
+                }
+                pairs.push((key, target(pc, i32_at(code, pair + 4))?));
+            }
+            Insn::LookupSwitch {
+                default: target(pc, i32_at(code, base))?,
+                pairs: pairs,
+            }
+        }
+        0xac..=0xb1 => Insn::Return,
+        0xb2 => Insn::GetStatic(constant(field)?),
+        0xb3 => Insn::PutStatic(constant(field)?),
+        0xb4 | 0xb5 => {
+            let idx = constant(field)?;
+            let offset = Offset::default();
+            match (opcode, is_wide(idx, constants)) {
+                (0xb4, false) => Insn::GetField(idx, offset),
+                (0xb4, true) => Insn::GetFieldWide(idx, offset),
+                (_, false) => Insn::PutField(idx, offset),
+                (_, true) => Insn::PutFieldWide(idx, offset),
+            }
+        }
+        0xb6 => Insn::InvokeVirtual(constant(method)?, InlineCache::default()),
+        0xb7 => Insn::InvokeSpecial(constant(any_method)?),
+        0xb8 => Insn::InvokeStatic(constant(any_method)?),
+        0xb9 => Insn::InvokeInterface(
+            constant(|item| matches!(item, ConstantItem::InterfaceMethodRef(..)))?,
+            InlineCache::default(),
+        ),
+        0xba => Insn::InvokeDynamic(constant(|item| {
+            matches!(item, ConstantItem::InvokeDynamic(..))
+        })?),
+        0xbb => Insn::New(constant(class)?),
+        0xbc => Insn::NewArray(match code[pc + 1] {
+            4 => "[Z",
+            5 => "[C",
+            6 => "[F",
+            7 => "[D",
+            8 => "[B",
+            9 => "[S",
+            10 => "[I",
+            11 => "[J",
+            atype => return Err(format!("Illegal array type {} at {}", atype, pc)),
+        }),
+        0xbd => Insn::ANewArray(constant(class)?),
+        0xbe => Insn::ArrayLength,
+        0xbf => Insn::AThrow,
+        0xc0 => Insn::CheckCast(constant(class)?),
+        0xc1 => Insn::InstanceOf(constant(class)?),
+        0xc2 => Insn::MonitorEnter,
+        0xc3 => Insn::MonitorExit,
+        0xc4 => {
+            let index = u2_at(code, pc + 2) as usize;
+            match code[pc + 1] {
+                0x15 | 0x17 | 0x19 => Insn::Load(index, 1),
+                0x16 | 0x18 => Insn::Load(index, 2),
+                0x36 | 0x38 | 0x3a => Insn::Store(index, 1),
+                0x37 | 0x39 => Insn::Store(index, 2),
+                0x84 => Insn::Iinc(index, i16_at(code, pc + 4) as i32),
+                0xa9 => Insn::Ret(index),
+                widened => {
+                    return Err(format!(
+                        "Instruction 0x{:02x} can't be widened at {}",
+                        widened, pc
+                    ))
+                }
+            }
+        }
+        0xc5 => match code[pc + 3] {
+            0 => return Err(format!("Illegal dimensions at {}", pc)),
+            dimensions => Insn::MultiANewArray(constant(class)?, dimensions as usize),
+        },
+        0xc6 => Insn::IfNull(Cond::Eq, branch(code, pc, target)?),
+        0xc7 => Insn::IfNull(Cond::Ne, branch(code, pc, target)?),
+        0xc8 => Insn::Goto(target(pc, i32_at(code, pc + 1))?),
+        0xc9 => Insn::Jsr(target(pc, i32_at(code, pc + 1))?),
+        0xd3 => Insn::InvokeAsync(constant(any_method)?),
+        _ => return Err(format!("Illegal opcode 0x{:02x} at {}", opcode, pc)),
+    };
+    Ok(insn)
+}
+
+// int, long, float, double and reference in the order of the typed opcodes
+fn slots(t: usize) -> usize {
+    match t {
+        1 | 3 => 2,
+        _ => 1,
+    }
+}
+
+fn branch<F>(code: &[U1], pc: usize, target: &F) -> Result<usize, String>
+where
+    F: Fn(usize, i32) -> Result<usize, String>,
+{
+    target(pc, i16_at(code, pc + 1) as i32)
+}
+
+fn ldc(idx: U2, constants: &ConstantPool) -> Insn {
+    match constants.get(idx) {
+        ConstantItem::Integer(i) => Insn::Push(i.to_le_bytes()),
+        ConstantItem::Float(f) => Insn::Push(f.to_le_bytes()),
+        _ => Insn::Ldc(idx),
+    }
+}
+
+fn is_wide(idx: U2, constants: &ConstantPool) -> bool {
+    match constants.get(idx) {
+        ConstantItem::FieldRef(_, nt) => match constants.get_name_and_type(*nt).1 {
+            "D" | "J" => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    pub fn test_decode() {
+        let constants = ConstantPool::new(vec![ConstantItem::NIL, ConstantItem::Integer(-7)]);
+        // bipush -2; ldc #1; wide iload 300; goto_w end; tableswitch; nop; end: return
+        let mut code = vec![0x10, 0xfe, 0x12, 0x01, 0xc4, 0x15, 0x01, 0x2c, 0xc8];
+        code.extend_from_slice(&25i32.to_be_bytes());
+        // tableswitch at 13 is padded to 16, default to `end`, low 0, high 0 and case 0 to `nop`
+        code.extend_from_slice(&[0xaa, 0x00, 0x00]);
+        for v in &[20, 0, 0, 19] {
+            code.extend_from_slice(&(*v as i32).to_be_bytes());
+        }
+        code.extend_from_slice(&[0x00, 0xb1]);
+        let handlers = vec![ExceptionHandler {
+            start_pc: 2,
+            end_pc: 33,
+            handler_pc: 32,
+            catch_type: None,
+        }];
+        let decoded = Code::decode(&code, 301, &handlers, &constants).unwrap();
+        let insns = decoded
+            .insns
+            .iter()
+            .map(|insn| format!("{:?}", insn))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                format!("Push({:?})", (-2i32).to_le_bytes()),
+                format!("Push({:?})", (-7i32).to_le_bytes()),
+                "Load(300, 1)".to_owned(),
+                "Goto(6)".to_owned(),
+                "TableSwitch { low: 0, default: 6, targets: [5] }".to_owned(),
+                "Nop".to_owned(),
+                "Return".to_owned(),
+            ],
+            insns
+        );
+        assert_eq!(vec![0, 2, 4, 8, 13, 32, 33], decoded.pcs);
+        assert_eq!(
+            (13, Some(4), None),
+            (decoded.pc_of(4), decoded.index_of(13), decoded.index_of(14))
+        );
+        let handler = &decoded.handlers[0];
+        assert_eq!(
+            (1, 6, 5),
+            (handler.start_pc, handler.end_pc, handler.handler_pc)
+        );
+    }
+
+    #[test]
+    pub fn test_malformed() {
+        let constants = ConstantPool::new(vec![ConstantItem::NIL, ConstantItem::Integer(-7)]);
+        let decode = |code: &[U1]| Code::decode(code, 1, &[], &constants).err();
+        let cases: Vec<(&[U1], &str)> = vec![
+            (&[], "Empty code"),
+            // invokevirtual without its index
+            (&[0x00, 0xb6, 0x00], "Truncated instruction at 1"),
+            (&[0xa7, 0x00, 0x02, 0xb1], "Illegal branch target 2 at 0"),
+            (&[0xa7, 0xff, 0xff, 0xb1], "Illegal branch target -1 at 0"),
+            (&[0xff], "Illegal opcode 0xff at 0"),
+            (
+                &[0xc4, 0x10, 0x00, 0x00],
+                "Instruction 0x10 can't be widened at 0",
+            ),
+            // getfield of an int constant
+            (&[0xb4, 0x00, 0x01], "Illegal constant #1 at 0"),
+            (&[0xb4, 0x00, 0x09], "Illegal constant #9 at 0"),
+            (&[0x1b], "Illegal local variable 1 at 0"),
+            (&[0xbc, 0x03], "Illegal array type 3 at 0"),
+            // tableswitch with low above high
+            (
+                &[0xaa, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0],
+                "Illegal tableswitch at 0",
+            ),
+        ];
+        for (code, message) in cases {
+            assert_eq!(Some(message.to_owned()), decode(code), "{:02x?}", code);
+        }
+        let handler = |start_pc, end_pc, handler_pc| ExceptionHandler {
+            start_pc: start_pc,
+            end_pc: end_pc,
+            handler_pc: handler_pc,
+            catch_type: None,
+        };
+        // a handler range ends at the end of the code at most, inside an instruction at no point
+        let code = [0x10, 0x01, 0xb1];
+        assert!(Code::decode(&code, 0, &[handler(0, 3, 2)], &constants).is_ok());
+        for &(start_pc, end_pc, handler_pc) in &[(1, 3, 2), (0, 3, 3), (2, 2, 0), (0, 4, 2)] {
+            let handlers = [handler(start_pc, end_pc, handler_pc)];
+            assert!(Code::decode(&code, 0, &handlers, &constants).is_err());
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use super::{atom::*, Traveler};
-use std::mem::transmute;
+use super::{
+    atom::*, is_field_descriptor, mutf8, parse_method_descriptor, ClassFormatError, Reader,
+    Traveler,
+};
 
+// the UTF-16 of the UTF8 entries holding an unpaired surrogate is kept by index, the Rust strings
+// of the entries replace it
 #[derive(Debug)]
-pub struct ConstantPool(Vec<ConstantItem>);
+pub struct ConstantPool(Vec<ConstantItem>, Vec<(U2, Vec<u16>)>);
 
 #[derive(Debug, Clone)]
 pub enum ConstantItem {
+    /// decoded from modified UTF-8, which a `str` can't borrow from the class file as it is
     UTF8(String),
     Integer(i32),
     Float(f32),
@@ -25,6 +30,13 @@ pub enum ConstantItem {
 }
 
 impl ConstantPool {
+    /// a pool of items built by the VM itself, `items[0]` is the unused entry
+    pub fn new(items: Vec<ConstantItem>) -> Self {
+        ConstantPool(items, vec![])
+    }
+
+    // the getters are given the indices of decoded instructions, which `Code::decode` checked
+    // the kinds of, and those the validated entries refer to
     pub fn get(&self, idx: U2) -> &ConstantItem {
         match self.0.get(idx as usize) {
             None => panic!("Illegal runtime constant pool"),
@@ -76,6 +88,118 @@ impl ConstantPool {
         }
     }
 
+    pub fn get_method_handle(&self, idx: U2) -> (U1, (&str, (&str, &str))) {
+        match self.get(idx) {
+            ConstantItem::MethodHandle(kind, r) => (*kind, self.get_javaref(*r)),
+            _ => panic!("invalid class file"),
+        }
+    }
+
+    pub fn get_method_type(&self, idx: U2) -> &str {
+        match self.get(idx) {
+            ConstantItem::MethodType(desc) => self.get_str(*desc),
+            _ => panic!("invalid class file"),
+        }
+    }
+
+    pub fn get_invoke_dynamic(&self, idx: U2) -> (U2, (&str, &str)) {
+        match self.get(idx) {
+            ConstantItem::InvokeDynamic(bootstrap, nt) => (*bootstrap, self.get_name_and_type(*nt)),
+            _ => panic!("invalid class file"),
+        }
+    }
+
+    /// the UTF-16 content of the String constant at `idx`
+    pub fn get_utf16(&self, idx: U2) -> Vec<u16> {
+        let utf8 = match self.get(idx) {
+            ConstantItem::String(utf8) => *utf8,
+            _ => panic!("invalid class file"),
+        };
+        match self.1.iter().find(|(unpaired, _)| *unpaired == utf8) {
+            Some((_, utf16)) => utf16.clone(),
+            None => self.get_str(utf8).encode_utf16().collect(),
+        }
+    }
+
+    /// the entry at `idx` if there is one
+    pub fn entry(&self, idx: U2) -> Option<&ConstantItem> {
+        self.0.get(idx as usize)
+    }
+
+    /// the UTF8 constant at `idx` if there is one
+    pub(super) fn utf8_at(&self, idx: U2) -> Option<&str> {
+        match self.0.get(idx as usize) {
+            Some(ConstantItem::UTF8(s)) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// the name of the class constant at `idx` if there is one
+    pub(super) fn class_at(&self, idx: U2) -> Option<&str> {
+        match self.0.get(idx as usize) {
+            Some(ConstantItem::Class(name)) => self.utf8_at(*name),
+            _ => None,
+        }
+    }
+
+    // JVMS 4.4, the entries referred to by each entry must be of the right kinds
+    fn validate(&self, offsets: &[usize]) -> Result<(), ClassFormatError> {
+        let is = |idx: U2, kind: fn(&ConstantItem) -> bool| {
+            self.0.get(idx as usize).map_or(false, |item| kind(item))
+        };
+        let utf8 = |item: &ConstantItem| matches!(item, ConstantItem::UTF8(_));
+        let class = |item: &ConstantItem| matches!(item, ConstantItem::Class(_));
+        // JVMS 4.3, the descriptor of the name and type at `nt`
+        let described = |nt: U2, method: bool| match self.0.get(nt as usize) {
+            Some(ConstantItem::NameAndType(_, t)) => match self.utf8_at(*t) {
+                Some(t) if method => parse_method_descriptor(t, 0).is_some(),
+                Some(t) => is_field_descriptor(t),
+                None => false,
+            },
+            _ => false,
+        };
+        for (idx, item) in self.0.iter().enumerate() {
+            let valid = match item {
+                ConstantItem::Class(n) | ConstantItem::String(n) => is(*n, utf8),
+                ConstantItem::MethodType(n) => self
+                    .utf8_at(*n)
+                    .map_or(false, |t| parse_method_descriptor(t, 0).is_some()),
+                ConstantItem::FieldRef(c, nt) => is(*c, class) && described(*nt, false),
+                ConstantItem::MethodRef(c, nt) | ConstantItem::InterfaceMethodRef(c, nt) => {
+                    is(*c, class) && described(*nt, true)
+                }
+                ConstantItem::NameAndType(n, t) => is(*n, utf8) && is(*t, utf8),
+                ConstantItem::MethodHandle(kind, r) => match *kind {
+                    REF_GET_FIELD..=REF_PUT_STATIC => {
+                        is(*r, |item| matches!(item, ConstantItem::FieldRef(..)))
+                    }
+                    REF_INVOKE_VIRTUAL | REF_NEW_INVOKE_SPECIAL => {
+                        is(*r, |item| matches!(item, ConstantItem::MethodRef(..)))
+                    }
+                    REF_INVOKE_STATIC | REF_INVOKE_SPECIAL => is(*r, |item| {
+                        matches!(
+                            item,
+                            ConstantItem::MethodRef(..) | ConstantItem::InterfaceMethodRef(..)
+                        )
+                    }),
+                    REF_INVOKE_INTERFACE => is(*r, |item| {
+                        matches!(item, ConstantItem::InterfaceMethodRef(..))
+                    }),
+                    _ => false,
+                },
+                ConstantItem::InvokeDynamic(_, nt) => described(*nt, true),
+                _ => true,
+            };
+            if !valid {
+                return Err(ClassFormatError::new(
+                    offsets[idx],
+                    format!("Invalid constant pool entry #{}", idx),
+                ));
+            }
+        }
+        Ok(())
+    }
+
     pub fn get_str(&self, idx: U2) -> &str {
         if idx == 0 {
             return "";
@@ -101,99 +225,124 @@ impl ConstantPool {
 }
 
 impl Traveler<ConstantPool> for ConstantPool {
-    fn read<I>(seq: &mut I, _constants: Option<&ConstantPool>) -> ConstantPool
-    where
-        I: Iterator<Item = u8>,
-    {
-        let size = U2::read(seq, None);
+    fn read(
+        seq: &mut Reader,
+        _constants: Option<&ConstantPool>,
+    ) -> Result<ConstantPool, ClassFormatError> {
+        let at = seq.offset();
+        let size = U2::read(seq, None)?;
+        if size == 0 {
+            return Err(ClassFormatError::new(
+                at,
+                "Illegal constant pool size 0".to_owned(),
+            ));
+        }
         let mut pool = Vec::<ConstantItem>::with_capacity(size as usize);
+        let mut unpaired = vec![];
+        // where each entry starts, for the errors found after the whole pool is read
+        let mut offsets = Vec::<usize>::with_capacity(size as usize);
         pool.push(ConstantItem::NIL);
+        offsets.push(at);
         let mut offset = 1;
         while offset < size {
-            let tag = U1::read(seq, None);
+            let at = seq.offset();
+            let tag = U1::read(seq, None)?;
             let ele = match tag {
                 INVOKEDYNAMIC_TAG => {
-                    let bootstrap_method_attr_idx = U2::read(seq, None);
-                    let name_and_type_idx = U2::read(seq, None);
+                    let bootstrap_method_attr_idx = U2::read(seq, None)?;
+                    let name_and_type_idx = U2::read(seq, None)?;
                     ConstantItem::InvokeDynamic(bootstrap_method_attr_idx, name_and_type_idx)
                 }
                 METHODTYPE_TAG => {
-                    let desc_idx = U2::read(seq, None);
+                    let desc_idx = U2::read(seq, None)?;
                     ConstantItem::MethodType(desc_idx)
                 }
                 METHODHANDLE_TAG => {
-                    let ref_type = U1::read(seq, None);
-                    let ref_idx = U2::read(seq, None);
+                    let ref_type = U1::read(seq, None)?;
+                    let ref_idx = U2::read(seq, None)?;
                     ConstantItem::MethodHandle(ref_type, ref_idx)
                 }
                 INTERFACEMETHODREF_TAG => {
-                    let class_idx = U2::read(seq, None);
-                    let name_and_type_idx = U2::read(seq, None);
+                    let class_idx = U2::read(seq, None)?;
+                    let name_and_type_idx = U2::read(seq, None)?;
                     ConstantItem::InterfaceMethodRef(class_idx, name_and_type_idx)
                 }
                 STRING_TAG => {
-                    let string_idx = U2::read(seq, None);
+                    let string_idx = U2::read(seq, None)?;
                     ConstantItem::String(string_idx)
                 }
                 CLASS_TAG => {
-                    let name_idx = U2::read(seq, None);
+                    let name_idx = U2::read(seq, None)?;
                     ConstantItem::Class(name_idx)
                 }
                 METHODREF_TAG => {
-                    let class_idx = U2::read(seq, None);
-                    let name_and_type_idx = U2::read(seq, None);
+                    let class_idx = U2::read(seq, None)?;
+                    let name_and_type_idx = U2::read(seq, None)?;
                     ConstantItem::MethodRef(class_idx, name_and_type_idx)
                 }
                 FIELDREF_TAG => {
-                    let class_idx = U2::read(seq, None);
-                    let name_and_type_idx = U2::read(seq, None);
+                    let class_idx = U2::read(seq, None)?;
+                    let name_and_type_idx = U2::read(seq, None)?;
                     ConstantItem::FieldRef(class_idx, name_and_type_idx)
                 }
                 UTF8_TAG => {
-                    let length = U2::read(seq, None);
-                    let mut buf = Vec::<u8>::with_capacity(length as usize);
-                    for _x in 0..length {
-                        buf.push(U1::read(seq, None));
+                    let length = U2::read(seq, None)?;
+                    let buf = seq.bytes(length as usize)?;
+                    let s = mutf8::decode(buf).map_err(|i| {
+                        ClassFormatError::new(
+                            at + 3 + i,
+                            format!("Illegal UTF8 constant #{}", offset),
+                        )
+                    })?;
+                    if s.contains(std::char::REPLACEMENT_CHARACTER) {
+                        let utf16 = mutf8::decode_utf16(buf).unwrap();
+                        if String::from_utf16(&utf16).is_err() {
+                            unpaired.push((offset, utf16));
+                        }
                     }
-                    // TODO MUTF-8 encode
-                    let s = std::str::from_utf8(&buf).unwrap();
-                    ConstantItem::UTF8(s.to_string())
-                }
-                INTEGER_TAG => {
-                    let v = U4::read(seq, None);
-                    let i: i32 = unsafe { transmute::<u32, i32>(v) };
-                    ConstantItem::Integer(i)
+                    ConstantItem::UTF8(s)
                 }
-                FLOAT_TAG => {
-                    let v = U4::read(seq, None);
-                    let i: f32 = unsafe { transmute::<u32, f32>(v) };
-                    ConstantItem::Float(i)
+                INTEGER_TAG => ConstantItem::Integer(i32::from_be_bytes(seq.array()?)),
+                FLOAT_TAG => ConstantItem::Float(f32::from_be_bytes(seq.array()?)),
+                LONG_TAG | DOUBLE_TAG if offset + 1 == size => {
+                    return Err(ClassFormatError::new(
+                        at,
+                        format!("8-byte constant #{} overflows the constant pool", offset),
+                    ));
                 }
                 LONG_TAG => {
-                    let v = U8::read(seq, None);
-                    let i: i64 = unsafe { transmute::<u64, i64>(v) };
+                    let v = i64::from_be_bytes(seq.array()?);
                     offset = offset + 1;
-                    pool.push(ConstantItem::Long(i));
+                    pool.push(ConstantItem::Long(v));
+                    offsets.push(at);
                     ConstantItem::PADDING
                 }
                 DOUBLE_TAG => {
-                    let v = U8::read(seq, None);
-                    let i: f64 = unsafe { transmute::<u64, f64>(v) };
+                    let v = f64::from_be_bytes(seq.array()?);
                     offset = offset + 1;
-                    pool.push(ConstantItem::Double(i));
+                    pool.push(ConstantItem::Double(v));
+                    offsets.push(at);
                     ConstantItem::PADDING
                 }
                 NAMEANDTYPE_TAG => {
-                    let name_idx = U2::read(seq, None);
-                    let desc_idx = U2::read(seq, None);
+                    let name_idx = U2::read(seq, None)?;
+                    let desc_idx = U2::read(seq, None)?;
                     ConstantItem::NameAndType(name_idx, desc_idx)
                 }
-                _ => panic!("invalid classfile"),
+                _ => {
+                    return Err(ClassFormatError::new(
+                        at,
+                        format!("Unknown constant pool tag {}", tag),
+                    ))
+                }
             };
             offset = offset + 1;
             pool.push(ele);
+            offsets.push(at);
         }
-        ConstantPool(pool)
+        let pool = ConstantPool(pool, unpaired);
+        pool.validate(&offsets)?;
+        Ok(pool)
     }
 }
 
@@ -211,3 +360,13 @@ const NAMEANDTYPE_TAG: u8 = 12;
 const METHODHANDLE_TAG: u8 = 15;
 const METHODTYPE_TAG: u8 = 16;
 const INVOKEDYNAMIC_TAG: u8 = 18;
+
+pub const REF_GET_FIELD: U1 = 1;
+pub const REF_GET_STATIC: U1 = 2;
+pub const REF_PUT_FIELD: U1 = 3;
+pub const REF_PUT_STATIC: U1 = 4;
+pub const REF_INVOKE_VIRTUAL: U1 = 5;
+pub const REF_INVOKE_STATIC: U1 = 6;
+pub const REF_INVOKE_SPECIAL: U1 = 7;
+pub const REF_NEW_INVOKE_SPECIAL: U1 = 8;
+pub const REF_INVOKE_INTERFACE: U1 = 9;
@@ -1,4 +1,6 @@
-use super::{atom::*, attribute::*, constant_pool::ConstantPool, Traveler};
+use super::{
+    atom::*, attribute::*, constant_pool::ConstantPool, ClassFormatError, Reader, Traveler,
+};
 use crate::mem::Value;
 use std::cell::Cell;
 use std::cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd};
@@ -76,16 +78,16 @@ impl PartialEq for Field {
 }
 
 impl Traveler<Fields> for Fields {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> Fields
-    where
-        I: Iterator<Item = u8>,
-    {
-        let size = U2::read(seq, None);
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<Fields, ClassFormatError> {
+        let size = U2::read(seq, None)?;
         let mut fields = Vec::<Arc<Field>>::with_capacity(size as usize);
         for _x in 0..size {
-            fields.push(Arc::new(Field::read(seq, constants)));
+            fields.push(Arc::new(Field::read(seq, constants)?));
         }
-        fields
+        Ok(fields)
     }
 }
 
@@ -102,22 +104,18 @@ fn init_value(access_flag: u16, descriptor: &str) -> Option<Value> {
 }
 
 impl Traveler<Field> for Field {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> Field
-    where
-        I: Iterator<Item = u8>,
-    {
-        let access_flag = U2::read(seq, None);
-        let name_idx = U2::read(seq, None);
-        let descriptor_idx = U2::read(seq, None);
+    fn read(seq: &mut Reader, constants: Option<&ConstantPool>) -> Result<Field, ClassFormatError> {
+        let access_flag = U2::read(seq, None)?;
         if let Some(pool) = constants {
-            let descriptor = pool.get_str(descriptor_idx).to_string();
-            return Field {
+            let name = seq.utf8(pool)?.to_string();
+            let descriptor = seq.descriptor(pool, false)?.to_string();
+            return Ok(Field {
                 access_flag: access_flag,
-                name: pool.get_str(name_idx).to_string(),
+                name: name,
                 value: Cell::new(None),
                 descriptor: descriptor,
-                attributes: Attributes::read(seq, Some(pool)),
-            };
+                attributes: Attributes::read(seq, Some(pool))?,
+            });
         }
         panic!("need constant pool to resolve fields")
     }
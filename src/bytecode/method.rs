@@ -1,6 +1,9 @@
-use super::{atom::*, attribute::*, constant_pool::ConstantPool, Traveler};
+use super::{
+    atom::*, attribute::*, class::Class, code::Code, constant_pool::ConstantPool,
+    resolve_method_descriptor, ClassFormatError, Reader, Traveler,
+};
 
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 pub type Methods = Vec<Arc<Method>>;
 
@@ -33,21 +36,24 @@ pub struct Method {
     pub name: String,
     pub descriptor: String,
     pub attributes: Attributes,
+    /// the code decoded for the interpreter by the first invocation
+    pub decoded: RwLock<Option<Arc<Code>>>,
 }
 
 impl Traveler<Method> for Method {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> Method
-    where
-        I: Iterator<Item = u8>,
-    {
-        let access_flag = U2::read(seq, None);
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<Method, ClassFormatError> {
+        let access_flag = U2::read(seq, None)?;
         if let Some(pool) = constants {
-            return Method {
+            return Ok(Method {
                 access_flag: access_flag,
-                name: pool.get_str(U2::read(seq, None)).to_string(),
-                descriptor: pool.get_str(U2::read(seq, None)).to_string(),
-                attributes: Attributes::read(seq, Some(pool)),
-            };
+                name: seq.utf8(pool)?.to_string(),
+                descriptor: seq.descriptor(pool, true)?.to_string(),
+                attributes: Attributes::read(seq, Some(pool))?,
+                decoded: RwLock::new(None),
+            });
         }
         panic!("need constant pool to resolve methods");
     }
@@ -80,6 +86,132 @@ impl Method {
         return None;
     }
 
+    /// the decoded code, or why it can't be decoded, `constants` is the constant pool of the
+    /// declaring class
+    pub fn get_decoded(&self, constants: &ConstantPool) -> Option<Result<Arc<Code>, String>> {
+        if let Some(code) = self.decoded.read().unwrap().as_ref() {
+            return Some(Ok(Arc::clone(code)));
+        }
+        let (_, max_locals, code, handlers, _) = self.get_code()?;
+        let code = match Code::decode(&code, max_locals as usize, &handlers, constants) {
+            Ok(code) => code,
+            Err(message) => return Some(Err(message)),
+        };
+        // the first decoded code wins when threads race
+        let mut decoded = self.decoded.write().unwrap();
+        Some(Ok(Arc::clone(
+            decoded.get_or_insert_with(|| Arc::new(code)),
+        )))
+    }
+
+    /// the source line of the instruction at bytecode offset `pc`, `None` without a
+    /// LineNumberTable
+    pub fn line_for_pc(&self, pc: U2) -> Option<U2> {
+        let (_, _, code, _, attributes) = self.get_code()?;
+        if pc as usize >= code.len() {
+            return None;
+        }
+        let mut found: Option<(U2, U2)> = None;
+        for attr in attributes.iter() {
+            if let Attribute::LineNumberTable(lines) = attr {
+                for line in lines.get(None).ok()?.iter() {
+                    if line.start_pc <= pc
+                        && found.map_or(true, |(start_pc, _)| start_pc <= line.start_pc)
+                    {
+                        found = Some((line.start_pc, line.line_number));
+                    }
+                }
+            }
+        }
+        found.map(|(_, line)| line)
+    }
+
+    /// the local variable in `slot` at bytecode offset `pc`, `constants` is the constant pool of
+    /// the declaring class
+    pub fn local_variable(
+        &self,
+        constants: &ConstantPool,
+        slot: U2,
+        pc: U2,
+    ) -> Option<LocalVariable> {
+        self.find_local_variable(constants, slot, pc, false)
+    }
+
+    /// the generic signature of the local variable in `slot` at bytecode offset `pc` if it has one
+    pub fn local_variable_signature(
+        &self,
+        constants: &ConstantPool,
+        slot: U2,
+        pc: U2,
+    ) -> Option<String> {
+        self.find_local_variable(constants, slot, pc, true)
+            .map(|variable| variable.descriptor)
+    }
+
+    fn find_local_variable(
+        &self,
+        constants: &ConstantPool,
+        slot: U2,
+        pc: U2,
+        typed: bool,
+    ) -> Option<LocalVariable> {
+        let (_, _, _, _, attributes) = self.get_code()?;
+        for attr in attributes.iter() {
+            let table = match (attr, typed) {
+                (Attribute::LocalVariableTable(table), false) => table,
+                (Attribute::LocalVariableTypeTable(table), true) => table,
+                _ => continue,
+            };
+            let found = table
+                .get(Some(constants))
+                .ok()?
+                .iter()
+                .find(|v| {
+                    v.index == slot
+                        && v.start_pc <= pc
+                        && (pc as u32) < v.start_pc as u32 + v.length as u32
+                })
+                .cloned();
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+
+    /// the frames of the StackMapTable, none without one, `class` is the declaring class
+    pub fn stack_map(&self, class: &Class) -> Result<Vec<Frame>, ClassFormatError> {
+        let (_, _, _, _, attributes) = match self.get_code() {
+            Some(code) => code,
+            None => return Ok(vec![]),
+        };
+        for attr in attributes.iter() {
+            if let Attribute::StackMapTable(table) = attr {
+                let frames = table.get(Some(&class.constant_pool))?;
+                // the implicit frame holds `this` and the parameters
+                let mut initial = vec![];
+                if !self.is_static() {
+                    initial.push(
+                        if self.name == "<init>" && class.get_name() != "java/lang/Object" {
+                            VerificationType::UninitializedThis
+                        } else {
+                            VerificationType::Object(class.get_name().to_owned())
+                        },
+                    );
+                }
+                let (params, _, _) = resolve_method_descriptor(&self.descriptor, self.access_flag);
+                initial.extend(params.iter().map(|p| VerificationType::of(p)));
+                let code = self
+                    .get_decoded(&class.constant_pool)
+                    .unwrap()
+                    .map_err(|message| ClassFormatError::new(table.offset(), message))?;
+                return expand_frames(&frames, initial, &code.pcs)
+                    .map_err(|message| ClassFormatError::new(table.offset(), message));
+            }
+        }
+        Ok(vec![])
+    }
+
     pub fn get_name_and_descriptor(&self) -> (&str, &str, U2) {
         (
             self.name.as_ref(),
@@ -92,6 +224,10 @@ impl Method {
         self.access_flag & ACC_PUBLIC == ACC_PUBLIC
     }
 
+    pub fn is_private(&self) -> bool {
+        self.access_flag & ACC_PRIVATE == ACC_PRIVATE
+    }
+
     pub fn is_protected(&self) -> bool {
         self.access_flag & ACC_PROTECTED == ACC_PROTECTED
     }
@@ -111,20 +247,28 @@ impl Method {
     pub fn is_synchronized(&self) -> bool {
         self.access_flag & ACC_SYNCHRONIZED == ACC_SYNCHRONIZED
     }
+
+    pub fn is_abstract(&self) -> bool {
+        self.access_flag & ACC_ABSTRACT == ACC_ABSTRACT
+    }
+
+    pub fn is_varargs(&self) -> bool {
+        self.access_flag & ACC_VARARGS == ACC_VARARGS
+    }
 }
 
 impl Traveler<Methods> for Methods {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> Methods
-    where
-        I: Iterator<Item = u8>,
-    {
-        let size = U2::read(seq, None);
+    fn read(
+        seq: &mut Reader,
+        constants: Option<&ConstantPool>,
+    ) -> Result<Methods, ClassFormatError> {
+        let size = U2::read(seq, None)?;
         let mut methods = Vec::<Arc<Method>>::with_capacity(size as usize);
         for _x in 0..size {
-            let method = Method::read(seq, constants);
+            let method = Method::read(seq, constants)?;
             methods.push(Arc::new(method));
         }
-        methods
+        Ok(methods)
     }
 }
 
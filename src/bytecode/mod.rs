@@ -1,100 +1,206 @@
 pub mod atom;
 pub mod attribute;
 pub mod class;
+pub mod code;
 pub mod constant_pool;
 pub mod field;
 pub mod interface;
 pub mod method;
+pub mod mutf8;
 
 use self::atom::*;
 use self::constant_pool::ConstantPool;
+use std::fmt;
 
-trait Traveler<T> {
-    fn read<I>(seq: &mut I, constants: Option<&ConstantPool>) -> T
-    where
-        I: Iterator<Item = u8>;
+pub(crate) trait Traveler<T> {
+    fn read(seq: &mut Reader, constants: Option<&ConstantPool>) -> Result<T, ClassFormatError>;
+}
+
+/// a malformed class file, `offset` is where the malformed bytes start
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassFormatError {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl ClassFormatError {
+    pub fn new(offset: usize, message: String) -> Self {
+        ClassFormatError { offset, message }
+    }
+}
+
+impl fmt::Display for ClassFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at offset {}", self.message, self.offset)
+    }
+}
+
+/// a cursor over the bytes of a class file
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    // the offset in the class file of `bytes[0]`
+    base: usize,
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self::at(bytes, 0)
+    }
+
+    /// a cursor over `bytes` cut from a class file at `base`
+    fn at(bytes: &'a [u8], base: usize) -> Self {
+        Reader {
+            bytes: bytes,
+            base: base,
+            pos: 0,
+        }
+    }
+
+    /// the offset in the class file read up to
+    fn offset(&self) -> usize {
+        self.base + self.pos
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], ClassFormatError> {
+        if self.bytes.len() - self.pos < length {
+            return Err(ClassFormatError::new(
+                self.base + self.bytes.len(),
+                "Truncated class file".to_owned(),
+            ));
+        }
+        let bytes = &self.bytes[self.pos..self.pos + length];
+        self.pos = self.pos + length;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ClassFormatError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    fn is_end(&self) -> bool {
+        self.pos == self.bytes.len()
+    }
+
+    /// reads the index of a UTF8 constant
+    fn utf8<'p>(&mut self, pool: &'p ConstantPool) -> Result<&'p str, ClassFormatError> {
+        let at = self.offset();
+        let idx = U2::read(self, None)?;
+        pool.utf8_at(idx)
+            .ok_or_else(|| ClassFormatError::new(at, format!("Invalid UTF8 constant #{}", idx)))
+    }
+
+    /// reads the index of a UTF8 constant holding a method descriptor, or a field descriptor
+    fn descriptor<'p>(
+        &mut self,
+        pool: &'p ConstantPool,
+        method: bool,
+    ) -> Result<&'p str, ClassFormatError> {
+        let at = self.offset();
+        let descriptor = self.utf8(pool)?;
+        let valid = if method {
+            parse_method_descriptor(descriptor, 0).is_some()
+        } else {
+            is_field_descriptor(descriptor)
+        };
+        if !valid {
+            return Err(ClassFormatError::new(
+                at,
+                format!("Illegal descriptor {}", descriptor),
+            ));
+        }
+        Ok(descriptor)
+    }
+
+    /// reads the index of a class constant, `0` is taken as no class if `optional`
+    fn class<'p>(
+        &mut self,
+        pool: &'p ConstantPool,
+        optional: bool,
+    ) -> Result<Option<&'p str>, ClassFormatError> {
+        let at = self.offset();
+        let idx = U2::read(self, None)?;
+        if idx == 0 && optional {
+            return Ok(None);
+        }
+        pool.class_at(idx)
+            .map(Some)
+            .ok_or_else(|| ClassFormatError::new(at, format!("Invalid class constant #{}", idx)))
+    }
 }
 
 pub const METHOD_ACC_STATIC: U2 = 0x0008;
 
+/// the parameters, the slots they take with `this` and the return type of `descriptor`, which
+/// was checked when its class file was read
 pub fn resolve_method_descriptor(
     descriptor: &str,
     access_flag: U2,
 ) -> (Vec<String>, usize, String) {
-    let t = descriptor
-        .chars()
-        .into_iter()
-        .map(|x| x.to_string())
-        .collect::<Vec<_>>();
-    let mut params: Vec<String> = vec![];
-    let mut expect_type: bool = false;
-    let mut expect_semicolon: bool = false;
-    let mut token: String = String::new();
+    match parse_method_descriptor(descriptor, access_flag) {
+        Some(resolved) => resolved,
+        None => panic!("Illegal method descriptor: {}", descriptor),
+    }
+}
+
+/// JVMS 4.3.3, `resolve_method_descriptor` of a descriptor that may be malformed
+pub fn parse_method_descriptor(
+    descriptor: &str,
+    access_flag: U2,
+) -> Option<(Vec<String>, usize, String)> {
+    let bytes = descriptor.as_bytes();
+    if bytes.first() != Some(&b'(') {
+        return None;
+    }
+    let mut params = vec![];
+    let mut at = 1;
+    while *bytes.get(at)? != b')' {
+        let len = field_type_length(&bytes[at..])?;
+        // the types are cut at ASCII bytes
+        params.push(descriptor[at..at + len].to_owned());
+        at += len;
+    }
+    let ret = &descriptor[at + 1..];
+    if ret != "V" && !is_field_descriptor(ret) {
+        return None;
+    }
     let instance = if access_flag & METHOD_ACC_STATIC == METHOD_ACC_STATIC {
         0
     } else {
         1
     };
-    for (i, ch) in descriptor.chars().enumerate() {
-        if expect_semicolon {
-            token.push(ch);
-            if ch == ';' {
-                expect_semicolon = false;
-                expect_type = false;
-                params.push(token.clone());
-                token.clear();
-            }
-            continue;
-        }
-        match ch {
-            '(' => {
-                if expect_type {
-                    panic!(format!("Illegal method descriptor: {}", descriptor));
-                }
-                continue;
-            }
-            ')' => {
-                if expect_type {
-                    panic!(format!("Illegal method descriptor: {}", descriptor));
-                }
-                let slots = params
-                    .iter()
-                    .map(|x| match x.as_ref() {
-                        "D" | "J" => 2,
-                        _ => 1,
-                    })
-                    .sum::<usize>()
-                    + instance;
-                return (params, slots, t[i + 1..].join(""));
-            }
-            '[' => {
-                expect_type = true;
-                token.push('[');
-            }
-            'L' => {
-                expect_semicolon = true;
-                token.push('L');
-            }
-            'I' | 'J' | 'S' | 'B' | 'C' | 'F' | 'D' | 'Z' => {
-                if expect_type {
-                    token.push(ch);
-                    params.push(token.clone());
-                    token.clear();
-                    expect_type = false;
-                } else {
-                    params.push(ch.to_string());
-                }
-            }
-            _ => {
-                if expect_semicolon {
-                    token.push(ch);
-                } else {
-                    panic!(format!("Illegal method descriptor: {}", descriptor));
-                }
-            }
-        }
+    let slots = params
+        .iter()
+        .map(|x| match x.as_ref() {
+            "D" | "J" => 2,
+            _ => 1,
+        })
+        .sum::<usize>()
+        + instance;
+    Some((params, slots, ret.to_owned()))
+}
+
+/// JVMS 4.3.2
+pub fn is_field_descriptor(descriptor: &str) -> bool {
+    field_type_length(descriptor.as_bytes()) == Some(descriptor.len())
+}
+
+// the length of the field type `descriptor` starts with
+fn field_type_length(descriptor: &[u8]) -> Option<usize> {
+    let dimensions = descriptor.iter().take_while(|b| **b == b'[').count();
+    if dimensions > 255 {
+        return None;
+    }
+    match descriptor.get(dimensions)? {
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' => Some(dimensions + 1),
+        b'L' => match descriptor[dimensions..].iter().position(|b| *b == b';') {
+            Some(end) if end > 1 => Some(dimensions + end + 1),
+            _ => None,
+        },
+        _ => None,
     }
-    panic!(format!("Illegal method descriptor: {}", descriptor));
 }
 
 #[test]
@@ -115,3 +221,28 @@ pub fn test_resolve_method() {
     assert_eq!(slots, 1);
     assert_eq!(ret, "V");
 }
+
+#[test]
+pub fn test_illegal_descriptor() {
+    for descriptor in &[
+        "",
+        "V",
+        "()",
+        "(V)V",
+        "(I",
+        "(L;)V",
+        "(Ljava/lang/String)V",
+        "()[V",
+        "()II",
+    ] {
+        assert_eq!(
+            None,
+            parse_method_descriptor(descriptor, 0),
+            "{}",
+            descriptor
+        );
+    }
+    assert!(is_field_descriptor("[[Ljava/lang/Object;"));
+    assert!(!is_field_descriptor("Ljava/lang/Object;I"));
+    assert!(!is_field_descriptor(&"[".repeat(256)));
+}
@@ -0,0 +1,123 @@
+// JVMS 4.4.7, the modified UTF-8 of class files differs from UTF-8 in two ways: NUL takes the
+// two bytes `C0 80` so that no byte is zero, and a supplementary character takes the six bytes
+// of its UTF-16 surrogate pair encoded one by one.
+
+/// decodes modified UTF-8, the error is the index of the first malformed byte
+///
+/// A surrogate left unpaired, legal in a Java string but not in a Rust one, is decoded as U+FFFD,
+/// `decode_utf16` keeps it.
+pub fn decode(bytes: &[u8]) -> Result<String, usize> {
+    // names and descriptors are mostly ASCII, which reads the same in UTF-8
+    if bytes.iter().all(|b| *b != 0 && *b < 0x80) {
+        return Ok(std::str::from_utf8(bytes).unwrap().to_owned());
+    }
+    Ok(std::char::decode_utf16(decode_utf16(bytes)?)
+        .map(|c| c.unwrap_or(std::char::REPLACEMENT_CHARACTER))
+        .collect())
+}
+
+/// decodes modified UTF-8 to the UTF-16 of a Java string, the error is the index of the first
+/// malformed byte
+pub fn decode_utf16(bytes: &[u8]) -> Result<Vec<u16>, usize> {
+    let mut utf16 = Vec::<u16>::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let continuation = |n: usize| match bytes.get(i + n) {
+            Some(c) if c & 0xc0 == 0x80 => Ok((c & 0x3f) as u16),
+            _ => Err(i + n),
+        };
+        match b {
+            0x01..=0x7f => {
+                utf16.push(b as u16);
+                i = i + 1;
+            }
+            0xc0..=0xdf => {
+                utf16.push((b as u16 & 0x1f) << 6 | continuation(1)?);
+                i = i + 2;
+            }
+            0xe0..=0xef => {
+                utf16.push((b as u16 & 0x0f) << 12 | continuation(1)? << 6 | continuation(2)?);
+                i = i + 3;
+            }
+            _ => return Err(i),
+        }
+    }
+    Ok(utf16)
+}
+
+/// encodes `s` in modified UTF-8
+pub fn encode(s: &str) -> Vec<u8> {
+    let mut bytes = Vec::<u8>::with_capacity(s.len());
+    for unit in s.encode_utf16() {
+        match unit {
+            0x0001..=0x007f => bytes.push(unit as u8),
+            0x0000 | 0x0080..=0x07ff => {
+                bytes.push(0xc0 | (unit >> 6) as u8);
+                bytes.push(0x80 | (unit & 0x3f) as u8);
+            }
+            _ => {
+                bytes.push(0xe0 | (unit >> 12) as u8);
+                bytes.push(0x80 | (unit >> 6 & 0x3f) as u8);
+                bytes.push(0x80 | (unit & 0x3f) as u8);
+            }
+        }
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    pub fn test_round_trip() {
+        let cases: &[(&str, &[u8])] = &[
+            ("", &[]),
+            ("java/lang/Object", b"java/lang/Object"),
+            ("\0", &[0xc0, 0x80]),
+            ("a\0b", &[0x61, 0xc0, 0x80, 0x62]),
+            ("\u{7f}\u{80}", &[0x7f, 0xc2, 0x80]),
+            ("h\u{e9}", &[0x68, 0xc3, 0xa9]),
+            ("\u{7ff}\u{800}", &[0xdf, 0xbf, 0xe0, 0xa0, 0x80]),
+            ("\u{65e5}\u{672c}", &[0xe6, 0x97, 0xa5, 0xe6, 0x9c, 0xac]),
+            ("\u{ffff}", &[0xef, 0xbf, 0xbf]),
+            ("\u{1f600}", &[0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80]),
+            ("\u{20000}", &[0xed, 0xa1, 0x80, 0xed, 0xb0, 0x80]),
+            ("\u{10ffff}", &[0xed, 0xaf, 0xbf, 0xed, 0xbf, 0xbf]),
+        ];
+        for (s, mutf8) in cases {
+            assert_eq!(*mutf8, &encode(s)[..], "{}", s);
+            assert_eq!(Ok(s.to_string()), decode(mutf8));
+        }
+        // all the characters of the basic and a supplementary plane
+        let all = (0..0x20000)
+            .filter_map(std::char::from_u32)
+            .collect::<String>();
+        let bytes = encode(&all);
+        assert!(bytes.iter().all(|b| *b != 0 && *b < 0xf0));
+        assert_eq!(Ok(all), decode(&bytes));
+    }
+
+    #[test]
+    pub fn test_malformed() {
+        assert_eq!(Err(1), decode(&[0x61, 0x00]));
+        assert_eq!(Err(0), decode(&[0xf0, 0x9f, 0x98, 0x80]));
+        assert_eq!(Err(0), decode(&[0x80]));
+        assert_eq!(Err(2), decode(&[0xe6, 0x97]));
+        assert_eq!(Err(1), decode(&[0xc3, 0x69]));
+        // unpaired surrogates
+        assert_eq!(
+            Ok("\u{fffd}a".to_owned()),
+            decode(&[0xed, 0xa0, 0xbd, 0x61])
+        );
+        assert_eq!(Ok("\u{fffd}".to_owned()), decode(&[0xed, 0xb8, 0x80]));
+        assert_eq!(
+            Ok(vec![0xd83d, 0x61]),
+            decode_utf16(&[0xed, 0xa0, 0xbd, 0x61])
+        );
+        assert_eq!(Ok(vec![0xde00]), decode_utf16(&[0xed, 0xb8, 0x80]));
+        assert_eq!(Err(2), decode_utf16(&[0xe6, 0x97]));
+    }
+}
@@ -0,0 +1,560 @@
+use super::{
+    concat::{self, Chunk},
+    constants, lambda,
+    thread::ThreadContext,
+};
+use crate::bytecode::{
+    self, atom::*, class::Class, constant_pool::*, method::Method, METHOD_ACC_STATIC,
+};
+use crate::mem::{
+    heap::Heap,
+    klass::{Klass, OBJ_HEADER_SIZE},
+    metaspace::ClassArena,
+    pool::find_method,
+    strings::Strings,
+    Ref, Slot, PTR_SIZE,
+};
+use crate::ready;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+// Bootstrap methods are called through the interpreter with a lookup on the caller, the name,
+// the method type and the static arguments of the instruction, the target of the call site they
+// return is linked. Native linkers stand in for the bootstrap methods whose java.lang.invoke
+// machinery needs far more runtime support than the VM offers, a linker is registered by the
+// class and name of the bootstrap method it stands for.
+
+const BOOTSTRAP_METHOD_ERROR: &'static str = "java/lang/BootstrapMethodError";
+
+/// the linked target of an invokedynamic instruction
+pub enum CallSite {
+    /// a direct handle to a static method, the arguments are passed through as they are
+    Static(Arc<Class>, Arc<Method>),
+    /// a class spun for a lambda or method reference, the arguments are captured by a new instance
+    Lambda(Arc<Klass>),
+    /// a string concatenation, the arguments are formatted into a new string
+    Concat(Vec<Chunk>),
+}
+
+/// the bootstrap specifier of an invokedynamic instruction
+pub struct Bootstrap<'a> {
+    /// the class containing the instruction, also the lookup class
+    pub caller: &'a Class,
+    pub name: &'a str,
+    pub descriptor: &'a str,
+    /// constant pool indexes of the static arguments
+    pub arguments: &'a [U2],
+}
+
+/// returns `Ok(None)` if linking triggered a class initialization and must be retried,
+/// `Err` carries the name of the error to throw
+pub type Linker = fn(&mut ThreadContext, &Bootstrap) -> Result<Option<CallSite>, String>;
+
+pub struct CallSites {
+    linkers: RwLock<HashMap<(String, String), Linker>>,
+    // keyed by the method and the pc of the instruction
+    sites: RwLock<HashMap<(usize, usize), Arc<CallSite>>>,
+}
+
+static mut CALL_SITES: Option<CallSites> = None;
+
+macro_rules! call_sites {
+    () => {
+        unsafe {
+            match CALL_SITES {
+                Some(ref v) => v,
+                None => panic!("CallSites not initialized"),
+            }
+        }
+    };
+}
+
+impl CallSites {
+    pub fn init() {
+        unsafe {
+            CALL_SITES.replace(CallSites {
+                linkers: RwLock::new(HashMap::new()),
+                sites: RwLock::new(HashMap::new()),
+            });
+        }
+        Self::register(
+            "java/lang/invoke/LambdaMetafactory",
+            "metafactory",
+            lambda::metafactory,
+        );
+        Self::register(
+            "java/lang/invoke/LambdaMetafactory",
+            "altMetafactory",
+            lambda::alt_metafactory,
+        );
+        Self::register(
+            "java/lang/invoke/StringConcatFactory",
+            "makeConcat",
+            concat::make_concat,
+        );
+        Self::register(
+            "java/lang/invoke/StringConcatFactory",
+            "makeConcatWithConstants",
+            concat::make_concat_with_constants,
+        );
+    }
+
+    pub fn register(class: &str, name: &str, linker: Linker) {
+        call_sites!()
+            .linkers
+            .write()
+            .unwrap()
+            .insert((class.to_owned(), name.to_owned()), linker);
+    }
+
+    /// returns the call site of the invokedynamic instruction at `pc` of the current method,
+    /// `idx` is its operand, the bootstrap method is called the first time only
+    pub fn link(
+        context: &mut ThreadContext,
+        pc: usize,
+        idx: U2,
+    ) -> Result<Option<Arc<CallSite>>, String> {
+        let key = (context.stack.method_ptr() as usize, pc);
+        if let Some(site) = call_sites!().sites.read().unwrap().get(&key) {
+            return Ok(Some(Arc::clone(site)));
+        }
+        let caller = unsafe { &*context.stack.class_ptr() };
+        let (bootstrap_idx, (name, descriptor)) = caller.constant_pool.get_invoke_dynamic(idx);
+        let method = caller
+            .get_bootstrap_method(bootstrap_idx)
+            .ok_or(BOOTSTRAP_METHOD_ERROR)?;
+        let (kind, (c, (m, _))) = caller.constant_pool.get_method_handle(method.method_ref);
+        if kind != REF_INVOKE_STATIC && kind != REF_NEW_INVOKE_SPECIAL {
+            return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+        }
+        let linker = call_sites!()
+            .linkers
+            .read()
+            .unwrap()
+            .get(&(c.to_owned(), m.to_owned()))
+            .map(|linker| *linker);
+        let bootstrap = Bootstrap {
+            caller: caller,
+            name: name,
+            descriptor: descriptor,
+            arguments: &method.arguments,
+        };
+        let site = match linker {
+            Some(linker) => linker(context, &bootstrap)?,
+            None => match call_bootstrap(context, &bootstrap, method.method_ref)? {
+                None if context.exception_pending => return wrap_exception(context),
+                site => site,
+            },
+        };
+        Ok(site.map(|site| {
+            // the first linked site wins when threads race
+            let mut sites = call_sites!().sites.write().unwrap();
+            Arc::clone(sites.entry(key).or_insert_with(|| Arc::new(site)))
+        }))
+    }
+}
+
+// calls the bootstrap method `handle` of the caller and links the target of the call site it
+// returns, `Ok(None)` if it must be retried or threw
+fn call_bootstrap(
+    context: &mut ThreadContext,
+    bootstrap: &Bootstrap,
+    handle: U2,
+) -> Result<Option<CallSite>, String> {
+    let caller = bootstrap.caller;
+    let (kind, (c, (m, t))) = caller.constant_pool.get_method_handle(handle);
+    let (klass, initialized) = ClassArena::load_class(c, context)?;
+    if !initialized {
+        return Ok(None);
+    }
+    let (declaring, method) = find_method(&klass, m, t).ok_or(BOOTSTRAP_METHOD_ERROR)?;
+    let constructor = kind == REF_NEW_INVOKE_SPECIAL;
+    if method.is_static() == constructor || (m == "<init>") != constructor {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    let (params, _, _) = bytecode::resolve_method_descriptor(t, METHOD_ACC_STATIC);
+    if params.len() < 3 {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    let lookup = ready!(constants::lookup(context, caller));
+    let name = Strings::get(bootstrap.name, context);
+    let method_type = ready!(constants::method_type(context, bootstrap.descriptor));
+    let mut args = vec![
+        lookup.to_le_bytes(),
+        name.to_le_bytes(),
+        method_type.to_le_bytes(),
+    ];
+    // the trailing static arguments of a variable arity method are collected into an array
+    let params = &params[3..];
+    let fixed = match params.last() {
+        Some(last) if method.is_varargs() && last.starts_with("[L") => params.len() - 1,
+        _ => params.len(),
+    };
+    let count = bootstrap.arguments.len();
+    if count < fixed || fixed == params.len() && count != fixed {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    for (idx, t) in bootstrap.arguments.iter().zip(&params[..fixed]) {
+        args.append(&mut ready!(argument(context, caller, *idx, t)));
+    }
+    if fixed < params.len() {
+        let rest = &bootstrap.arguments[fixed..];
+        let (array_klass, initialized) = ClassArena::load_class(&params[fixed], context)?;
+        if !initialized {
+            return Ok(None);
+        }
+        let mut elements = vec![];
+        for idx in rest {
+            elements.append(&mut ready!(argument(
+                context,
+                caller,
+                *idx,
+                &params[fixed][1..]
+            )));
+        }
+        let array = Heap::allocate_array_directly(&array_klass, rest.len() as u32)
+            .expect("OutOfMemoryError");
+        for (i, element) in elements.iter().enumerate() {
+            let ptr = Heap::ptr(array as usize + OBJ_HEADER_SIZE + i * PTR_SIZE);
+            unsafe { ptr.copy_from(element.as_ptr(), PTR_SIZE) };
+        }
+        args.push(array.to_le_bytes());
+    }
+    let class = Arc::as_ptr(declaring.bytecode.as_ref().unwrap());
+    let site = if constructor {
+        let site = Heap::allocate_object(&klass);
+        args.insert(0, site.to_le_bytes());
+        match super::call(context, class, Arc::as_ptr(&method), &args) {
+            Some(_) => site,
+            None => return Ok(None),
+        }
+    } else {
+        match super::call(context, class, Arc::as_ptr(&method), &args) {
+            Some(site) => Ref::from_le_bytes(site),
+            None => return Ok(None),
+        }
+    };
+    let target = constants::call_site_target(site).ok_or(BOOTSTRAP_METHOD_ERROR)?;
+    if constants::handle_type(target).as_ref().map(|t| t.as_ref()) != Some(bootstrap.descriptor) {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    // any other handle needs the java.lang.invoke machinery to be invoked
+    let (klass, name, descriptor) = match constants::direct_method(target) {
+        Some((REF_INVOKE_STATIC, klass, name, descriptor)) => (klass, name, descriptor),
+        _ => return Err(BOOTSTRAP_METHOD_ERROR.to_owned()),
+    };
+    let (declaring, method) =
+        find_method(&klass, &name, &descriptor).ok_or(BOOTSTRAP_METHOD_ERROR)?;
+    if !method.is_static() {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    let class = Arc::clone(declaring.bytecode.as_ref().unwrap());
+    Ok(Some(CallSite::Static(class, method)))
+}
+
+// the slots of the static argument at `idx` of `class` passed as `t`, primitives passed as
+// references are boxed
+fn argument(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+    t: &str,
+) -> Result<Option<Vec<Slot>>, String> {
+    let (primitive, slots) = match class.constant_pool.get(idx) {
+        ConstantItem::Integer(v) => ("I", vec![v.to_le_bytes()]),
+        ConstantItem::Float(v) => ("F", vec![v.to_le_bytes()]),
+        ConstantItem::Long(v) => ("J", wide(v.to_le_bytes())),
+        ConstantItem::Double(v) => ("D", wide(v.to_le_bytes())),
+        _ => ("", vec![]),
+    };
+    if primitive == t {
+        return Ok(Some(slots));
+    }
+    if !t.starts_with("L") {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    let obj = if primitive.is_empty() {
+        ready!(constants::resolve(context, class, idx))
+    } else {
+        let (wrapper, _) = lambda::boxing(primitive);
+        let (klass, initialized) = ClassArena::load_class(wrapper, context)?;
+        if !initialized {
+            return Ok(None);
+        }
+        let wrapper_class = klass.bytecode.as_ref().unwrap();
+        let value_of = wrapper_class
+            .get_method("valueOf", &format!("({})L{};", primitive, wrapper))
+            .ok_or("java/lang/NoSuchMethodError")?;
+        let class = Arc::as_ptr(wrapper_class);
+        match super::call(context, class, Arc::as_ptr(&value_of), &slots) {
+            Some(obj) => Ref::from_le_bytes(obj),
+            None => return Ok(None),
+        }
+    };
+    let klass = unsafe { &*Heap::as_obj(obj).klass };
+    if !klass.is_subtype_of(&t[1..t.len() - 1]) {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    Ok(Some(vec![obj.to_le_bytes()]))
+}
+
+fn wide(v: [u8; 8]) -> Vec<Slot> {
+    vec![[v[0], v[1], v[2], v[3]], [v[4], v[5], v[6], v[7]]]
+}
+
+// the exception the bootstrap method threw is wrapped unless it is an error
+fn wrap_exception(context: &mut ThreadContext) -> Result<Option<Arc<CallSite>>, String> {
+    let exception = Ref::from_le_bytes(*context.stack.top());
+    if unsafe { &*Heap::as_obj(exception).klass }.is_subtype_of("java/lang/Error") {
+        return Ok(None);
+    }
+    context.stack.pop();
+    context.exception_pending = false;
+    Err(BOOTSTRAP_METHOD_ERROR.to_owned())
+}
+
+/// resolves a `REF_invokeStatic` method handle constant of `class`
+pub fn resolve_static_handle(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+) -> Result<Option<CallSite>, String> {
+    let (kind, (c, (m, t))) = class.constant_pool.get_method_handle(idx);
+    if kind != REF_INVOKE_STATIC {
+        return Err("java/lang/IncompatibleClassChangeError".to_owned());
+    }
+    let (klass, initialized) = ClassArena::load_class(c, context)?;
+    if !initialized {
+        return Ok(None);
+    }
+    let method = klass
+        .bytecode
+        .as_ref()
+        .unwrap()
+        .get_method(m, t)
+        .ok_or("java/lang/NoSuchMethodError")?;
+    if !method.is_static() {
+        return Err("java/lang/IncompatibleClassChangeError".to_owned());
+    }
+    let class = Arc::clone(klass.bytecode.as_ref().unwrap());
+    Ok(Some(CallSite::Static(class, method)))
+}
+
+#[cfg(test)]
+pub mod test {
+
+    use super::super::constants::test::define_invoke_classes;
+    use super::super::test::{define_exceptions, exception, run_method};
+    use super::*;
+    use crate::mem::{
+        heap::test::reset_heap,
+        klass::test::define_object,
+        metaspace::test::define_class,
+        strings::test::{define_string, init_strings},
+        Value,
+    };
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Once,
+    };
+
+    // assembled by hand, javac doesn't emit invokedynamic for arbitrary bootstrap methods
+    //
+    // class Indy {
+    //     static int twice(int x) { return x * 2; }
+    //     // invokedynamic apply:(I)I, linked by Indy.bootstrap with Indy::twice
+    //     static int run(int x) { return apply(x); }
+    //     // invokedynamic apply:(I)I, linked by Indy.missing which is neither registered nor
+    //     // declared
+    //     static int unlinked(int x) { return apply(x); }
+    // }
+    const INDY: &'static str = "yv66vgAAADQAHQEABEluZHkHAAEBABBqYXZhL2xhbmcvT2JqZWN0BwADAQAEKEkpSQEABXR3aWNlDAAGAAUKAAIABw8GAAgBAAlib290c3RyYXABAJIoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7TGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTtMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGU7KUxqYXZhL2xhbmcvaW52b2tlL0NhbGxTaXRlOwwACgALCgACAAwPBgANAQAFYXBwbHkMAA8ABRIAAAAQAQAHbWlzc2luZwEAcyhMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGVzJExvb2t1cDtMamF2YS9sYW5nL1N0cmluZztMamF2YS9sYW5nL2ludm9rZS9NZXRob2RUeXBlOylMamF2YS9sYW5nL2ludm9rZS9DYWxsU2l0ZTsMABIAEwoAAgAUDwYAFRIAAQAQAQAEQ29kZQEAEEJvb3RzdHJhcE1ldGhvZHMBAAV0d2ljZQEAA3J1bgEACHVubGlua2VkACAAAgAEAAAAAAADAAgAGgAFAAEAGAAAABAAAgABAAAABBoFaKwAAAAAAAgAGwAFAAEAGAAAABMAAQABAAAABxq6ABEAAKwAAAAAAAgAHAAFAAEAGAAAABMAAQABAAAABxq6ABcAAKwAAAAAAAEAGQAAAAwAAgAOAAEACQAWAAA=";
+
+    // assembled by hand like `Indy`, the bootstrap methods run as bytecode
+    //
+    // class Bootstraps {
+    //     static MethodHandles.Lookup lookup;
+    //     static String name;
+    //     static int factor;
+    //     static Object[] extra;
+    //     static int twice(int x) { return x * 2; }
+    //     static int scale(int x) { return x * factor; }
+    //     static CallSite link(MethodHandles.Lookup lookup, String name, MethodType type,
+    //             MethodHandle target, int factor) {
+    //         Bootstraps.lookup = lookup;
+    //         Bootstraps.name = name;
+    //         Bootstraps.factor = factor;
+    //         return new ConstantCallSite(target);
+    //     }
+    //     static CallSite spread(MethodHandles.Lookup lookup, String name, MethodType type,
+    //             Object... extra) {
+    //         Bootstraps.extra = extra;
+    //         return new ConstantCallSite((MethodHandle) extra[0]);
+    //     }
+    //     static CallSite fail(MethodHandles.Lookup lookup, String name, MethodType type) {
+    //         throw new NullPointerException();
+    //     }
+    //     // invokedynamic apply:(I)I, linked by Bootstraps.link with Bootstraps::scale and 3
+    //     static int linked(int x) { return apply(x); }
+    //     // invokedynamic apply:(I)I, linked by Bootstraps.spread with Bootstraps::twice, 5L and
+    //     // "text"
+    //     static int spread(int x) { return apply(x); }
+    //     // invokedynamic apply:(I)I, linked by Bootstraps.fail
+    //     static int failed(int x) { return apply(x); }
+    // }
+    const BOOTSTRAPS: &'static str = "yv66vgAAADQARQEACkJvb3RzdHJhcHMHAAEBABBqYXZhL2xhbmcvT2JqZWN0BwADAQAGbG9va3VwAQAnTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7AQAEbmFtZQEAEkxqYXZhL2xhbmcvU3RyaW5nOwEABmZhY3RvcgEAAUkBAAVleHRyYQEAE1tMamF2YS9sYW5nL09iamVjdDsBAAV0d2ljZQEABChJKUkBAARDb2RlDAAJAAoJAAIAEAEABXNjYWxlDAAFAAYJAAIAEwwABwAICQACABUBACFqYXZhL2xhbmcvaW52b2tlL0NvbnN0YW50Q2FsbFNpdGUHABcBAAY8aW5pdD4BACIoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlOylWDAAZABoKABgAGwEABGxpbmsBAJMoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7TGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTtMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGU7SSlMamF2YS9sYW5nL2ludm9rZS9DYWxsU2l0ZTsMAAsADAkAAgAfAQAdamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGUHACEBAAZzcHJlYWQBAIYoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7TGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTtbTGphdmEvbGFuZy9PYmplY3Q7KUxqYXZhL2xhbmcvaW52b2tlL0NhbGxTaXRlOwEAHmphdmEvbGFuZy9OdWxsUG9pbnRlckV4Y2VwdGlvbgcAJQEABGZhaWwBAHMoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7TGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTspTGphdmEvbGFuZy9pbnZva2UvQ2FsbFNpdGU7DAAdAB4KAAIAKQ8GACoMABIADgoAAgAsDwYALQMAAAADDAAjACQKAAIAMA8GADEMAA0ADgoAAgAzDwYANAUAAAAAAAAABQEABHRleHQIADgMACcAKAoAAgA6DwYAOwEABWFwcGx5DAA9AA4SAAAAPgEABmxpbmtlZBIAAQA+EgACAD4BAAZmYWlsZWQBABBCb290c3RyYXBNZXRob2RzACEAAgAEAAAABAAIAAUABgAAAAgABwAIAAAACAAJAAoAAAAIAAsADAAAAAgACAANAA4AAQAPAAAAEAACAAEAAAAEGgVorAAAAAAACAASAA4AAQAPAAAAEgACAAEAAAAGGrIAEWisAAAAAAAIAB0AHgABAA8AAAAiAAMABQAAABYqswAUK7MAFhUEswARuwAYWS23ABywAAAAAACIACMAJAABAA8AAAAeAAQABAAAABItswAguwAYWS0DMsAAIrcAHLAAAAAAAAgAJwAoAAEADwAAABAAAQADAAAABLsAJr8AAAAAAAgAQAAOAAEADwAAABMAAQABAAAABxq6AD8AAKwAAAAAAAgAIwAOAAEADwAAABMAAQABAAAABxq6AEEAAKwAAAAAAAgAQwAOAAEADwAAABMAAQABAAAABxq6AEIAAKwAAAAAAAEARAAAABgAAwArAAIALgAvADIAAwA1ADYAOQA8AAA=";
+
+    // class ConstantCallSite extends CallSite {
+    //     ConstantCallSite(MethodHandle target) { this.target = target; }
+    // }
+    const CONSTANT_CALL_SITE: &'static str = "yv66vgAAADQADAEAIWphdmEvbGFuZy9pbnZva2UvQ29uc3RhbnRDYWxsU2l0ZQcAAQEAGWphdmEvbGFuZy9pbnZva2UvQ2FsbFNpdGUHAAMBAAZ0YXJnZXQBAB9MamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGU7DAAFAAYJAAQABwEABjxpbml0PgEAIihMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGU7KVYBAARDb2RlACEAAgAEAAAAAAABAAEACQAKAAEACwAAABIAAgACAAAABiortQAIsQAAAAAAAA==";
+
+    // class Long {
+    //     final long value;
+    //     static Long valueOf(long l) { return new Long(l); }
+    // }
+    const LONG: &'static str = "yv66vgAAADQADAEADmphdmEvbGFuZy9Mb25nBwABAQAQamF2YS9sYW5nL09iamVjdAcAAwEABXZhbHVlAQABSgwABQAGCQACAAcBAAd2YWx1ZU9mAQATKEopTGphdmEvbGFuZy9Mb25nOwEABENvZGUAIQACAAQAAAABABIABQAGAAAAAQAJAAkACgABAAsAAAAVAAQAAgAAAAm7AAJZHrUACLAAAAAAAAA=";
+
+    static INIT: Once = Once::new();
+
+    /// the call sites shared by all tests, with the bootstrap method of `Indy` registered
+    pub fn init_call_sites() {
+        INIT.call_once(|| {
+            CallSites::init();
+            CallSites::register("Indy", "bootstrap", bootstrap);
+        });
+    }
+
+    static LINKED: AtomicUsize = AtomicUsize::new(0);
+
+    fn bootstrap(
+        context: &mut ThreadContext,
+        bootstrap: &Bootstrap,
+    ) -> Result<Option<CallSite>, String> {
+        assert_eq!(("apply", "(I)I"), (bootstrap.name, bootstrap.descriptor));
+        LINKED.fetch_add(1, Ordering::SeqCst);
+        resolve_static_handle(context, bootstrap.caller, bootstrap.arguments[0])
+    }
+
+    fn call(klass: &Klass, name: &str, arg: i32) -> Result<i32, String> {
+        let class = klass.bytecode.as_ref().unwrap();
+        let method = class.get_method(name, "(I)I").unwrap();
+        run_method(class, &method, &arg.to_le_bytes(), |ctx| {
+            match exception(ctx) {
+                Some(e) => Err(e),
+                None => Ok(i32::from_le_bytes(ctx.stack.pop())),
+            }
+        })
+    }
+
+    #[test]
+    pub fn test_bootstrap_methods() {
+        let class = Class::from_vec(base64::decode(INDY).unwrap()).unwrap();
+        let bootstrap = class.get_bootstrap_method(0).unwrap();
+        assert_eq!(
+            (REF_INVOKE_STATIC, ("Indy", ("bootstrap", "(Ljava/lang/invoke/MethodHandles$Lookup;Ljava/lang/String;Ljava/lang/invoke/MethodType;Ljava/lang/invoke/MethodHandle;)Ljava/lang/invoke/CallSite;"))),
+            class.constant_pool.get_method_handle(bootstrap.method_ref)
+        );
+        assert_eq!(1, bootstrap.arguments.len());
+        assert_eq!(
+            (REF_INVOKE_STATIC, ("Indy", ("twice", "(I)I"))),
+            class
+                .constant_pool
+                .get_method_handle(bootstrap.arguments[0])
+        );
+        assert!(class.get_bootstrap_method(1).unwrap().arguments.is_empty());
+        assert!(class.get_bootstrap_method(2).is_none());
+    }
+
+    #[test]
+    pub fn test_invokedynamic() {
+        let _heap = reset_heap();
+        define_exceptions();
+        init_call_sites();
+        let class = Arc::new(Class::from_vec(base64::decode(INDY).unwrap()).unwrap());
+        let klass = define_class(Klass::new(class, 0, None, vec![]));
+        assert_eq!(Ok(42), call(&klass, "run", 21));
+        assert_eq!(Ok(-6), call(&klass, "run", -3));
+        // linked once, then the call site is reused
+        assert_eq!(1, LINKED.load(Ordering::SeqCst));
+        let error = Err("java/lang/BootstrapMethodError".to_owned());
+        assert_eq!(error, call(&klass, "unlinked", 1));
+        assert_eq!(error, call(&klass, "unlinked", 1));
+    }
+
+    fn define(bytecode: &str, superclass: &Arc<Klass>) -> Arc<Klass> {
+        let class = Class::from_vec(base64::decode(bytecode).unwrap()).unwrap();
+        define_class(Klass::new(
+            Arc::new(class),
+            0,
+            Some(Arc::clone(superclass)),
+            vec![],
+        ))
+    }
+
+    fn static_ref(klass: &Klass, name: &str, descriptor: &str) -> Ref {
+        let class = klass.bytecode.as_ref().unwrap();
+        let value = class.get_field(name, descriptor).unwrap().value.get();
+        Ref::from_le_bytes(Value::of(value.unwrap()))
+    }
+
+    fn field(obj: Ref, class: &str, name: &str, descriptor: &str) -> Ref {
+        let klass = unsafe { &*Heap::as_obj(obj).klass };
+        let (offset, _) = klass.layout.get(&(class, name, descriptor)).unwrap();
+        let field = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + offset);
+        Ref::from_le_bytes(unsafe { *field.cast::<[u8; PTR_SIZE]>() })
+    }
+
+    fn element(array: Ref, i: usize) -> Ref {
+        let element = Heap::ptr(array as usize + OBJ_HEADER_SIZE + i * PTR_SIZE);
+        Ref::from_le_bytes(unsafe { *element.cast::<[u8; PTR_SIZE]>() })
+    }
+
+    #[test]
+    pub fn test_call_bootstrap_methods() {
+        let _heap = reset_heap();
+        define_exceptions();
+        define_string();
+        init_strings();
+        define_invoke_classes();
+        init_call_sites();
+        let object = define_object();
+        let call_site = ClassArena::loaded()
+            .into_iter()
+            .find(|klass| klass.name == "java/lang/invoke/CallSite")
+            .unwrap();
+        define(CONSTANT_CALL_SITE, &call_site);
+        define(LONG, &object);
+        let klass = define(BOOTSTRAPS, &object);
+        assert_eq!(Ok(63), call(&klass, "linked", 21));
+        assert_eq!(Ok(-9), call(&klass, "linked", -3));
+        let lookup = static_ref(&klass, "lookup", "Ljava/lang/invoke/MethodHandles$Lookup;");
+        let lookup_class = "java/lang/invoke/MethodHandles$Lookup";
+        assert_eq!(
+            klass.mirror.load(std::sync::atomic::Ordering::Acquire),
+            field(lookup, lookup_class, "lookupClass", "Ljava/lang/Class;")
+        );
+        assert_eq!(0x0f, field(lookup, lookup_class, "allowedModes", "I"));
+        let name = static_ref(&klass, "name", "Ljava/lang/String;");
+        assert_eq!(
+            "apply",
+            String::from_utf16(&Strings::content(name)).unwrap()
+        );
+
+        // the static arguments of a variable arity method are boxed into an array
+        assert_eq!(Ok(42), call(&klass, "spread", 21));
+        let extra = static_ref(&klass, "extra", "[Ljava/lang/Object;");
+        assert_eq!(Some(3), Heap::as_obj(extra).size);
+        let handle = element(extra, 0);
+        assert_eq!(Some("(I)I".to_owned()), constants::handle_type(handle));
+        let boxed = element(extra, 1);
+        assert_eq!(5, field(boxed, "java/lang/Long", "value", "J"));
+        let text = String::from_utf16(&Strings::content(element(extra, 2))).unwrap();
+        assert_eq!("text", text);
+
+        // the exception is wrapped and nothing is linked
+        let error = Err("java/lang/BootstrapMethodError".to_owned());
+        assert_eq!(error, call(&klass, "failed", 1));
+        assert_eq!(error, call(&klass, "failed", 1));
+    }
+}
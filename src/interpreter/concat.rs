@@ -0,0 +1,369 @@
+use super::callsite::{Bootstrap, CallSite};
+use super::thread::ThreadContext;
+use crate::bytecode::{self, constant_pool::ConstantItem, METHOD_ACC_STATIC};
+use crate::mem::{heap::Heap, klass::OBJ_HEADER_SIZE, strings::Strings, Ref, PTR_SIZE};
+use std::mem;
+
+// String concatenation of javac 9 and later is linked from the recipe instead of running the
+// strategies of `StringConcatFactory`, every argument is formatted like `String.valueOf` does.
+
+const TAG_ARG: char = '\u{1}';
+
+const TAG_CONST: char = '\u{2}';
+
+const BOOTSTRAP_METHOD_ERROR: &'static str = "java/lang/BootstrapMethodError";
+
+/// a piece of the concatenated string
+pub enum Chunk {
+    /// UTF-16 text known at link time
+    Constant(Vec<u16>),
+    /// an argument of the descriptor
+    Argument(String),
+}
+
+/// linker of `StringConcatFactory.makeConcat`, the arguments are concatenated one by one
+pub fn make_concat(
+    _context: &mut ThreadContext,
+    bootstrap: &Bootstrap,
+) -> Result<Option<CallSite>, String> {
+    let chunks = arguments(bootstrap)?
+        .into_iter()
+        .map(|t| Chunk::Argument(t))
+        .collect();
+    Ok(Some(CallSite::Concat(chunks)))
+}
+
+/// linker of `StringConcatFactory.makeConcatWithConstants`, `\1` of the recipe stands for the
+/// next argument and `\2` for the next of the remaining static arguments
+pub fn make_concat_with_constants(
+    _context: &mut ThreadContext,
+    bootstrap: &Bootstrap,
+) -> Result<Option<CallSite>, String> {
+    let pool = &bootstrap.caller.constant_pool;
+    let recipe = match bootstrap.arguments.first() {
+        Some(idx) => pool.get_str(*idx),
+        None => return Err(BOOTSTRAP_METHOD_ERROR.to_owned()),
+    };
+    let mut arguments = arguments(bootstrap)?.into_iter();
+    let mut constants = bootstrap.arguments[1..].iter();
+    let mut chunks = vec![];
+    let mut text = vec![];
+    for ch in recipe.chars() {
+        match ch {
+            TAG_ARG => {
+                if !text.is_empty() {
+                    chunks.push(Chunk::Constant(mem::take(&mut text)));
+                }
+                let t = arguments.next().ok_or(BOOTSTRAP_METHOD_ERROR)?;
+                chunks.push(Chunk::Argument(t));
+            }
+            TAG_CONST => {
+                let idx = *constants.next().ok_or(BOOTSTRAP_METHOD_ERROR)?;
+                let constant = match pool.get(idx) {
+                    ConstantItem::Integer(i) => i.to_string(),
+                    ConstantItem::Long(l) => l.to_string(),
+                    ConstantItem::Float(f) => floating(&format!("{:e}", f)),
+                    ConstantItem::Double(d) => floating(&format!("{:e}", d)),
+                    ConstantItem::String(_) => pool.get_str(idx).to_owned(),
+                    _ => return Err(BOOTSTRAP_METHOD_ERROR.to_owned()),
+                };
+                text.extend(constant.encode_utf16());
+            }
+            _ => text.extend(ch.encode_utf16(&mut [0; 2]).iter()),
+        }
+    }
+    if !text.is_empty() {
+        chunks.push(Chunk::Constant(text));
+    }
+    if arguments.next().is_some() {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    Ok(Some(CallSite::Concat(chunks)))
+}
+
+// the dynamic arguments, the call site must return a string
+fn arguments(bootstrap: &Bootstrap) -> Result<Vec<String>, String> {
+    let (params, _, ret) =
+        bytecode::resolve_method_descriptor(bootstrap.descriptor, METHOD_ACC_STATIC);
+    if ret != "Ljava/lang/String;" {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    Ok(params)
+}
+
+/// pops the arguments and returns the string of `chunks`, `None` if the heap is exhausted or
+/// `toString` of an argument threw, the arguments are left as they are
+pub fn concat(context: &mut ThreadContext, chunks: &[Chunk]) -> Option<Ref> {
+    let width = |t: &str| match t {
+        "D" | "J" => 2,
+        _ => 1,
+    };
+    let slots = chunks
+        .iter()
+        .map(|chunk| match chunk {
+            Chunk::Argument(t) => width(t),
+            Chunk::Constant(_) => 0,
+        })
+        .sum::<usize>();
+    let mut content = vec![];
+    let mut argument = unsafe { context.stack.operands().sub(slots * PTR_SIZE) };
+    for chunk in chunks {
+        match chunk {
+            Chunk::Constant(text) => content.extend_from_slice(text),
+            Chunk::Argument(t) => unsafe {
+                format(context, t, argument, &mut content)?;
+                argument = argument.add(width(t) * PTR_SIZE);
+            },
+        }
+    }
+    let string = Strings::new_string(&content, context)?;
+    context.stack.downward(slots);
+    Some(string)
+}
+
+// appends the value of type `t` at `value`, `None` if `toString` threw
+unsafe fn format(
+    context: &mut ThreadContext,
+    t: &str,
+    value: *const u8,
+    content: &mut Vec<u16>,
+) -> Option<()> {
+    let word = i32::from_le_bytes(*value.cast::<[u8; 4]>());
+    let text = match t {
+        "Z" => (word != 0).to_string(),
+        "C" => {
+            content.push(word as u16);
+            return Some(());
+        }
+        "B" | "S" | "I" => word.to_string(),
+        "J" => i64::from_le_bytes(*value.cast::<[u8; 8]>()).to_string(),
+        "F" => floating(&format!("{:e}", f32::from_le_bytes(word.to_le_bytes()))),
+        "D" => floating(&format!(
+            "{:e}",
+            f64::from_le_bytes(*value.cast::<[u8; 8]>())
+        )),
+        _ => return format_object(context, word as Ref, content),
+    };
+    content.extend(text.encode_utf16());
+    Some(())
+}
+
+// strings and boxed primitives print their value, an overridden `toString` is called and
+// `Object.toString` is done here since the natives it calls aren't implemented
+fn format_object(context: &mut ThreadContext, obj: Ref, content: &mut Vec<u16>) -> Option<()> {
+    if obj == 0 {
+        content.extend("null".encode_utf16());
+        return Some(());
+    }
+    let klass = unsafe { &*Heap::as_obj(obj).klass };
+    let primitive = match klass.name.as_ref() {
+        "java/lang/String" => {
+            content.extend(Strings::content(obj));
+            return Some(());
+        }
+        "java/lang/Boolean" => Some("Z"),
+        "java/lang/Byte" => Some("B"),
+        "java/lang/Character" => Some("C"),
+        "java/lang/Short" => Some("S"),
+        "java/lang/Integer" => Some("I"),
+        "java/lang/Long" => Some("J"),
+        "java/lang/Float" => Some("F"),
+        "java/lang/Double" => Some("D"),
+        _ => None,
+    };
+    if let Some(t) = primitive {
+        if let Some((offset, _)) = klass.layout.get(&(klass.name.as_ref(), "value", t)) {
+            let value = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + offset);
+            return unsafe { format(context, t, value, content) };
+        }
+    }
+    if let Some((class, method)) = klass.get_method_in_vtable("toString", "()Ljava/lang/String;") {
+        if unsafe { &**class }.get_name() != "java/lang/Object" {
+            let string = super::call(context, *class, *method, &[obj.to_le_bytes()])?;
+            return format_object(context, Ref::from_le_bytes(string), content);
+        }
+    }
+    let text = format!("{}@{:x}", klass.name.replace("/", "."), obj);
+    content.extend(text.encode_utf16());
+    Some(())
+}
+
+// `Float.toString` and `Double.toString` of the shortest digits in scientific notation, which
+// Rust prints the same way for both
+fn floating(scientific: &str) -> String {
+    match scientific {
+        "NaN" => return "NaN".to_owned(),
+        "inf" => return "Infinity".to_owned(),
+        "-inf" => return "-Infinity".to_owned(),
+        _ => {}
+    }
+    let (mantissa, exponent) = scientific.split_at(scientific.find('e').unwrap());
+    let exponent = exponent[1..].parse::<i32>().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace(".", "");
+    if exponent < -3 || exponent >= 7 {
+        let fraction = if digits.len() > 1 { &digits[1..] } else { "0" };
+        format!("{}{}.{}E{}", sign, &digits[..1], fraction, exponent)
+    } else if exponent < 0 {
+        let zeros = "0".repeat((-exponent - 1) as usize);
+        format!("{}0.{}{}", sign, zeros, digits)
+    } else {
+        // at least one digit after the point
+        let point = exponent as usize + 1;
+        let digits = format!("{:0<1$}", digits, point + 1);
+        format!("{}{}.{}", sign, &digits[..point], &digits[point..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::super::callsite::test::init_call_sites;
+    use super::super::test::{define_exceptions, exception, run_method};
+    use super::*;
+    use crate::bytecode::class::Class;
+    use crate::mem::{
+        heap::test::reset_heap,
+        klass::{test::define_object, Klass},
+        metaspace::test::define_class,
+        strings::test::define_string,
+    };
+    use std::sync::{mpsc::channel, Arc};
+
+    // javac --release 11 -g:none
+    //
+    // class Concat {
+    //     static String mixed(int i, long l, char c, boolean z, byte b, short s, float f, double d) {
+    //         return "i=" + i + ", l=" + l + ", c=" + c + ", z=" + z + ", b=" + b + ", s=" + s + ", f=" + f + ", d=" + d;
+    //     }
+    //     static String strings(String s, String t) { return s + t + "\u0001\u0002"; }
+    // }
+    const CONCAT: &'static str = "yv66vgAAADcAJgoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWEgAAAAgMAAkACgEAF21ha2VDb25jYXRXaXRoQ29uc3RhbnRzAQAcKElKQ1pCU0ZEKUxqYXZhL2xhbmcvU3RyaW5nOxIAAQAMDAAJAA0BADgoTGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9TdHJpbmc7KUxqYXZhL2xhbmcvU3RyaW5nOwcADwEABkNvbmNhdAEABENvZGUBAAVtaXhlZAEAB3N0cmluZ3MBABBCb290c3RyYXBNZXRob2RzDwYAFQoAFgAXBwAYDAAJABkBACRqYXZhL2xhbmcvaW52b2tlL1N0cmluZ0NvbmNhdEZhY3RvcnkBAJgoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7TGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTtMamF2YS9sYW5nL1N0cmluZztbTGphdmEvbGFuZy9PYmplY3Q7KUxqYXZhL2xhbmcvaW52b2tlL0NhbGxTaXRlOwgAGwEAJmk9ASwgbD0BLCBjPQEsIHo9ASwgYj0BLCBzPQEsIGY9ASwgZD0BCAAdAQADAQECCAAfAQACAQIBAAxJbm5lckNsYXNzZXMHACIBACVqYXZhL2xhbmcvaW52b2tlL01ldGhvZEhhbmRsZXMkTG9va3VwBwAkAQAeamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGVzAQAGTG9va3VwACAADgACAAAAAAADAAAABQAGAAEAEAAAABEAAQABAAAABSq3AAGxAAAAAAAIABEACgABABAAAAAfAAoACgAAABMaHx0VBBUFFQYXBxgIugAHAACwAAAAAAAIABIADQABABAAAAAUAAIAAgAAAAgqK7oACwAAsAAAAAAAAgATAAAAEAACABQAAQAaABQAAgAcAB4AIAAAAAoAAQAhACMAJQAZ";
+
+    // javac --release 11 -g:none -XDstringConcat=indy
+    //
+    // class Bare {
+    //     static String plain(String a, int b) { return a + b; }
+    // }
+    const BARE: &'static str = "yv66vgAAADcAHAoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWEgAAAAgMAAkACgEACm1ha2VDb25jYXQBACcoTGphdmEvbGFuZy9TdHJpbmc7SSlMamF2YS9sYW5nL1N0cmluZzsHAAwBAARCYXJlAQAEQ29kZQEABXBsYWluAQAQQm9vdHN0cmFwTWV0aG9kcw8GABEKABIAEwcAFAwACQAVAQAkamF2YS9sYW5nL2ludm9rZS9TdHJpbmdDb25jYXRGYWN0b3J5AQBzKExqYXZhL2xhbmcvaW52b2tlL01ldGhvZEhhbmRsZXMkTG9va3VwO0xqYXZhL2xhbmcvU3RyaW5nO0xqYXZhL2xhbmcvaW52b2tlL01ldGhvZFR5cGU7KUxqYXZhL2xhbmcvaW52b2tlL0NhbGxTaXRlOwEADElubmVyQ2xhc3NlcwcAGAEAJWphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXAHABoBAB5qYXZhL2xhbmcvaW52b2tlL01ldGhvZEhhbmRsZXMBAAZMb29rdXAAIAALAAIAAAAAAAIAAAAFAAYAAQANAAAAEQABAAEAAAAFKrcAAbEAAAAAAAgADgAKAAEADQAAABQAAgACAAAACCobugAHAACwAAAAAAACAA8AAAAGAAEAEAAAABYAAAAKAAEAFwAZABsAGQ==";
+
+    // javac --release 11 -g:none
+    //
+    // class Shown {
+    //     int x;
+    //     int[] values;
+    //     public String toString() { return x < 0 ? "" + values[0] : "Shown" + x; }
+    // }
+    const SHOWN: &'static str = "yv66vgAAADcAOAoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWCQAIAAkHAAoMAAsADAEABVNob3duAQABeAEAAUkJAAgADgwADwAQAQAGdmFsdWVzAQACW0kSAAAAEgwAEwAUAQAXbWFrZUNvbmNhdFdpdGhDb25zdGFudHMBABUoSSlMamF2YS9sYW5nL1N0cmluZzsSAAEAEgoAFwAYBwAZDAAaABsBABBqYXZhL2xhbmcvU3RyaW5nAQAHdmFsdWVPZgEAJihMamF2YS9sYW5nL09iamVjdDspTGphdmEvbGFuZy9TdHJpbmc7EgACAB0MABMAHgEAJihMamF2YS9sYW5nL1N0cmluZzspTGphdmEvbGFuZy9TdHJpbmc7AQAEQ29kZQEACHRvU3RyaW5nAQAUKClMamF2YS9sYW5nL1N0cmluZzsBAA1TdGFja01hcFRhYmxlAQAEc2hvdwEAGyhMU2hvd247KUxqYXZhL2xhbmcvU3RyaW5nOwEAEEJvb3RzdHJhcE1ldGhvZHMPBgAnCgAoACkHACoMABMAKwEAJGphdmEvbGFuZy9pbnZva2UvU3RyaW5nQ29uY2F0RmFjdG9yeQEAmChMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGVzJExvb2t1cDtMamF2YS9sYW5nL1N0cmluZztMamF2YS9sYW5nL2ludm9rZS9NZXRob2RUeXBlO0xqYXZhL2xhbmcvU3RyaW5nO1tMamF2YS9sYW5nL09iamVjdDspTGphdmEvbGFuZy9pbnZva2UvQ2FsbFNpdGU7CAAtAQABAQgALwEABlNob3duAQgAMQEAA1sBXQEADElubmVyQ2xhc3NlcwcANAEAJWphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXAHADYBAB5qYXZhL2xhbmcvaW52b2tlL01ldGhvZEhhbmRsZXMBAAZMb29rdXAAIAAIAAIAAAACAAAACwAMAAAAAAAPABAAAAADAAAABQAGAAEAHwAAABEAAQABAAAABSq3AAGxAAAAAAABACAAIQABAB8AAAA4AAIAAQAAAB8qtAAHnAARKrQADQMuugARAACnAAwqtAAHugAVAACwAAAAAQAiAAAABwACFUgHABcACAAjACQAAQAfAAAAFgABAAEAAAAKKrgAFroAHAAAsAAAAAAAAgAlAAAAFAADACYAAQAsACYAAQAuACYAAQAwADIAAAAKAAEAMwA1ADcAGQ==";
+
+    // assembled by hand, javac 17 and later pass objects to the concatenation as strings
+    //
+    // class Show {
+    //     // invokedynamic makeConcatWithConstants:(Ljava/lang/Object;)Ljava/lang/String;
+    //     // with the recipe "[\1]"
+    //     static String show(Object o) { return "[" + o + "]"; }
+    // }
+    const SHOW: &'static str = "yv66vgAAADQAFAEABFNob3cHAAEBABBqYXZhL2xhbmcvT2JqZWN0BwADAQAkamF2YS9sYW5nL2ludm9rZS9TdHJpbmdDb25jYXRGYWN0b3J5BwAFAQAXbWFrZUNvbmNhdFdpdGhDb25zdGFudHMBAJgoTGphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXA7TGphdmEvbGFuZy9TdHJpbmc7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTtMamF2YS9sYW5nL1N0cmluZztbTGphdmEvbGFuZy9PYmplY3Q7KUxqYXZhL2xhbmcvaW52b2tlL0NhbGxTaXRlOwwABwAICgAGAAkPBgAKAQADWwFdCAAMAQAmKExqYXZhL2xhbmcvT2JqZWN0OylMamF2YS9sYW5nL1N0cmluZzsMAAcADhIAAAAPAQAEc2hvdwEABENvZGUBABBCb290c3RyYXBNZXRob2RzACAAAgAEAAAAAAABAAgAEQAOAAEAEgAAABMAAQABAAAAByq6ABAAALAAAAAAAAEAEwAAAAgAAQALAAEADQ==";
+
+    fn call(bytecode: &str, name: &str, locals: &[u8]) -> Result<String, String> {
+        let class = Arc::new(Class::from_vec(base64::decode(bytecode).unwrap()).unwrap());
+        let method = class
+            .methods
+            .iter()
+            .find(|m| m.name == name)
+            .unwrap()
+            .clone();
+        run_method(&class, &method, locals, |ctx| match exception(ctx) {
+            Some(e) => Err(e),
+            None => {
+                let s = Ref::from_le_bytes(ctx.stack.pop());
+                Ok(String::from_utf16(&Strings::content(s)).unwrap())
+            }
+        })
+    }
+
+    fn string(content: &str, context: &mut ThreadContext) -> [u8; PTR_SIZE] {
+        let content = content.encode_utf16().collect::<Vec<_>>();
+        Strings::new_string(&content, context)
+            .unwrap()
+            .to_le_bytes()
+    }
+
+    #[test]
+    pub fn test_concat() {
+        let _heap = reset_heap();
+        define_exceptions();
+        define_string();
+        init_call_sites();
+        let (_, rx) = channel();
+        let mut context = ThreadContext::new(1, 0, rx, channel().0);
+        let locals = [
+            &(-7i32).to_le_bytes()[..],
+            &(1i64 << 40).to_le_bytes(),
+            &('\u{e9}' as i32).to_le_bytes(),
+            &1i32.to_le_bytes(),
+            &(-128i32).to_le_bytes(),
+            &1234i32.to_le_bytes(),
+            &1.5f32.to_le_bytes(),
+            &1e10f64.to_le_bytes(),
+        ]
+        .concat();
+        assert_eq!(
+            Ok(
+                "i=-7, l=1099511627776, c=\u{e9}, z=true, b=-128, s=1234, f=1.5, d=1.0E10"
+                    .to_owned()
+            ),
+            call(CONCAT, "mixed", &locals)
+        );
+        let locals = [string("\u{65e5}", &mut context), [0; PTR_SIZE]].concat();
+        assert_eq!(
+            Ok("\u{65e5}null\u{1}\u{2}".to_owned()),
+            call(CONCAT, "strings", &locals)
+        );
+        let locals = [string("a", &mut context), 42i32.to_le_bytes()].concat();
+        assert_eq!(Ok("a42".to_owned()), call(BARE, "plain", &locals));
+    }
+
+    #[test]
+    pub fn test_to_string() {
+        let _heap = reset_heap();
+        define_exceptions();
+        define_string();
+        init_call_sites();
+        let object = define_object();
+        let class = Class::from_vec(base64::decode(SHOWN).unwrap()).unwrap();
+        let shown = define_class(Klass::new(Arc::new(class), 0, Some(object), vec![]));
+        let obj = Heap::allocate_object(&shown);
+        let (offset, _) = shown.layout.get(&("Shown", "x", "I")).unwrap();
+        let x = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + offset);
+        unsafe { x.copy_from(7i32.to_le_bytes().as_ptr(), PTR_SIZE) };
+        let locals = obj.to_le_bytes();
+        assert_eq!(Ok("[Shown7]".to_owned()), call(SHOW, "show", &locals));
+        // the exception thrown by `toString` is left to the caller
+        unsafe { x.copy_from((-1i32).to_le_bytes().as_ptr(), PTR_SIZE) };
+        let npe = Err("java/lang/NullPointerException".to_owned());
+        assert_eq!(npe, call(SHOW, "show", &locals));
+    }
+
+    #[test]
+    pub fn test_floating() {
+        let float = |f: f32| floating(&format!("{:e}", f));
+        let double = |d: f64| floating(&format!("{:e}", d));
+        assert_eq!("0.0", float(0.0));
+        assert_eq!("-0.0", float(-0.0));
+        assert_eq!("0.1", float(0.1));
+        assert_eq!("100.0", float(100.0));
+        assert_eq!("1234567.0", float(1234567.0));
+        assert_eq!("1.0E7", float(1e7));
+        assert_eq!("0.001", double(0.001));
+        assert_eq!("1.0E-4", double(0.0001));
+        assert_eq!("-1.2345E-5", double(-0.000012345));
+        assert_eq!("3.141592653589793", double(std::f64::consts::PI));
+        assert_eq!("Infinity", double(f64::INFINITY));
+        assert_eq!("-Infinity", float(f32::NEG_INFINITY));
+        assert_eq!("NaN", double(f64::NAN));
+    }
+}
@@ -0,0 +1,682 @@
+use super::thread::ThreadContext;
+use crate::bytecode::{self, atom::*, class::Class, constant_pool::*, METHOD_ACC_STATIC};
+use crate::mem::{
+    heap::Heap,
+    klass::{Klass, OBJ_HEADER_SIZE},
+    metaspace::ClassArena,
+    pool::{self, find_field, find_method},
+    strings::Strings,
+    Ref, PTR_SIZE,
+};
+use crate::ready;
+use std::sync::{atomic::Ordering, Arc};
+
+// Class, method type and method handle constants are built by the VM instead of the upcalls a
+// JDK expects, e.g. `MethodHandleNatives.linkMethodHandleConstant`. Like string constants the
+// objects are allocated in the old generation and their constructors never run, only the fields
+// describing the constant are filled.
+
+// the flags of java.lang.invoke.MemberName
+const IS_METHOD: i32 = 0x00010000;
+
+const IS_CONSTRUCTOR: i32 = 0x00020000;
+
+const IS_FIELD: i32 = 0x00040000;
+
+const REFERENCE_KIND_SHIFT: i32 = 24;
+
+// the access modes of a lookup with full privileges, java.lang.invoke.MethodHandles.Lookup
+const ALL_MODES: i32 = 0x0f;
+
+const INCOMPATIBLE_CLASS_CHANGE_ERROR: &'static str = "java/lang/IncompatibleClassChangeError";
+
+/// resolves the string, class, method type or method handle constant at `idx` of `class` once,
+/// returns `Ok(None)` if resolution triggered a class initialization and must be retried,
+/// `Err` carries the name of the error to throw
+pub fn resolve(context: &mut ThreadContext, class: &Class, idx: U2) -> Result<Option<Ref>, String> {
+    let constants = &class.constant_pool;
+    match constants.get(idx) {
+        ConstantItem::String(_) => pool::resolve_object(context, class, idx, |context| {
+            Ok(Some(Strings::intern(&constants.get_utf16(idx), context)))
+        }),
+        // the mirror is kept by the klass
+        ConstantItem::Class(_) => {
+            let klass = ready!(pool::resolve_class(context, class, idx));
+            mirror(&klass, context)
+        }
+        ConstantItem::MethodType(_) => pool::resolve_object(context, class, idx, |context| {
+            method_type(context, constants.get_method_type(idx))
+        }),
+        ConstantItem::MethodHandle(_, _) => pool::resolve_object(context, class, idx, |context| {
+            method_handle(context, class, idx)
+        }),
+        _ => panic!("invalid class file"),
+    }
+}
+
+/// the `java.lang.Class` instance of `klass`, the same one every time
+pub fn mirror(klass: &Klass, context: &mut ThreadContext) -> Result<Option<Ref>, String> {
+    let mirror = klass.mirror.load(Ordering::Acquire);
+    if mirror != 0 {
+        return Ok(Some(mirror));
+    }
+    let class = ready!(load(context, "java/lang/Class"));
+    let obj = allocate(&class);
+    match klass
+        .mirror
+        .compare_exchange(0, obj, Ordering::AcqRel, Ordering::Acquire)
+    {
+        Ok(_) => Ok(Some(obj)),
+        Err(mirror) => Ok(Some(mirror)),
+    }
+}
+
+/// a `java.lang.invoke.MethodHandles$Lookup` with full privileges on `class`, the one its
+/// bootstrap methods are called with
+pub fn lookup(context: &mut ThreadContext, class: &Class) -> Result<Option<Ref>, String> {
+    let klass = ready!(load(context, class.get_name()));
+    let lookup_class = ready!(mirror(&klass, context));
+    let lookup_klass = ready!(load(context, "java/lang/invoke/MethodHandles$Lookup"));
+    let obj = allocate(&lookup_klass);
+    let set = |name, descriptor, value: Ref| {
+        set_field(
+            &lookup_klass,
+            obj,
+            "java/lang/invoke/MethodHandles$Lookup",
+            name,
+            descriptor,
+            value,
+        )
+    };
+    set("lookupClass", "Ljava/lang/Class;", lookup_class);
+    set("allowedModes", "I", ALL_MODES as Ref);
+    Ok(Some(obj))
+}
+
+/// the reference kind, the class, the name and the descriptor of the method a
+/// `java.lang.invoke.DirectMethodHandle` refers to, `None` for any other handle
+pub fn direct_method(handle: Ref) -> Option<(U1, Arc<Klass>, String, String)> {
+    let member = get_field(
+        handle,
+        "java/lang/invoke/DirectMethodHandle",
+        "member",
+        "Ljava/lang/invoke/MemberName;",
+    )?;
+    let get = |name, descriptor| get_field(member, "java/lang/invoke/MemberName", name, descriptor);
+    let flags = get("flags", "I")? as i32;
+    if flags & (IS_METHOD | IS_CONSTRUCTOR) == 0 {
+        return None;
+    }
+    let clazz = get("clazz", "Ljava/lang/Class;")?;
+    // mirrors are only made by the VM and kept by their klasses
+    let klass = ClassArena::loaded()
+        .into_iter()
+        .find(|klass| klass.mirror.load(Ordering::Acquire) == clazz)?;
+    let name = String::from_utf16(&Strings::content(get("name", "Ljava/lang/String;")?)).ok()?;
+    let descriptor = method_descriptor(get("type", "Ljava/lang/Object;")?)?;
+    Some((
+        (flags >> REFERENCE_KIND_SHIFT) as U1 & 0x0f,
+        klass,
+        name,
+        descriptor,
+    ))
+}
+
+/// the target of a `java.lang.invoke.CallSite`, `None` if `site` isn't one or has none
+pub fn call_site_target(site: Ref) -> Option<Ref> {
+    if site == 0
+        || !unsafe { &*Heap::as_obj(site).klass }.is_subtype_of("java/lang/invoke/CallSite")
+    {
+        return None;
+    }
+    get_field(
+        site,
+        "java/lang/invoke/CallSite",
+        "target",
+        "Ljava/lang/invoke/MethodHandle;",
+    )
+}
+
+/// the descriptor of the type of a `java.lang.invoke.MethodHandle`
+pub fn handle_type(handle: Ref) -> Option<String> {
+    let method_type = get_field(
+        handle,
+        "java/lang/invoke/MethodHandle",
+        "type",
+        "Ljava/lang/invoke/MethodType;",
+    )?;
+    method_descriptor(method_type)
+}
+
+// the descriptor of a `java.lang.invoke.MethodType`, `None` if it was never filled
+fn method_descriptor(method_type: Ref) -> Option<String> {
+    let descriptor = get_field(
+        method_type,
+        "java/lang/invoke/MethodType",
+        "methodDescriptor",
+        "Ljava/lang/String;",
+    )?;
+    String::from_utf16(&Strings::content(descriptor)).ok()
+}
+
+/// a `java.lang.invoke.MethodType` of the classes in `descriptor`
+pub fn method_type(context: &mut ThreadContext, descriptor: &str) -> Result<Option<Ref>, String> {
+    let (params, _, ret) = bytecode::resolve_method_descriptor(descriptor, METHOD_ACC_STATIC);
+    let rtype = ready!(load(context, class_name(&ret)));
+    let rtype = ready!(mirror(&rtype, context));
+    let mut ptypes = vec![];
+    for t in &params {
+        let ptype = ready!(load(context, class_name(t)));
+        ptypes.push(ready!(mirror(&ptype, context)));
+    }
+    let klass = ready!(load(context, "java/lang/invoke/MethodType"));
+    let array_klass = ready!(load(context, "[Ljava/lang/Class;"));
+    let array =
+        Heap::allocate_array_directly(&array_klass, ptypes.len() as u32).expect("OutOfMemoryError");
+    for (i, ptype) in ptypes.iter().enumerate() {
+        let element = Heap::ptr(array as usize + OBJ_HEADER_SIZE + i * PTR_SIZE);
+        unsafe { element.copy_from(ptype.to_le_bytes().as_ptr(), PTR_SIZE) };
+    }
+    let obj = allocate(&klass);
+    let set = |name, descriptor, value: Ref| {
+        set_field(
+            &klass,
+            obj,
+            "java/lang/invoke/MethodType",
+            name,
+            descriptor,
+            value,
+        )
+    };
+    set("rtype", "Ljava/lang/Class;", rtype);
+    set("ptypes", "[Ljava/lang/Class;", array);
+    set(
+        "methodDescriptor",
+        "Ljava/lang/String;",
+        Strings::get(descriptor, context),
+    );
+    Ok(Some(obj))
+}
+
+// a `java.lang.invoke.DirectMethodHandle` of the member the constant at `idx` refers to
+fn method_handle(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+) -> Result<Option<Ref>, String> {
+    let (kind, (c, (name, descriptor))) = class.constant_pool.get_method_handle(idx);
+    let klass = ready!(load(context, c));
+    let receiver = reference(c);
+    let (flags, member_type, handle_type) = match kind {
+        REF_GET_FIELD..=REF_PUT_STATIC => {
+            let (_, field) =
+                find_field(&klass, name, descriptor).ok_or("java/lang/NoSuchFieldError")?;
+            let is_static = kind == REF_GET_STATIC || kind == REF_PUT_STATIC;
+            if field.is_static() != is_static {
+                return Err(INCOMPATIBLE_CLASS_CHANGE_ERROR.to_owned());
+            }
+            let handle_type = match kind {
+                REF_GET_FIELD => format!("({}){}", receiver, descriptor),
+                REF_GET_STATIC => format!("(){}", descriptor),
+                REF_PUT_FIELD => format!("({}{})V", receiver, descriptor),
+                _ => format!("({})V", descriptor),
+            };
+            let field_type = ready!(load(context, class_name(descriptor)));
+            let field_type = ready!(mirror(&field_type, context));
+            (field.access_flag as i32 | IS_FIELD, field_type, handle_type)
+        }
+        REF_INVOKE_VIRTUAL..=REF_INVOKE_INTERFACE => {
+            let (_, method) =
+                find_method(&klass, name, descriptor).ok_or("java/lang/NoSuchMethodError")?;
+            let constructor = kind == REF_NEW_INVOKE_SPECIAL;
+            if method.is_static() != (kind == REF_INVOKE_STATIC)
+                || constructor != (name == "<init>")
+            {
+                return Err(INCOMPATIBLE_CLASS_CHANGE_ERROR.to_owned());
+            }
+            let handle_type = match kind {
+                REF_INVOKE_STATIC => descriptor.to_owned(),
+                // `<init>` returns void
+                REF_NEW_INVOKE_SPECIAL => {
+                    format!("{}{}", &descriptor[..descriptor.len() - 1], receiver)
+                }
+                _ => format!("({}{}", receiver, &descriptor[1..]),
+            };
+            let flags = if constructor {
+                IS_CONSTRUCTOR
+            } else {
+                IS_METHOD
+            };
+            let member_type = ready!(method_type(context, descriptor));
+            (method.access_flag as i32 | flags, member_type, handle_type)
+        }
+        _ => panic!("invalid class file"),
+    };
+    let handle_type = ready!(method_type(context, &handle_type));
+    let clazz = ready!(mirror(&klass, context));
+    let member_klass = ready!(load(context, "java/lang/invoke/MemberName"));
+    let handle_klass = ready!(load(context, "java/lang/invoke/DirectMethodHandle"));
+    let member = allocate(&member_klass);
+    let set = |name, descriptor, value: Ref| {
+        set_field(
+            &member_klass,
+            member,
+            "java/lang/invoke/MemberName",
+            name,
+            descriptor,
+            value,
+        )
+    };
+    set("clazz", "Ljava/lang/Class;", clazz);
+    set("name", "Ljava/lang/String;", Strings::get(name, context));
+    set("type", "Ljava/lang/Object;", member_type);
+    set(
+        "flags",
+        "I",
+        (flags | (kind as i32) << REFERENCE_KIND_SHIFT) as Ref,
+    );
+    let handle = allocate(&handle_klass);
+    set_field(
+        &handle_klass,
+        handle,
+        "java/lang/invoke/MethodHandle",
+        "type",
+        "Ljava/lang/invoke/MethodType;",
+        handle_type,
+    );
+    set_field(
+        &handle_klass,
+        handle,
+        "java/lang/invoke/DirectMethodHandle",
+        "member",
+        "Ljava/lang/invoke/MemberName;",
+        member,
+    );
+    Ok(Some(handle))
+}
+
+fn load(context: &mut ThreadContext, name: &str) -> Result<Option<Arc<Klass>>, String> {
+    match ClassArena::load_class(name, context) {
+        Ok((klass, true)) => Ok(Some(klass)),
+        Ok(_) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+// an object of `klass` in the old generation with all fields zeroed
+fn allocate(klass: &Arc<Klass>) -> Ref {
+    let obj = Heap::allocate_object_directly(klass);
+    unsafe { Heap::ptr(obj as usize + OBJ_HEADER_SIZE).write_bytes(0, klass.len) };
+    obj
+}
+
+// fields missing from the layout of the JDK in use are skipped
+fn set_field(klass: &Klass, obj: Ref, class: &str, name: &str, descriptor: &str, value: Ref) {
+    if let Some((offset, _)) = klass.layout.get(&(class, name, descriptor)) {
+        let field = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + offset);
+        unsafe { field.copy_from(value.to_le_bytes().as_ptr(), PTR_SIZE) };
+    }
+}
+
+// the value of a 32-bit field, `None` if it is null or missing from the layout of the JDK in use
+fn get_field(obj: Ref, class: &str, name: &str, descriptor: &str) -> Option<Ref> {
+    if obj == 0 {
+        return None;
+    }
+    let klass = unsafe { &*Heap::as_obj(obj).klass };
+    let (offset, _) = klass.layout.get(&(class, name, descriptor))?;
+    let field = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + offset);
+    match Ref::from_le_bytes(unsafe { *field.cast::<[u8; PTR_SIZE]>() }) {
+        0 => None,
+        value => Some(value),
+    }
+}
+
+// `Ljava/lang/String;` to `java/lang/String`, primitives and arrays are named by descriptor
+fn class_name(descriptor: &str) -> &str {
+    if descriptor.starts_with("L") && descriptor.ends_with(";") {
+        &descriptor[1..descriptor.len() - 1]
+    } else {
+        descriptor
+    }
+}
+
+// `java/lang/String` to `Ljava/lang/String;`, arrays are named by descriptor
+fn reference(class: &str) -> String {
+    if class.starts_with("[") {
+        class.to_owned()
+    } else {
+        format!("L{};", class)
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+
+    use super::super::test::{define_exceptions, exception, method, run_method};
+    use super::*;
+    use crate::bytecode::{attribute::Attribute, field::Field, method::Method};
+    use crate::mem::{
+        heap::test::reset_heap,
+        klass::{test::define_object, ObjHeader},
+        metaspace::test::define_class,
+        pool::RuntimeConstantPool,
+        strings::test::{define_string, init_strings},
+    };
+    use std::cell::Cell;
+    use std::sync::{mpsc::channel, RwLock};
+
+    fn class(
+        name: &str,
+        superclass: &str,
+        items: Vec<ConstantItem>,
+        fields: &[(U2, &str, &str)],
+        methods: &[(U2, &str, &str)],
+    ) -> Class {
+        Class {
+            constant_pool: ConstantPool::new(items),
+            access_flag: 0x0021,
+            this_class_name: name.to_owned(),
+            super_class_name: superclass.to_owned(),
+            interfaces: vec![],
+            fields: fields
+                .iter()
+                .map(|(access_flag, name, descriptor)| {
+                    Arc::new(Field {
+                        access_flag: *access_flag,
+                        name: name.to_string(),
+                        descriptor: descriptor.to_string(),
+                        attributes: vec![],
+                        value: Cell::new(None),
+                    })
+                })
+                .collect(),
+            methods: methods
+                .iter()
+                .map(|(access_flag, name, descriptor)| {
+                    Arc::new(Method {
+                        access_flag: *access_flag,
+                        name: name.to_string(),
+                        descriptor: descriptor.to_string(),
+                        attributes: vec![],
+                        decoded: RwLock::new(None),
+                    })
+                })
+                .collect(),
+            attributes: vec![],
+            runtime_pool: RuntimeConstantPool::new(),
+        }
+    }
+
+    /// the fields the VM fills, the rest of the JDK classes don't matter
+    pub fn define_invoke_classes() {
+        let object = define_object();
+        let define = |name: &str, superclass: &Arc<Klass>, fields: &[(U2, &str, &str)]| {
+            let class = class(name, &superclass.name, vec![ConstantItem::NIL], fields, &[]);
+            define_class(Klass::new(
+                Arc::new(class),
+                0,
+                Some(Arc::clone(superclass)),
+                vec![],
+            ))
+        };
+        define(
+            "java/lang/Class",
+            &object,
+            &[(0x0002, "name", "Ljava/lang/String;")],
+        );
+        define(
+            "java/lang/invoke/MethodType",
+            &object,
+            &[
+                (0x0012, "rtype", "Ljava/lang/Class;"),
+                (0x0012, "ptypes", "[Ljava/lang/Class;"),
+                (0x0002, "methodDescriptor", "Ljava/lang/String;"),
+            ],
+        );
+        define(
+            "java/lang/invoke/MemberName",
+            &object,
+            &[
+                (0x0002, "clazz", "Ljava/lang/Class;"),
+                (0x0002, "name", "Ljava/lang/String;"),
+                (0x0002, "type", "Ljava/lang/Object;"),
+                (0x0002, "flags", "I"),
+            ],
+        );
+        let handle = define(
+            "java/lang/invoke/MethodHandle",
+            &object,
+            &[(0x0012, "type", "Ljava/lang/invoke/MethodType;")],
+        );
+        define(
+            "java/lang/invoke/DirectMethodHandle",
+            &handle,
+            &[(0x0010, "member", "Ljava/lang/invoke/MemberName;")],
+        );
+        define(
+            "java/lang/invoke/MethodHandles$Lookup",
+            &object,
+            &[
+                (0x0012, "lookupClass", "Ljava/lang/Class;"),
+                (0x0012, "allowedModes", "I"),
+            ],
+        );
+        define(
+            "java/lang/invoke/CallSite",
+            &object,
+            &[(0x0000, "target", "Ljava/lang/invoke/MethodHandle;")],
+        );
+    }
+
+    // class Ldc {
+    //     int count;
+    //     static int twice(int x);
+    // }
+    //
+    // with constants of every kind, `far` is beyond the reach of ldc
+    fn define_ldc() -> Arc<Class> {
+        use ConstantItem::*;
+        let mut items = vec![
+            NIL,
+            UTF8("Ldc".to_owned()),
+            Class(1),
+            UTF8("twice".to_owned()),
+            UTF8("(I)I".to_owned()),
+            NameAndType(3, 4),
+            MethodRef(2, 5),
+            // 7
+            MethodHandle(REF_INVOKE_STATIC, 6),
+            UTF8("(I[Ljava/lang/String;)J".to_owned()),
+            // 9
+            MethodType(8),
+            UTF8("count".to_owned()),
+            UTF8("I".to_owned()),
+            NameAndType(10, 11),
+            FieldRef(2, 12),
+            // 14
+            MethodHandle(REF_GET_FIELD, 13),
+            // 15, count isn't static
+            MethodHandle(REF_GET_STATIC, 13),
+            // 16, twice is static
+            MethodHandle(REF_INVOKE_VIRTUAL, 6),
+            UTF8("Missing".to_owned()),
+            // 18
+            Class(17),
+        ];
+        while items.len() < 300 {
+            items.push(UTF8("padding".to_owned()));
+        }
+        items.push(String(301));
+        items.push(UTF8("far".to_owned()));
+        let ldc = class(
+            "Ldc",
+            "java/lang/Object",
+            items,
+            &[(0x0000, "count", "I")],
+            &[(0x0008, "twice", "(I)I")],
+        );
+        let klass = define_class(Klass::new(Arc::new(ldc), 0, Some(define_object()), vec![]));
+        Arc::clone(klass.bytecode.as_ref().unwrap())
+    }
+
+    // ldc_w #idx
+    fn ldc(class: &Arc<Class>, idx: U2) -> Result<Ref, String> {
+        let [hi, lo] = idx.to_be_bytes();
+        let method = method(vec![0x13, hi, lo], 0);
+        run_method(class, &method, &[], |ctx| match exception(ctx) {
+            Some(e) => Err(e),
+            None => Ok(Ref::from_le_bytes(ctx.stack.pop())),
+        })
+    }
+
+    fn get(obj: Ref, class: &str, name: &str, descriptor: &str) -> Ref {
+        let klass = unsafe { &*Heap::as_obj(obj).klass };
+        let (offset, _) = klass.layout.get(&(class, name, descriptor)).unwrap();
+        let field = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + offset);
+        Ref::from_le_bytes(unsafe { *field.cast::<[u8; PTR_SIZE]>() })
+    }
+
+    fn klass_name(obj: Ref) -> &'static str {
+        unsafe { &(*Heap::as_obj(obj).klass).name }
+    }
+
+    fn string(obj: Ref) -> std::string::String {
+        std::string::String::from_utf16(&Strings::content(obj)).unwrap()
+    }
+
+    fn descriptor(method_type: Ref) -> std::string::String {
+        let descriptor = "Ljava/lang/String;";
+        string(get(
+            method_type,
+            "java/lang/invoke/MethodType",
+            "methodDescriptor",
+            descriptor,
+        ))
+    }
+
+    fn mirror_of(name: &str) -> Ref {
+        define_class(Klass::new_phantom_klass(name))
+            .mirror
+            .load(Ordering::Acquire)
+    }
+
+    #[test]
+    pub fn test_ldc() {
+        let _heap = reset_heap();
+        define_exceptions();
+        define_string();
+        init_strings();
+        define_invoke_classes();
+        let class = define_ldc();
+        assert_eq!(Ok("far".to_owned()), ldc(&class, 300).map(string));
+
+        let mirror = ldc(&class, 2).unwrap();
+        assert_eq!("java/lang/Class", klass_name(mirror));
+        assert_eq!(mirror, mirror_of("Ldc"));
+        assert_eq!(Ok(mirror), ldc(&class, 2));
+
+        let method_type = ldc(&class, 9).unwrap();
+        assert_eq!(Ok(method_type), ldc(&class, 9));
+        assert_eq!("(I[Ljava/lang/String;)J", descriptor(method_type));
+        let rtype = get(
+            method_type,
+            "java/lang/invoke/MethodType",
+            "rtype",
+            "Ljava/lang/Class;",
+        );
+        assert_eq!(mirror_of("J"), rtype);
+        let ptypes = get(
+            method_type,
+            "java/lang/invoke/MethodType",
+            "ptypes",
+            "[Ljava/lang/Class;",
+        );
+        assert_eq!(Some(2), Heap::as_obj(ptypes).size);
+        let element = |i: usize| {
+            let element = Heap::ptr(ptypes as usize + OBJ_HEADER_SIZE + i * PTR_SIZE);
+            Ref::from_le_bytes(unsafe { *element.cast::<[u8; PTR_SIZE]>() })
+        };
+        assert_eq!(mirror_of("I"), element(0));
+        assert_eq!(mirror_of("[Ljava/lang/String;"), element(1));
+
+        let handle = ldc(&class, 7).unwrap();
+        assert_eq!(Ok(handle), ldc(&class, 7));
+        assert_eq!("java/lang/invoke/DirectMethodHandle", klass_name(handle));
+        let handle_type = "Ljava/lang/invoke/MethodType;";
+        let handle_type = get(handle, "java/lang/invoke/MethodHandle", "type", handle_type);
+        assert_eq!("(I)I", descriptor(handle_type));
+        let member = "Ljava/lang/invoke/MemberName;";
+        let member = get(
+            handle,
+            "java/lang/invoke/DirectMethodHandle",
+            "member",
+            member,
+        );
+        let member_name = "java/lang/invoke/MemberName";
+        assert_eq!(
+            mirror,
+            get(member, member_name, "clazz", "Ljava/lang/Class;")
+        );
+        assert_eq!(
+            "twice",
+            string(get(member, member_name, "name", "Ljava/lang/String;"))
+        );
+        let member_type = get(member, member_name, "type", "Ljava/lang/Object;");
+        assert_eq!("(I)I", descriptor(member_type));
+        let flags = 0x0008 | IS_METHOD | (REF_INVOKE_STATIC as i32) << REFERENCE_KIND_SHIFT;
+        assert_eq!(flags as Ref, get(member, member_name, "flags", "I"));
+
+        let getter = ldc(&class, 14).unwrap();
+        let getter_type = "Ljava/lang/invoke/MethodType;";
+        let getter_type = get(getter, "java/lang/invoke/MethodHandle", "type", getter_type);
+        assert_eq!("(LLdc;)I", descriptor(getter_type));
+
+        let icce = Err("java/lang/IncompatibleClassChangeError".to_owned());
+        assert_eq!(icce, ldc(&class, 15));
+        assert_eq!(icce, ldc(&class, 16));
+        let missing = Err("java/lang/NoClassDefFoundError".to_owned());
+        assert_eq!(missing, ldc(&class, 18));
+    }
+
+    #[test]
+    pub fn test_static_synchronized() {
+        let _heap = reset_heap();
+        define_invoke_classes();
+        let class = define_ldc();
+        // static synchronized void test() { return; }
+        let synchronized = Arc::new(Method {
+            access_flag: 0x0028,
+            name: "test".to_owned(),
+            descriptor: "()V".to_owned(),
+            attributes: vec![Attribute::Code(
+                1,
+                0,
+                Arc::new(vec![0xb1]),
+                Arc::new(vec![]),
+                Arc::new(vec![]),
+            )],
+            decoded: RwLock::new(None),
+        });
+        let (_, rx) = channel();
+        let mut context = ThreadContext::new(1, 0, rx, channel().0);
+        let caller = method(vec![0x00], 0);
+        context
+            .invoke(Arc::as_ptr(&class), Arc::as_ptr(&caller), 0, 0)
+            .unwrap();
+        context
+            .invoke(Arc::as_ptr(&class), Arc::as_ptr(&synchronized), 1, 0)
+            .unwrap();
+        // the same object `Ldc.class` evaluates to
+        let mirror = mirror_of("Ldc");
+        let locked = |obj: Ref| !ObjHeader::from_vm_raw(Heap::ptr(obj as usize)).is_lock_free();
+        assert!(locked(mirror));
+        assert!(context
+            .stack
+            .collect_tracing_roots()
+            .into_iter()
+            .any(|root| unsafe { *root } == mirror));
+        context.stack.return_normal().unwrap();
+        assert!(!locked(mirror));
+    }
+}
@@ -0,0 +1,582 @@
+use super::callsite::{Bootstrap, CallSite};
+use super::thread::ThreadContext;
+use crate::bytecode::{
+    self, atom::*, attribute::Attribute, class::Class, constant_pool::*, field::Field,
+    method::Method, METHOD_ACC_STATIC,
+};
+use crate::mem::{klass::Klass, metaspace::*, pool::RuntimeConstantPool};
+use std::cell::Cell;
+use std::iter;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, RwLock,
+};
+
+// Lambdas and method references are linked without running the JDK's class spinner: a class
+// implementing the functional interface is built in place, the captured arguments become its
+// fields and the interface method forwards them with its own parameters to the target handle.
+
+const ACC_PUBLIC: U2 = 0x0001;
+
+const ACC_PRIVATE: U2 = 0x0002;
+
+const ACC_FINAL: U2 = 0x0010;
+
+const ACC_SUPER: U2 = 0x0020;
+
+const ACC_SYNTHETIC: U2 = 0x1000;
+
+const FLAG_SERIALIZABLE: i32 = 1;
+
+const FLAG_MARKERS: i32 = 2;
+
+const FLAG_BRIDGES: i32 = 4;
+
+const BOOTSTRAP_METHOD_ERROR: &'static str = "java/lang/BootstrapMethodError";
+
+static SPUN: AtomicUsize = AtomicUsize::new(0);
+
+/// linker of `LambdaMetafactory.metafactory`
+pub fn metafactory(
+    context: &mut ThreadContext,
+    bootstrap: &Bootstrap,
+) -> Result<Option<CallSite>, String> {
+    spin(context, bootstrap, vec![], vec![])
+}
+
+/// linker of `LambdaMetafactory.altMetafactory`, the flags argument tells which of the marker
+/// interfaces and bridge descriptors follow
+pub fn alt_metafactory(
+    context: &mut ThreadContext,
+    bootstrap: &Bootstrap,
+) -> Result<Option<CallSite>, String> {
+    let pool = &bootstrap.caller.constant_pool;
+    let mut extra = bootstrap.arguments.iter().skip(3);
+    let mut next = || {
+        extra
+            .next()
+            .map(|idx| *idx)
+            .ok_or_else(|| BOOTSTRAP_METHOD_ERROR.to_owned())
+    };
+    let flags = pool.get_integer(next()?);
+    let mut markers = vec![];
+    if flags & FLAG_MARKERS != 0 {
+        for _ in 0..pool.get_integer(next()?) {
+            markers.push(pool.get_str(next()?).to_owned());
+        }
+    }
+    let mut bridges = vec![];
+    if flags & FLAG_BRIDGES != 0 {
+        for _ in 0..pool.get_integer(next()?) {
+            bridges.push(pool.get_method_type(next()?).to_owned());
+        }
+    }
+    if flags & FLAG_SERIALIZABLE != 0 {
+        markers.push("java/io/Serializable".to_owned());
+    }
+    spin(context, bootstrap, markers, bridges)
+}
+
+fn spin(
+    context: &mut ThreadContext,
+    bootstrap: &Bootstrap,
+    markers: Vec<String>,
+    bridges: Vec<String>,
+) -> Result<Option<CallSite>, String> {
+    if bootstrap.arguments.len() < 3 {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    let pool = &bootstrap.caller.constant_pool;
+    let sam = pool.get_method_type(bootstrap.arguments[0]);
+    let (kind, target) = pool.get_method_handle(bootstrap.arguments[1]);
+    // the call site takes the captured arguments and returns the functional interface
+    let (captured, _, interface) =
+        bytecode::resolve_method_descriptor(bootstrap.descriptor, METHOD_ACC_STATIC);
+    if !interface.starts_with("L") {
+        return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+    }
+    let interface = &interface[1..interface.len() - 1];
+    let mut interfaces = vec![];
+    for name in iter::once(interface).chain(markers.iter().map(|m| m.as_ref())) {
+        let (klass, initialized) = ClassArena::load_class(name, context)?;
+        if !initialized {
+            return Ok(None);
+        }
+        interfaces.push(klass);
+    }
+    let (object, _) = ClassArena::load_class("java/lang/Object", context)?;
+    // defined by the loader of the caller, it is in the runtime package of the caller
+    let (caller, _) = ClassArena::load_class(bootstrap.caller.get_name(), context)?;
+    let name = format!(
+        "{}$$Lambda${}",
+        bootstrap.caller.get_name(),
+        SPUN.fetch_add(1, Ordering::Relaxed)
+    );
+    let fields = captured
+        .into_iter()
+        .enumerate()
+        .map(|(i, descriptor)| {
+            Arc::new(Field {
+                access_flag: ACC_PRIVATE | ACC_FINAL,
+                name: format!("arg${}", i + 1),
+                descriptor: descriptor,
+                attributes: vec![],
+                value: Cell::new(None),
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut spinner = Spinner::new(&name);
+    let mut methods: Vec<Arc<Method>> = vec![];
+    for descriptor in iter::once(sam).chain(bridges.iter().map(|b| b.as_ref())) {
+        if methods.iter().any(|m| m.descriptor == descriptor) {
+            continue;
+        }
+        let method = spinner.forward(bootstrap.name, descriptor, &fields, kind, target)?;
+        methods.push(Arc::new(method));
+    }
+    let class = Class {
+        constant_pool: ConstantPool::new(spinner.items),
+        access_flag: ACC_SYNTHETIC | ACC_FINAL | ACC_SUPER,
+        this_class_name: name,
+        super_class_name: "java/lang/Object".to_owned(),
+        interfaces: interfaces.iter().map(|i| i.name.clone()).collect(),
+        fields: fields,
+        methods: methods,
+        attributes: vec![],
+        runtime_pool: RuntimeConstantPool::new(),
+    };
+    let klass = Klass::new(
+        Arc::new(class),
+        caller.classloader,
+        Some(object),
+        interfaces,
+    );
+    Ok(Some(CallSite::Lambda(ClassArena::define(klass))))
+}
+
+// builds the constant pool of a spun class along with its code
+struct Spinner {
+    this: String,
+    items: Vec<ConstantItem>,
+}
+
+impl Spinner {
+    fn new(this: &str) -> Self {
+        Spinner {
+            this: this.to_owned(),
+            items: vec![ConstantItem::NIL],
+        }
+    }
+
+    fn push(&mut self, item: ConstantItem) -> U2 {
+        self.items.push(item);
+        (self.items.len() - 1) as U2
+    }
+
+    fn utf8(&mut self, s: &str) -> U2 {
+        let found = self.items.iter().position(|item| match item {
+            ConstantItem::UTF8(v) => v == s,
+            _ => false,
+        });
+        match found {
+            Some(idx) => idx as U2,
+            None => self.push(ConstantItem::UTF8(s.to_owned())),
+        }
+    }
+
+    fn class(&mut self, name: &str) -> U2 {
+        let name = self.utf8(name);
+        self.push(ConstantItem::Class(name))
+    }
+
+    fn member(&mut self, tag: fn(U2, U2) -> ConstantItem, c: &str, name: &str, t: &str) -> U2 {
+        let c = self.class(c);
+        let name = self.utf8(name);
+        let t = self.utf8(t);
+        let nt = self.push(ConstantItem::NameAndType(name, t));
+        self.push(tag(c, nt))
+    }
+
+    /// the method `name` of `descriptor` which loads the captured fields and its parameters,
+    /// then invokes the target handle of `kind`
+    fn forward(
+        &mut self,
+        name: &str,
+        descriptor: &str,
+        fields: &[Arc<Field>],
+        kind: U1,
+        (c, (m, t)): (&str, (&str, &str)),
+    ) -> Result<Method, String> {
+        let (params, slots, ret) = bytecode::resolve_method_descriptor(descriptor, 0);
+        let (mut targets, _, mut target_ret) =
+            bytecode::resolve_method_descriptor(t, METHOD_ACC_STATIC);
+        match kind {
+            REF_INVOKE_VIRTUAL | REF_INVOKE_SPECIAL | REF_INVOKE_INTERFACE => {
+                targets.insert(0, format!("L{};", c));
+            }
+            REF_NEW_INVOKE_SPECIAL => target_ret = format!("L{};", c),
+            _ => {}
+        }
+        if fields.len() + params.len() != targets.len() || target_ret == "V" && ret != "V" {
+            return Err(BOOTSTRAP_METHOD_ERROR.to_owned());
+        }
+        let mut code = vec![];
+        if kind == REF_NEW_INVOKE_SPECIAL {
+            // new, dup
+            let class = self.class(c);
+            code.push(0xbb);
+            code.extend_from_slice(&class.to_be_bytes());
+            code.push(0x59);
+        }
+        for (f, to) in fields.iter().zip(targets.iter()) {
+            // aload_0, getfield
+            let this = self.this.clone();
+            let field = self.member(ConstantItem::FieldRef, &this, &f.name, &f.descriptor);
+            code.push(0x2a);
+            code.push(0xb4);
+            code.extend_from_slice(&field.to_be_bytes());
+            self.adapt(&mut code, &f.descriptor, to);
+        }
+        let mut local = 1;
+        for (p, to) in params.iter().zip(targets[fields.len()..].iter()) {
+            code.push(match stack_type(p) {
+                'I' => 0x15,
+                'J' => 0x16,
+                'F' => 0x17,
+                'D' => 0x18,
+                _ => 0x19,
+            });
+            code.push(local as U1);
+            local += width(p);
+            self.adapt(&mut code, p, to);
+        }
+        match kind {
+            REF_INVOKE_STATIC => {
+                let method = self.member(ConstantItem::MethodRef, c, m, t);
+                code.push(0xb8);
+                code.extend_from_slice(&method.to_be_bytes());
+            }
+            REF_INVOKE_VIRTUAL => {
+                let method = self.member(ConstantItem::MethodRef, c, m, t);
+                code.push(0xb6);
+                code.extend_from_slice(&method.to_be_bytes());
+            }
+            REF_INVOKE_SPECIAL | REF_NEW_INVOKE_SPECIAL => {
+                let method = self.member(ConstantItem::MethodRef, c, m, t);
+                code.push(0xb7);
+                code.extend_from_slice(&method.to_be_bytes());
+            }
+            REF_INVOKE_INTERFACE => {
+                let method = self.member(ConstantItem::InterfaceMethodRef, c, m, t);
+                code.push(0xb9);
+                code.extend_from_slice(&method.to_be_bytes());
+                code.push(targets.iter().map(|t| width(t)).sum::<usize>() as U1);
+                code.push(0);
+            }
+            _ => return Err(BOOTSTRAP_METHOD_ERROR.to_owned()),
+        }
+        if ret == "V" {
+            // pop, pop2
+            match width(&target_ret) {
+                1 => code.push(0x57),
+                2 => code.push(0x58),
+                _ => {}
+            }
+            code.push(0xb1);
+        } else {
+            self.adapt(&mut code, &target_ret, &ret);
+            code.push(match stack_type(&ret) {
+                'I' => 0xac,
+                'J' => 0xad,
+                'F' => 0xae,
+                'D' => 0xaf,
+                _ => 0xb0,
+            });
+        }
+        // every value may be widened on its way, plus the new instance and its duplicate
+        let max_stack = 2 + 2 * targets.len();
+        Ok(Method {
+            access_flag: ACC_PUBLIC,
+            name: name.to_owned(),
+            descriptor: descriptor.to_owned(),
+            attributes: vec![Attribute::Code(
+                max_stack as U2,
+                slots as U2,
+                Arc::new(code),
+                Arc::new(vec![]),
+                Arc::new(vec![]),
+            )],
+            decoded: RwLock::new(None),
+        })
+    }
+
+    // converts the value on top of the stack from `from` to `to`, by casting, boxing,
+    // unboxing or widening
+    fn adapt(&mut self, code: &mut Vec<U1>, from: &str, to: &str) {
+        if from == to {
+            return;
+        }
+        match (is_primitive(from), is_primitive(to)) {
+            (true, true) => {
+                if let Some(op) = widening(stack_type(from), stack_type(to)) {
+                    code.push(op);
+                }
+            }
+            (true, false) => {
+                // invokestatic Box.valueOf
+                let (boxed, _) = boxing(from);
+                let method = self.member(
+                    ConstantItem::MethodRef,
+                    boxed,
+                    "valueOf",
+                    &format!("({})L{};", from, boxed),
+                );
+                code.push(0xb8);
+                code.extend_from_slice(&method.to_be_bytes());
+            }
+            (false, true) => {
+                // checkcast Box, invokevirtual Box.xValue
+                let (boxed, unbox) = boxing(to);
+                let class = self.class(boxed);
+                code.push(0xc0);
+                code.extend_from_slice(&class.to_be_bytes());
+                let method =
+                    self.member(ConstantItem::MethodRef, boxed, unbox, &format!("(){}", to));
+                code.push(0xb6);
+                code.extend_from_slice(&method.to_be_bytes());
+            }
+            (false, false) => {
+                if to != "Ljava/lang/Object;" {
+                    let class = if to.starts_with("L") {
+                        self.class(&to[1..to.len() - 1])
+                    } else {
+                        self.class(to)
+                    };
+                    code.push(0xc0);
+                    code.extend_from_slice(&class.to_be_bytes());
+                }
+            }
+        }
+    }
+}
+
+fn is_primitive(descriptor: &str) -> bool {
+    !descriptor.starts_with("L") && !descriptor.starts_with("[")
+}
+
+fn width(descriptor: &str) -> usize {
+    match descriptor {
+        "V" => 0,
+        "D" | "J" => 2,
+        _ => 1,
+    }
+}
+
+// boolean, byte, char and short are ints on the operand stack
+fn stack_type(descriptor: &str) -> char {
+    match descriptor {
+        "Z" | "B" | "C" | "S" | "I" => 'I',
+        "J" => 'J',
+        "F" => 'F',
+        "D" => 'D',
+        _ => 'L',
+    }
+}
+
+fn widening(from: char, to: char) -> Option<U1> {
+    match (from, to) {
+        ('I', 'J') => Some(0x85),
+        ('I', 'F') => Some(0x86),
+        ('I', 'D') => Some(0x87),
+        ('J', 'F') => Some(0x89),
+        ('J', 'D') => Some(0x8a),
+        ('F', 'D') => Some(0x8d),
+        _ => None,
+    }
+}
+
+/// the wrapper class of a primitive type and the method unboxing it
+pub fn boxing(primitive: &str) -> (&'static str, &'static str) {
+    match primitive {
+        "Z" => ("java/lang/Boolean", "booleanValue"),
+        "B" => ("java/lang/Byte", "byteValue"),
+        "C" => ("java/lang/Character", "charValue"),
+        "S" => ("java/lang/Short", "shortValue"),
+        "I" => ("java/lang/Integer", "intValue"),
+        "J" => ("java/lang/Long", "longValue"),
+        "F" => ("java/lang/Float", "floatValue"),
+        _ => ("java/lang/Double", "doubleValue"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::super::callsite::test::init_call_sites;
+    use super::super::test::{define_exceptions, exception, run_method};
+    use super::*;
+    use crate::mem::{
+        heap::test::reset_heap, klass::test::define_object, metaspace::test::define_class, Ref,
+    };
+
+    // javac --release 8 -g:none
+    //
+    // interface IntOp { int apply(int x); }
+    // interface Marker {}
+    // interface Adder { int add(Lambdas c, int x); }
+    // interface Maker { Lambdas make(int start); }
+    // interface Wide { long apply(int x, long y, double z); }
+    //
+    // class Lambdas {
+    //     int value;
+    //     Lambdas(int start) { value = start; }
+    //     public int add(int x) { value += x; return value; }
+    //     public IntOp bump() { return y -> add(y); }
+    //     static int twice(int x) { return x * 2; }
+    //
+    //     static int run(int x) {
+    //         Maker maker = Lambdas::new;
+    //         Lambdas c = maker.make(x);
+    //         IntOp capture = y -> y + c.value;
+    //         IntOp twice = Lambdas::twice;
+    //         Adder unbound = Lambdas::add;
+    //         IntOp marked = (IntOp & Marker) y -> y - 1;
+    //         int v = twice.apply(capture.apply(1));
+    //         v = unbound.add(c, v);
+    //         v = c.bump().apply(v);
+    //         return marked.apply(v);
+    //     }
+    //
+    //     static long wide(int x) {
+    //         long k = 1L << 40;
+    //         Wide w = (a, b, d) -> a + b + (long) d + k;
+    //         return w.apply(x, 10L, 2.5);
+    //     }
+    // }
+    const INT_OP: &'static str = "yv66vgAAADQABwcAAgEABUludE9wBwAEAQAQamF2YS9sYW5nL09iamVjdAEABWFwcGx5AQAEKEkpSQYAAAEAAwAAAAAAAQQBAAUABgAAAAA=";
+
+    const MARKER: &'static str =
+        "yv66vgAAADQABQcAAgEABk1hcmtlcgcABAEAEGphdmEvbGFuZy9PYmplY3QGAAABAAMAAAAAAAAAAA==";
+
+    const ADDER: &'static str = "yv66vgAAADQABwcAAgEABUFkZGVyBwAEAQAQamF2YS9sYW5nL09iamVjdAEAA2FkZAEADShMTGFtYmRhcztJKUkGAAABAAMAAAAAAAEEAQAFAAYAAAAA";
+
+    const MAKER: &'static str = "yv66vgAAADQABwcAAgEABU1ha2VyBwAEAQAQamF2YS9sYW5nL09iamVjdAEABG1ha2UBAAwoSSlMTGFtYmRhczsGAAABAAMAAAAAAAEEAQAFAAYAAAAA";
+
+    const WIDE: &'static str = "yv66vgAAADQABwcAAgEABFdpZGUHAAQBABBqYXZhL2xhbmcvT2JqZWN0AQAFYXBwbHkBAAYoSUpEKUoGAAABAAMAAAAAAAEEAQAFAAYAAAAA";
+
+    const LAMBDAS: &'static str = "yv66vgAAADQAegoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWCQAIAAkHAAoMAAsADAEAB0xhbWJkYXMBAAV2YWx1ZQEAAUkSAAAADgwADwAQAQAFYXBwbHkBABIoTExhbWJkYXM7KUxJbnRPcDsSAAEAEgwAEwAUAQAEbWFrZQEACSgpTE1ha2VyOwsAFgAXBwAYDAATABkBAAVNYWtlcgEADChJKUxMYW1iZGFzOxIAAgAOEgADABwMAA8AHQEACSgpTEludE9wOxIABAAfDAAgACEBAANhZGQBAAkoKUxBZGRlcjsSAAUAHAcAJAEABk1hcmtlcgcAJgEABUludE9wCwAlACgMAA8AKQEABChJKUkLACsALAcALQwAIAAuAQAFQWRkZXIBAA0oTExhbWJkYXM7SSlJCgAIADAMADEAHQEABGJ1bXAFAAABAAAAAAASAAYANQwADwA2AQAJKEopTFdpZGU7BQAAAAAAAAAKBkAEAAAAAAAACwA8AD0HAD4MAA8APwEABFdpZGUBAAYoSUpEKUoKAAgAQQwAIAApAQAEKEkpVgEABENvZGUBAAV0d2ljZQEAA3J1bgEABHdpZGUBAAQoSSlKAQANbGFtYmRhJHdpZGUkMwEAByhKSUpEKUoBAAxsYW1iZGEkcnVuJDIBAAxsYW1iZGEkcnVuJDEBAA1sYW1iZGEkYnVtcCQwAQAQQm9vdHN0cmFwTWV0aG9kcw8GAE8KAFAAUQcAUgwAUwBUAQAiamF2YS9sYW5nL2ludm9rZS9MYW1iZGFNZXRhZmFjdG9yeQEAC21ldGFmYWN0b3J5AQDMKExqYXZhL2xhbmcvaW52b2tlL01ldGhvZEhhbmRsZXMkTG9va3VwO0xqYXZhL2xhbmcvU3RyaW5nO0xqYXZhL2xhbmcvaW52b2tlL01ldGhvZFR5cGU7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTtMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGU7TGphdmEvbGFuZy9pbnZva2UvTWV0aG9kVHlwZTspTGphdmEvbGFuZy9pbnZva2UvQ2FsbFNpdGU7EAApDwcAVwoACABYDABMACkQABkPCABbCgAIAFwMAAUAQg8GAF4KAAgAXwwASwAuDwYAYQoACABiDABEACkQAC4PBQBADwYAZgoAUABnDABoAGkBAA5hbHRNZXRhZmFjdG9yeQEAhihMamF2YS9sYW5nL2ludm9rZS9NZXRob2RIYW5kbGVzJExvb2t1cDtMamF2YS9sYW5nL1N0cmluZztMamF2YS9sYW5nL2ludm9rZS9NZXRob2RUeXBlO1tMamF2YS9sYW5nL09iamVjdDspTGphdmEvbGFuZy9pbnZva2UvQ2FsbFNpdGU7DwYAawoACABsDABKACkDAAAABgMAAAABAwAAAAAQAD8PBgByCgAIAHMMAEgASQEADElubmVyQ2xhc3NlcwcAdgEAJWphdmEvbGFuZy9pbnZva2UvTWV0aG9kSGFuZGxlcyRMb29rdXAHAHgBAB5qYXZhL2xhbmcvaW52b2tlL01ldGhvZEhhbmRsZXMBAAZMb29rdXAAIAAIAAIAAAABAAAACwAMAAAACgAAAAUAQgABAEMAAAAWAAIAAgAAAAoqtwABKhu1AAexAAAAAAABACAAKQABAEMAAAAbAAMAAgAAAA8qWbQABxtgtQAHKrQAB6wAAAAAAAEAMQAdAAEAQwAAABMAAQABAAAAByq6AA0AALAAAAAAAAgARAApAAEAQwAAABAAAgABAAAABBoFaKwAAAAAAAgARQApAAEAQwAAAG8AAwAIAAAAY7oAEQAATCsauQAVAgBNLLoAGgAATroAGwAAOgS6AB4AADoFugAiAADAACPAACU6BhkELQS5ACcCALkAJwIANgcZBSwVB7kAKgMANgcstgAvFQe5ACcCADYHGQYVB7kAJwIArAAAAAAACABGAEcAAQBDAAAAJQAGAAQAAAAZFAAyQB+6ADQAAE4tGhQANxQAObkAOwYArQAAAAAQCgBIAEkAAQBDAAAAFwAEAAcAAAALHIUhYRgFj2EeYa0AAAAAEAoASgApAAEAQwAAABAAAgABAAAABBoEZKwAAAAAEAoASwAuAAEAQwAAABMAAgACAAAABxsqtAAHYKwAAAAAEAIATAApAAEAQwAAABIAAgACAAAABiobtgBArAAAAAAAAgBNAAAAUAAHAE4AAwBVAFYAVQBOAAMAWQBaAFkATgADAFUAXQBVAE4AAwBVAGAAVQBOAAMAYwBkAGMAZQAHAFUAagBVAG0AbgAjAG8ATgADAHAAcQBwAHQAAAAKAAEAdQB3AHkAGQ==";
+
+    // stands for an application class loader
+    const APP_CLASSLOADER: Ref = 0x100;
+
+    fn define(bytecode: &str, loader: Ref, superclass: Option<Arc<Klass>>) -> Arc<Klass> {
+        let class = Arc::new(Class::from_vec(base64::decode(bytecode).unwrap()).unwrap());
+        define_class(Klass::new(class, loader, superclass, vec![]))
+    }
+
+    fn define_lambdas() -> Arc<Klass> {
+        define_exceptions();
+        init_call_sites();
+        let object = define_object();
+        for interface in &[INT_OP, MARKER, ADDER, MAKER, WIDE] {
+            define(interface, ROOT_CLASSLOADER, None);
+        }
+        define(LAMBDAS, APP_CLASSLOADER, Some(object))
+    }
+
+    fn call(klass: &Klass, name: &str, descriptor: &str, arg: i32) -> Result<Vec<u8>, String> {
+        let class = klass.bytecode.as_ref().unwrap();
+        let method = class.get_method(name, descriptor).unwrap();
+        let (_, _, ret) = bytecode::resolve_method_descriptor(descriptor, METHOD_ACC_STATIC);
+        run_method(class, &method, &arg.to_le_bytes(), |ctx| {
+            match exception(ctx) {
+                Some(e) => Err(e),
+                None if ret == "J" => Ok(ctx.stack.pop_w().to_vec()),
+                None => Ok(ctx.stack.pop().to_vec()),
+            }
+        })
+    }
+
+    #[test]
+    pub fn test_lambdas() {
+        let _heap = reset_heap();
+        let klass = define_lambdas();
+        // run twice, the second time through the linked call sites
+        for _ in 0..2 {
+            assert_eq!(
+                Ok(33i32.to_le_bytes().to_vec()),
+                call(&klass, "run", "(I)I", 5)
+            );
+        }
+        assert_eq!(
+            Ok((5i64 + 10 + 2 + (1 << 40)).to_le_bytes().to_vec()),
+            call(&klass, "wide", "(I)J", 5)
+        );
+        let spun = ClassArena::loaded()
+            .into_iter()
+            .filter(|klass| klass.name.starts_with("Lambdas$$Lambda$"))
+            .collect::<Vec<_>>();
+        assert!(!spun.is_empty());
+        assert!(spun
+            .iter()
+            .all(|klass| klass.classloader == APP_CLASSLOADER));
+    }
+
+    #[test]
+    pub fn test_forward() {
+        let mut spinner = Spinner::new("Lambdas$$Lambda$0");
+        let field = Arc::new(Field {
+            access_flag: ACC_PRIVATE | ACC_FINAL,
+            name: "arg$1".to_owned(),
+            descriptor: "LLambdas;".to_owned(),
+            attributes: vec![],
+            value: Cell::new(None),
+        });
+        // the receiver of `Lambdas::add` is captured
+        let forward = spinner
+            .forward(
+                "apply",
+                "(I)I",
+                &[field],
+                REF_INVOKE_VIRTUAL,
+                ("Lambdas", ("add", "(I)I")),
+            )
+            .unwrap();
+        let (_, max_locals, code, _, _) = forward.get_code().unwrap();
+        let pool = ConstantPool::new(spinner.items.clone());
+        let at = |pc: usize| u16::from_be_bytes([code[pc], code[pc + 1]]);
+        assert_eq!(2, max_locals);
+        // aload_0, getfield, iload 1, invokevirtual, ireturn
+        assert_eq!(
+            vec![0x2a, 0xb4, 0x15, 0x01, 0xb6, 0xac],
+            [&code[..2], &code[4..7], &code[9..]].concat()
+        );
+        assert_eq!(
+            ("Lambdas$$Lambda$0", ("arg$1", "LLambdas;")),
+            pool.get_javaref(at(2))
+        );
+        assert_eq!(("Lambdas", ("add", "(I)I")), pool.get_javaref(at(7)));
+        // boxed to the erased return type
+        let mut code = vec![];
+        spinner.adapt(&mut code, "I", "Ljava/lang/Object;");
+        let pool = ConstantPool::new(spinner.items.clone());
+        assert_eq!(0xb8, code[0]);
+        assert_eq!(
+            ("java/lang/Integer", ("valueOf", "(I)Ljava/lang/Integer;")),
+            pool.get_javaref(u16::from_be_bytes([code[1], code[2]]))
+        );
+        // unboxed from the erased parameter type
+        let mut code = vec![];
+        spinner.adapt(&mut code, "Ljava/lang/Object;", "J");
+        let pool = ConstantPool::new(spinner.items.clone());
+        assert_eq!((0xc0, 0xb6), (code[0], code[3]));
+        assert_eq!(
+            "java/lang/Long",
+            pool.get_str(u16::from_be_bytes([code[1], code[2]]))
+        );
+        assert_eq!(
+            ("java/lang/Long", ("longValue", "()J")),
+            pool.get_javaref(u16::from_be_bytes([code[4], code[5]]))
+        );
+    }
+}
@@ -1,18 +1,41 @@
+pub mod callsite;
+pub mod concat;
+pub mod constants;
+pub mod lambda;
 pub mod thread;
 
-use self::thread::ThreadContext;
+use self::{
+    callsite::{CallSite, CallSites},
+    thread::{safepoint, ThreadContext},
+};
 use crate::{
     bytecode,
-    bytecode::{atom::*, constant_pool::ConstantItem},
+    bytecode::{
+        atom::*,
+        class::Class,
+        code::{InlineCache, Insn, Offset},
+        constant_pool::ConstantItem,
+        field::Field,
+        method::Method,
+    },
     gc,
-    mem::{heap::Heap, klass::*, metaspace::*, strings::Strings, *},
+    mem::{
+        heap::Heap,
+        klass::*,
+        metaspace::*,
+        pool::{self, Entry},
+        *,
+    },
 };
 use std::sync::Arc;
 use std::thread::Thread;
 
-use log::trace;
+use log::{log_enabled, trace, warn, Level};
 
 macro_rules! math_bi {
+    ($l: tt, $r: tt, $f: ident) => {
+        |a, b| $l::from_le_bytes(a).$f($r::from_le_bytes(b)).to_le_bytes()
+    };
     ($l: tt, $r: tt, $op: tt) => {
         |a, b| ($l::from_le_bytes(a) $op $r::from_le_bytes(b)).to_le_bytes()
     }
@@ -25,298 +48,249 @@ macro_rules! math_un {
 }
 
 pub fn execute(context: &mut ThreadContext) {
-    while context.stack.has_next(context.pc) {
-        let pause = context.rx.try_recv();
-        if pause.is_ok() {
-            context.tx.send(context.roots()).unwrap();
-            // waiting signal
-            let _ = context.rx.recv().unwrap();
+    run(context, 0);
+}
+
+/// runs `method` with `args` as a call made by the current instruction and returns the slot it
+/// returned, `None` if it threw and the exception is pending at the current instruction,
+/// methods returning long or double aren't called this way
+pub fn call(
+    context: &mut ThreadContext,
+    class: *const Class,
+    method: *const Method,
+    args: &[Slot],
+) -> Option<Slot> {
+    let depth = context.stack.depth();
+    let pc = context.pc;
+    for arg in args {
+        context.stack.push(arg);
+    }
+    match context.invoke(class, method, pc, args.len()) {
+        Ok(entry) => context.pc = entry,
+        Err(message) => {
+            warn!(
+                "{}.{}: {}",
+                unsafe { &*class }.get_name(),
+                unsafe { &*method }.name,
+                message
+            );
+            throw_vm_exception(context, "java/lang/VerifyError");
+            return None;
         }
+    }
+    run(context, depth);
+    if context.exception_pending {
+        return None;
+    }
+    let (_, descriptor, access_flag) = unsafe { &*method }.get_name_and_descriptor();
+    let (_, _, ret) = bytecode::resolve_method_descriptor(descriptor, access_flag);
+    match ret.as_ref() {
+        "V" => Some(NULL),
+        _ => Some(context.stack.pop()),
+    }
+}
+
+/// the mirror the static synchronized methods of `class` lock, `java.lang.Class` is initialized
+/// on the spot if it hasn't been
+pub fn class_mirror(context: &mut ThreadContext, class: &Class) -> Result<Ref, String> {
+    let (klass, _) = ClassArena::load_class(class.get_name(), context)?;
+    loop {
+        let depth = context.stack.depth();
+        if let Some(mirror) = constants::mirror(&klass, context)? {
+            return Ok(mirror);
+        }
+        run(context, depth);
+        if context.exception_pending {
+            return Err("java/lang/ExceptionInInitializerError".to_owned());
+        }
+    }
+}
+
+// runs the frames above the first `depth` ones, a frame returning to the one below them or
+// throwing to it ends the run
+fn run(context: &mut ThreadContext, depth: usize) {
+    while context.stack.depth() > depth && context.stack.has_next(context.pc) {
+        context.safepoint();
         // handle_exception
         if context.exception_pending {
             handle_exception(context);
+            continue;
+        }
+        // the decoded code is owned by the method rather than the frame
+        let code = unsafe { &*context.stack.code_ptr() };
+        let instruction = &code.insns[context.pc];
+        if log_enabled!(Level::Trace) {
+            context.stack.dump(context.pc);
         }
-        let instruction = context.stack.code_at(context.pc);
-        context.stack.dump(context.pc);
         match instruction {
-            // nop
-            0x00 => {
-                context.pc = context.pc + 1;
-            }
-            // aconst_null
-            0x01 => {
-                context.stack.push(&NULL);
-                context.pc = context.pc + 1;
-            }
-            // iconst -1 ~ 5
-            0x02..=0x08 => {
-                let opr = context.stack.code_at(context.pc) as i32 - 3;
-                context.stack.push(&opr.to_le_bytes());
-                context.pc = context.pc + 1;
-            }
-            // lconst 0 ~ 1
-            0x09..=0x0a => {
-                let opr = context.stack.code_at(context.pc) as i64 - 9;
-                context.stack.push_w(&opr.to_le_bytes());
-                context.pc = context.pc + 1;
-            }
-            // fconst 0 ~ 2
-            0x0b..=0x0d => {
-                let opr = context.stack.code_at(context.pc) as f32 - 11.0;
-                context.stack.push(&opr.to_le_bytes());
-                context.pc = context.pc + 1;
-            }
-            // dconst 0 ~ 1
-            0x0e..=0x0f => {
-                let opr = context.stack.code_at(context.pc) as f64 - 14.0;
-                context.stack.push_w(&opr.to_le_bytes());
-                context.pc = context.pc + 1;
-            }
-            // bipush
-            0x10 => {
-                context
-                    .stack
-                    .push(&(context.stack.code_at(context.pc + 1) as i32).to_le_bytes());
-                context.pc = context.pc + 2;
-            }
-            // sipush
-            0x11 => {
-                let opr = (context.stack.code_at(context.pc + 1) as i32) << 8
-                    | (context.stack.code_at(context.pc + 2) as i32);
-                context.stack.push(&opr.to_le_bytes());
-                context.pc = context.pc + 3;
-            }
-            // ldc
-            0x12 => {
-                let v = match context
-                    .stack
-                    .class()
-                    .constant_pool
-                    .get(context.stack.code_at(context.pc + 1) as U2)
-                {
-                    ConstantItem::Float(f) => f.to_le_bytes(),
-                    ConstantItem::Integer(i) => i.to_le_bytes(),
-                    ConstantItem::String(r) => {
-                        let utf8 = context.stack.class().constant_pool.get(*r).clone();
-                        match utf8 {
-                            ConstantItem::UTF8(s) => Strings::get(&s, context).to_le_bytes(),
-                            _ => panic!(""),
-                        }
-                    }
-                    _ => unreachable!(),
-                };
-                context.stack.push(&v);
-                context.pc = context.pc + 2;
-            }
-            // ldc2w
-            0x14 => {
-                let opr = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let v = match context.stack.class().constant_pool.get(opr) {
-                    ConstantItem::Double(d) => d.to_le_bytes(),
-                    ConstantItem::Long(l) => l.to_le_bytes(),
-                    _ => panic!(""),
-                };
-                context.stack.push_w(&v);
-                context.pc = context.pc + 3;
-            }
-            // iload/fload
-            0x15 | 0x17 => {
-                let opr = context.stack.code_at(context.pc + 1) as usize;
-                context.stack.load(opr, 1);
-                context.pc = context.pc + 2;
-            }
-            // lload/dload
-            0x16 | 0x18 => {
-                let opr = context.stack.code_at(context.pc + 1) as usize;
-                context.stack.load(opr, 2);
-                context.pc = context.pc + 2;
-            }
-            // iload 0 ~ 3
-            0x1a..=0x1d => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x1a;
-                context.stack.load(opr, 1);
+            Insn::Nop => {
                 context.pc = context.pc + 1;
             }
-            // lload 0 ~ 3
-            0x1e..=0x21 => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x1e;
-                context.stack.load(opr, 2);
+            Insn::Push(v) => {
+                context.stack.push(v);
                 context.pc = context.pc + 1;
             }
-            // fload 0 ~ 3
-            0x22..=0x25 => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x22;
-                context.stack.load(opr, 1);
+            Insn::PushWide(v) => {
+                context.stack.push_w(v);
                 context.pc = context.pc + 1;
             }
-            // dload 0 ~ 3
-            0x26..=0x29 => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x26;
-                context.stack.load(opr, 2);
+            Insn::Ldc(idx) => load_constant(context, *idx),
+            Insn::Load(index, slots) => {
+                context.stack.load(*index, *slots);
                 context.pc = context.pc + 1;
             }
-            // aload 0 ~ 3
-            0x2a..=0x2d => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x2a;
-                context.stack.load(opr, 1);
-                context.pc = context.pc + 1;
-            }
-            // iaload
-            0x2e => {
-                let array_idx = u32::from_le_bytes(context.stack.pop()) as usize;
-                let arrayref = u32::from_le_bytes(context.stack.pop()) as usize;
-                // let current = context.stack.mut_frame();
+            // iaload, laload, faload, daload, aaload, baload, caload, saload
+            Insn::ArrayLoad(op) => {
+                let (element, _) = match array_element(context, 0) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                context.stack.downward(2);
                 unsafe {
-                    let header = ObjHeader::from_vm_raw(Heap::ptr(arrayref));
-                    if array_idx >= header.size.unwrap() as usize {
-                        context
-                            .stack
-                            .update(context.stack.operands().add(2 * PTR_SIZE));
-                        throw_vm_exception(context, "java/lang/ArrayIndexOutOfBoundsException");
-                        continue;
+                    match op {
+                        0x2f | 0x31 => context.stack.push_w(&*element.cast::<WideSlot>()),
+                        0x33 => context.stack.push(&(*element as i8 as i32).to_le_bytes()),
+                        0x34 => {
+                            let v = u16::from_le_bytes(*element.cast::<[u8; 2]>());
+                            context.stack.push(&(v as i32).to_le_bytes());
+                        }
+                        0x35 => {
+                            let v = i16::from_le_bytes(*element.cast::<[u8; 2]>());
+                            context.stack.push(&(v as i32).to_le_bytes());
+                        }
+                        _ => context.stack.push(&*element.cast::<Slot>()),
                     }
-                    // FIXME heap -> stack
-                    let offset = (&*header.klass).len * array_idx;
-                    context.stack.operands().copy_from(
-                        Heap::ptr(arrayref + OBJ_HEADER_SIZE + offset),
-                        (&*header.klass).len,
-                    );
-                    context
-                        .stack
-                        .update(context.stack.operands().add((&*header.klass).len));
                 }
                 context.pc = context.pc + 1;
             }
-            // istore/fstore/astore
-            0x36 | 0x38 | 0x3a => {
-                let opr = context.stack.code_at(context.pc + 1) as usize;
-                context.stack.store(opr, 1);
-                context.pc = context.pc + 2;
-            }
-            // lstore/dstore
-            0x37 | 0x39 => {
-                let opr = context.stack.code_at(context.pc + 1) as usize;
-                context.stack.store(opr, 2);
-                context.pc = context.pc + 2;
-            }
-            // istore 0 ~ 3
-            0x3b..=0x3e => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x3b;
-                context.stack.store(opr, 1);
-                context.pc = context.pc + 1;
-            }
-            // lstore 0 ~ 3
-            0x3f..=0x42 => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x3f;
-                context.stack.store(opr, 2);
-                context.pc = context.pc + 1;
-            }
-            // fstore 0 ~ 3
-            0x43..=0x46 => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x43;
-                context.stack.store(opr, 1);
-                context.pc = context.pc + 1;
-            }
-            // dstore 0 ~ 3
-            0x47..=0x4a => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x47;
-                context.stack.store(opr, 2);
-                context.pc = context.pc + 1;
-            }
-            // astore 0 ~ 3
-            0x4b..=0x4e => {
-                let opr = context.stack.code_at(context.pc) as usize - 0x4b;
-                context.stack.store(opr, 1);
+            Insn::Store(index, slots) => {
+                context.stack.store(*index, *slots);
                 context.pc = context.pc + 1;
             }
-            // iastore
-            0x4f => {
-                let v = context.stack.pop();
-                let array_idx = u32::from_le_bytes(context.stack.pop()) as usize;
-                let arrayref = u32::from_le_bytes(context.stack.pop()) as usize;
-                unsafe {
-                    let header = ObjHeader::from_vm_raw(Heap::ptr(arrayref));
-                    if array_idx >= header.size.unwrap() as usize {
-                        context.stack.upward(3);
-                        throw_vm_exception(context, "java/lang/ArrayIndexOutOfBoundsException");
+            // iastore, lastore, fastore, dastore, aastore, bastore, castore, sastore
+            Insn::ArrayStore(op) => {
+                let slots = match op {
+                    0x50 | 0x52 => 2,
+                    _ => 1,
+                };
+                let (element, klass) = match array_element(context, slots) {
+                    Some(found) => found,
+                    None => continue,
+                };
+                let klass = unsafe { &*klass };
+                if *op == 0x53 && *context.stack.top() != NULL {
+                    let value = Heap::as_obj(Ref::from_le_bytes(*context.stack.top()));
+                    let component = klass.component.as_ref().expect("array_component");
+                    if !unsafe { &*value.klass }.is_subtype_of(&component.name) {
+                        throw_vm_exception(context, "java/lang/ArrayStoreException");
                         continue;
                     }
-                    let offset = (&*header.klass).len * array_idx;
-                    Heap::ptr(arrayref + OBJ_HEADER_SIZE + offset)
-                        .copy_from(v.as_ptr(), (&*header.klass).len);
                 }
+                unsafe {
+                    match op {
+                        0x50 | 0x52 => element.copy_from(context.stack.pop_w().as_ptr(), 8),
+                        // boolean arrays share bastore with byte arrays
+                        0x54 if klass.name == "[Z" => element.write(context.stack.pop()[0] & 1),
+                        0x54 => element.write(context.stack.pop()[0]),
+                        0x55 | 0x56 => element.copy_from(context.stack.pop().as_ptr(), 2),
+                        _ => element.copy_from(context.stack.pop().as_ptr(), PTR_SIZE),
+                    }
+                }
+                context.stack.downward(2);
                 context.pc = context.pc + 1;
             }
-            // pop
-            0x57 => {
+            Insn::Pop => {
                 context.stack.pop();
                 context.pc = context.pc + 1;
             }
-            // pop2
-            0x58 => {
+            Insn::Pop2 => {
                 context.stack.pop_w();
                 context.pc = context.pc + 1;
             }
-            // dup
-            0x59 => {
-                unsafe {
-                    context
-                        .stack
-                        .operands()
-                        .copy_from(context.stack.operands().sub(PTR_SIZE), PTR_SIZE);
+            // dup, dup_x1, dup_x2, dup2, dup2_x1, dup2_x2
+            Insn::Dup(n, depth) => {
+                let (n, depth) = (*n, *depth);
+                let slots = (1..=depth)
+                    .rev()
+                    .map(|i| *context.stack.top_n(i))
+                    .collect::<Vec<_>>();
+                context.stack.downward(depth);
+                for slot in slots[depth - n..].iter().chain(slots.iter()) {
+                    context.stack.push(slot);
                 }
-                context.stack.upward(1);
+                context.pc = context.pc + 1;
+            }
+            Insn::Swap => {
+                let v1 = context.stack.pop();
+                let v2 = context.stack.pop();
+                context.stack.push(&v1);
+                context.stack.push(&v2);
                 context.pc = context.pc + 1;
             }
             // i/l/f/d +,-,*,/,%,<<,>>,>>>
-            0x60..=0x83 => {
-                let code = context.stack.code_at(context.pc);
+            Insn::Math(code) => {
+                // idiv, ldiv, irem, lrem
+                let divided_by_zero = match code {
+                    0x6c | 0x70 => i32::from_le_bytes(*context.stack.top()) == 0,
+                    0x6d | 0x71 => i64::from_le_bytes(*context.stack.top_w()) == 0,
+                    _ => false,
+                };
+                if divided_by_zero {
+                    throw_vm_exception(context, "java/lang/ArithmeticException");
+                    continue;
+                }
                 match code {
-                    0x60 => context.stack.bi_op(math_bi!(i32, i32, +)),
-                    0x61 => context.stack.bi_op_w(math_bi!(i64, i64, +)),
+                    0x60 => context.stack.bi_op(math_bi!(i32, i32, wrapping_add)),
+                    0x61 => context.stack.bi_op_w(math_bi!(i64, i64, wrapping_add)),
                     0x62 => context.stack.bi_op(math_bi!(f32, f32, +)),
                     0x63 => context.stack.bi_op_w(math_bi!(f64, f64, +)),
-                    0x64 => context.stack.bi_op(math_bi!(i32, i32, -)),
-                    0x65 => context.stack.bi_op_w(math_bi!(i64, i64, -)),
+                    0x64 => context.stack.bi_op(math_bi!(i32, i32, wrapping_sub)),
+                    0x65 => context.stack.bi_op_w(math_bi!(i64, i64, wrapping_sub)),
                     0x66 => context.stack.bi_op(math_bi!(f32, f32, -)),
                     0x67 => context.stack.bi_op_w(math_bi!(f64, f64, -)),
-                    0x68 => context.stack.bi_op(math_bi!(i32, i32, *)),
-                    0x69 => context.stack.bi_op_w(math_bi!(i64, i64, *)),
+                    0x68 => context.stack.bi_op(math_bi!(i32, i32, wrapping_mul)),
+                    0x69 => context.stack.bi_op_w(math_bi!(i64, i64, wrapping_mul)),
                     0x6a => context.stack.bi_op(math_bi!(f32, f32, *)),
                     0x6b => context.stack.bi_op_w(math_bi!(f64, f64, *)),
-                    0x6c => context.stack.bi_op(math_bi!(i32, i32, /)),
-                    0x6d => context.stack.bi_op_w(math_bi!(i64, i64, /)),
+                    // MIN_VALUE / -1 overflows to MIN_VALUE
+                    0x6c => context.stack.bi_op(math_bi!(i32, i32, wrapping_div)),
+                    0x6d => context.stack.bi_op_w(math_bi!(i64, i64, wrapping_div)),
                     0x6e => context.stack.bi_op(math_bi!(f32, f32, /)),
                     0x6f => context.stack.bi_op_w(math_bi!(f64, f64, /)),
-                    0x70 => context.stack.bi_op(math_bi!(i32, i32, %)),
-                    0x71 => context.stack.bi_op_w(math_bi!(i64, i64, %)),
+                    0x70 => context.stack.bi_op(math_bi!(i32, i32, wrapping_rem)),
+                    0x71 => context.stack.bi_op_w(math_bi!(i64, i64, wrapping_rem)),
+                    // truncating like fmod, as Java requires
                     0x72 => context.stack.bi_op(math_bi!(f32, f32, %)),
                     0x73 => context.stack.bi_op_w(math_bi!(f64, f64, %)),
-                    0x74 => context.stack.un_op(math_un!(i32, -)),
-                    0x75 => context.stack.un_op_w(math_un!(i64, -)),
+                    0x74 => context
+                        .stack
+                        .un_op(|a| i32::from_le_bytes(a).wrapping_neg().to_le_bytes()),
+                    0x75 => context
+                        .stack
+                        .un_op_w(|a| i64::from_le_bytes(a).wrapping_neg().to_le_bytes()),
                     0x76 => context.stack.un_op(math_un!(f32, -)),
                     0x77 => context.stack.un_op_w(math_un!(f64, -)),
-                    0x78 => context.stack.bi_op(math_bi!(i32, i32, <<)),
-                    0x7a => context.stack.bi_op(math_bi!(u32, u32, >>)),
-                    0x7c => context.stack.bi_op(math_bi!(i32, i32, >>)),
+                    // wrapping shifts mask the shift count by the bit width
+                    0x78 => context.stack.bi_op(math_bi!(i32, u32, wrapping_shl)),
+                    0x7a => context.stack.bi_op(math_bi!(i32, u32, wrapping_shr)),
+                    0x7c => context.stack.bi_op(math_bi!(u32, u32, wrapping_shr)),
                     0x79 => {
                         let s = u32::from_le_bytes(context.stack.pop());
                         context
                             .stack
-                            .un_op_w(|d| (i64::from_le_bytes(d) << s).to_le_bytes());
+                            .un_op_w(|d| i64::from_le_bytes(d).wrapping_shl(s).to_le_bytes());
                     }
                     0x7b => {
                         let s = u32::from_le_bytes(context.stack.pop());
                         context
                             .stack
-                            .un_op_w(|d| (u64::from_le_bytes(d) << s).to_le_bytes());
+                            .un_op_w(|d| i64::from_le_bytes(d).wrapping_shr(s).to_le_bytes());
                     }
                     0x7d => {
                         let s = u32::from_le_bytes(context.stack.pop());
                         context
                             .stack
-                            .un_op_w(|d| (i64::from_le_bytes(d) << s).to_le_bytes());
+                            .un_op_w(|d| u64::from_le_bytes(d).wrapping_shr(s).to_le_bytes());
                     }
                     0x7e => context.stack.bi_op(math_bi!(i32, i32, &)),
                     0x7f => context.stack.bi_op_w(math_bi!(i64, i64, &)),
@@ -328,25 +302,25 @@ pub fn execute(context: &mut ThreadContext) {
                 }
                 context.pc = context.pc + 1;
             }
-            // iinc
-            0x84 => {
-                let index = context.stack.code_at(context.pc + 1) as usize;
-                let cst = context.stack.code_at(context.pc + 2) as i32;
-                let new = i32::from_le_bytes(context.stack.get(index)) + cst;
-                context.stack.set(index, &new.to_le_bytes());
-                context.pc = context.pc + 3;
+            Insn::Iinc(index, cst) => {
+                let new = i32::from_le_bytes(context.stack.get(*index)).wrapping_add(*cst);
+                context.stack.set(*index, &new.to_le_bytes());
+                context.pc = context.pc + 1;
             }
             // i2l,i2f,i2d,l2i,l2f,l2d,f2i,f2l,f2d,d2i,d2l,d2f,i2b,i2c,i2s
-            0x85..=0x93 => {
-                let code = context.stack.code_at(context.pc);
+            Insn::Convert(code) => {
+                // float to integer casts saturate and map NaN to 0 as the JVMS requires
                 match code {
-                    // i2f, l2d, f2i, d2l, i2b, i2c, i2s
-                    0x86 | 0x8a | 0x8b | 0x8f | 0x91 | 0x92 | 0x93 => {}
                     // i2l
                     0x85 => {
                         let v = i32::from_le_bytes(context.stack.pop());
                         context.stack.push_w(&(v as i64).to_le_bytes());
                     }
+                    // i2f
+                    0x86 => {
+                        let v = i32::from_le_bytes(context.stack.pop());
+                        context.stack.push(&(v as f32).to_le_bytes());
+                    }
                     // i2d
                     0x87 => {
                         let v = i32::from_le_bytes(context.stack.pop());
@@ -362,6 +336,16 @@ pub fn execute(context: &mut ThreadContext) {
                         let v = i64::from_le_bytes(context.stack.pop_w());
                         context.stack.push(&(v as f32).to_le_bytes());
                     }
+                    // l2d
+                    0x8a => {
+                        let v = i64::from_le_bytes(context.stack.pop_w());
+                        context.stack.push_w(&(v as f64).to_le_bytes());
+                    }
+                    // f2i
+                    0x8b => {
+                        let v = f32::from_le_bytes(context.stack.pop());
+                        context.stack.push(&(v as i32).to_le_bytes());
+                    }
                     // f2l
                     0x8c => {
                         let v = f32::from_le_bytes(context.stack.pop());
@@ -377,218 +361,199 @@ pub fn execute(context: &mut ThreadContext) {
                         let v = f64::from_le_bytes(context.stack.pop_w());
                         context.stack.push(&(v as i32).to_le_bytes());
                     }
+                    // d2l
+                    0x8f => {
+                        let v = f64::from_le_bytes(context.stack.pop_w());
+                        context.stack.push_w(&(v as i64).to_le_bytes());
+                    }
                     // d2f
                     0x90 => {
                         let v = f64::from_le_bytes(context.stack.pop_w());
                         context.stack.push(&(v as f32).to_le_bytes());
                     }
+                    // i2b
+                    0x91 => {
+                        let v = i32::from_le_bytes(context.stack.pop());
+                        context.stack.push(&(v as i8 as i32).to_le_bytes());
+                    }
+                    // i2c
+                    0x92 => {
+                        let v = i32::from_le_bytes(context.stack.pop());
+                        context.stack.push(&(v as u16 as i32).to_le_bytes());
+                    }
+                    // i2s
+                    0x93 => {
+                        let v = i32::from_le_bytes(context.stack.pop());
+                        context.stack.push(&(v as i16 as i32).to_le_bytes());
+                    }
                     _ => unreachable!(),
                 }
                 context.pc = context.pc + 1;
             }
+            Insn::Lcmp => {
+                let v2 = i64::from_le_bytes(context.stack.pop_w());
+                let v1 = i64::from_le_bytes(context.stack.pop_w());
+                let v = compare(v1, v2, 0);
+                context.stack.push(&v.to_le_bytes());
+                context.pc = context.pc + 1;
+            }
+            // fcmpl, fcmpg
+            Insn::Fcmp(nan) => {
+                let v2 = f32::from_le_bytes(context.stack.pop());
+                let v1 = f32::from_le_bytes(context.stack.pop());
+                let v = compare(v1, v2, *nan);
+                context.stack.push(&v.to_le_bytes());
+                context.pc = context.pc + 1;
+            }
+            // dcmpl, dcmpg
+            Insn::Dcmp(nan) => {
+                let v2 = f64::from_le_bytes(context.stack.pop_w());
+                let v1 = f64::from_le_bytes(context.stack.pop_w());
+                let v = compare(v1, v2, *nan);
+                context.stack.push(&v.to_le_bytes());
+                context.pc = context.pc + 1;
+            }
             // ifeq, ifne, iflt, ifge, ifgt, ifle
-            0x99..=0x9e => {
+            Insn::If(cond, target) => {
                 let opr = i32::from_le_bytes(context.stack.pop());
-                if opr == 0 && instruction == 0x99
-                    || opr != 0 && instruction == 0x9a
-                    || opr < 0 && instruction == 0x9b
-                    || opr >= 0 && instruction == 0x9c
-                    || opr > 0 && instruction == 0x9d
-                    || opr <= 0 && instruction == 0x9e
-                {
-                    let jump = (context.stack.code_at(context.pc + 1) as U2) << 8
-                        | context.stack.code_at(context.pc + 2) as U2;
-                    context.pc = jump as usize;
-                } else {
-                    context.pc = context.pc + 3;
-                }
+                context.pc = branch(context.pc, cond.holds(opr), *target);
             }
             // if_icmp eq,ne,lt,ge,gt,le
-            0x9f..=0xa4 => {
-                let (v1, v2) = {
-                    let v2 = i32::from_le_bytes(context.stack.pop());
-                    let v1 = i32::from_le_bytes(context.stack.pop());
-                    (v1, v2)
-                };
-                let code = context.stack.code_at(context.pc);
-                let offset = if code == 0x9f && v1 == v2
-                    || code == 0xa0 && v1 != v2
-                    || code == 0xa1 && v1 < v2
-                    || code == 0xa2 && v1 >= v2
-                    || code == 0xa3 && v1 > v2
-                    || code == 0xa4 && v1 <= v2
-                {
-                    ((context.stack.code_at(context.pc + 1) as i16) << 8
-                        | context.stack.code_at(context.pc + 2) as i16) as isize
-                } else {
-                    3
-                };
-                context.pc = (context.pc as isize + offset) as usize;
+            Insn::IfCmp(cond, target) => {
+                let v2 = i32::from_le_bytes(context.stack.pop());
+                let v1 = i32::from_le_bytes(context.stack.pop());
+                context.pc = branch(context.pc, cond.holds(compare(v1, v2, 0)), *target);
             }
             // if_acmpeq, if_acmpne
-            0xa5 | 0xa6 => {
-                // TODO reference as u32
-                let (v1, v2) = {
-                    let v2 = Ref::from_le_bytes(context.stack.pop());
-                    let v1 = Ref::from_le_bytes(context.stack.pop());
-                    (v1, v2)
-                };
-                let code = context.stack.code_at(context.pc);
-                let offset = if code == 0xa5 && v1 == v2 || code == 0xa6 && v1 != v2 {
-                    ((context.stack.code_at(context.pc + 1) as i16) << 8
-                        | context.stack.code_at(context.pc + 2) as i16) as isize
+            Insn::IfACmp(cond, target) => {
+                let v2 = Ref::from_le_bytes(context.stack.pop());
+                let v1 = Ref::from_le_bytes(context.stack.pop());
+                context.pc = branch(context.pc, cond.holds((v1 != v2) as i32), *target);
+            }
+            // ifnull, ifnonnull
+            Insn::IfNull(cond, target) => {
+                let v = context.stack.pop();
+                context.pc = branch(context.pc, cond.holds((v != NULL) as i32), *target);
+            }
+            // goto, goto_w
+            Insn::Goto(target) => {
+                context.pc = *target;
+            }
+            // jsr, jsr_w, the return address is the index of the next instruction
+            Insn::Jsr(target) => {
+                context.stack.push(&((context.pc + 1) as u32).to_le_bytes());
+                context.pc = *target;
+            }
+            Insn::Ret(index) => {
+                context.pc = u32::from_le_bytes(context.stack.get(*index)) as usize;
+            }
+            Insn::TableSwitch {
+                low,
+                default,
+                targets,
+            } => {
+                let index = i32::from_le_bytes(context.stack.pop()) as i64 - *low as i64;
+                context.pc = if index < 0 || index >= targets.len() as i64 {
+                    *default
                 } else {
-                    3
+                    targets[index as usize]
                 };
-                context.pc = (context.pc as isize + offset) as usize;
             }
-            // goto
-            0xa7 => {
-                let offset = ((context.stack.code_at(context.pc + 1) as i16) << 8
-                    | context.stack.code_at(context.pc + 2) as i16)
-                    as i16;
-                context.pc = (context.pc as isize + offset as isize) as usize;
+            Insn::LookupSwitch { default, pairs } => {
+                let key = i32::from_le_bytes(context.stack.pop());
+                context.pc = match pairs.binary_search_by_key(&key, |(m, _)| *m) {
+                    Ok(i) => pairs[i].1,
+                    Err(_) => *default,
+                };
             }
             // ireturn/lreturn/freturn/dreturn/areturn/return
-            0xac..=0xb1 => {
-                context.pc = context.stack.return_normal();
-            }
-            // getstatic
-            0xb2 => {
-                let field_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let class = unsafe { context.stack.class_ptr().as_ref() }
-                    .expect("stack_class_pointer_null");
-                let (c, (f, t)) = class.constant_pool.get_javaref(field_idx);
-                let found = ClassArena::load_class(c, context);
-                if found.is_err() {
-                    throw_vm_exception(context, "java/lang/ClassNotFoundException");
-                    continue;
-                }
-                let (klass, initialized) = found.unwrap();
-                if !initialized {
-                    continue;
-                }
-                if let Some(ref field) = klass.bytecode.as_ref().unwrap().get_field(f, t) {
-                    match &field.value.get() {
-                        None => {
-                            throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
-                            continue;
-                        }
-                        Some(value) => match value {
-                            Value::DWord(_) => context.stack.push_w(&Value::of_w(*value)),
-                            _ => context.stack.push(&Value::of(*value)),
-                        },
+            Insn::Return => match context.stack.return_normal() {
+                Ok(pc) => context.pc = pc,
+                Err(error) => throw_vm_exception(context, &error),
+            },
+            Insn::GetStatic(idx) => {
+                let field = match resolve_static(context, *idx) {
+                    Some(field) => field,
+                    None => continue,
+                };
+                match &field.value.get() {
+                    None => {
+                        throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
+                        continue;
                     }
-                    context.pc = context.pc + 3;
-                } else {
-                    throw_vm_exception(context, "java/lang/NoSuchFieldError");
+                    Some(value) => match value {
+                        Value::DWord(_) => context.stack.push_w(&Value::of_w(*value)),
+                        _ => context.stack.push(&Value::of(*value)),
+                    },
                 }
+                context.pc = context.pc + 1;
             }
-            // putstatic
-            0xb3 => {
-                let field_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let (c, (f, t)) = context.stack.class().constant_pool.get_javaref(field_idx);
-                let (c, f, t) = (c.to_string(), f.to_string(), t.to_string());
-                let found = ClassArena::load_class(&c, context);
-                if found.is_err() {
-                    throw_vm_exception(context, "java/lang/ClassNotFoundException");
-                    continue;
-                }
-                let (klass, initialized) = found.unwrap();
-                if !initialized {
-                    continue;
-                }
-                if let Some(ref field) = klass.bytecode.as_ref().unwrap().get_field(&f, &t) {
-                    &field.value.set(match t.as_ref() {
-                        "D" | "J" => Some(Value::eval_w(context.stack.pop_w())),
-                        _ => Some(Value::eval(context.stack.pop(), &t)),
-                    });
-                    context.pc = context.pc + 3;
-                } else {
-                    throw_vm_exception(context, "java/lang/NoSuchFieldError");
-                }
+            Insn::PutStatic(idx) => {
+                let field = match resolve_static(context, *idx) {
+                    Some(field) => field,
+                    None => continue,
+                };
+                field.value.set(match field.descriptor.as_ref() {
+                    "D" | "J" => Some(Value::eval_w(context.stack.pop_w())),
+                    t => Some(Value::eval(context.stack.pop(), t)),
+                });
+                context.pc = context.pc + 1;
             }
-            // getfield
-            0xb4 => {
-                let field_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let (c, (f, t)) = context.stack.class().constant_pool.get_javaref(field_idx);
-                let (c, f, t) = (c.to_string(), f.to_string(), t.to_string());
-                let found = ClassArena::load_class(&c, context);
-                if found.is_err() {
-                    throw_vm_exception(context, "java/lang/ClassNotFoundException");
-                    continue;
-                }
-                let klass = found.unwrap().0;
+            Insn::GetField(idx, offset) | Insn::GetFieldWide(idx, offset) => {
+                let len = match instruction {
+                    Insn::GetFieldWide(..) => 2 * PTR_SIZE,
+                    _ => PTR_SIZE,
+                };
+                let offset = match field_offset(context, *idx, offset) {
+                    Some(offset) => offset,
+                    None => continue,
+                };
                 let objref = context.stack.pop();
                 if objref == NULL {
                     throw_vm_exception(context, "java/lang/NullPointerException");
                     continue;
                 }
                 let objref = u32::from_le_bytes(objref) as usize;
-                let found = klass.layout.get(&(c.as_ref(), f.as_ref(), t.as_ref()));
-                if found.is_none() {
-                    throw_vm_exception(context, "java/lang/NoSuchFieldError");
-                    continue;
-                }
-                let (offset, len) = found.unwrap();
                 unsafe {
-                    let target = Heap::ptr(objref + OBJ_HEADER_SIZE + *offset);
-                    context.stack.operands().copy_from(target, *len);
-                    context.stack.upward(*len / PTR_SIZE);
-                }
-                context.pc = context.pc + 3;
-            }
-            // putfield
-            0xb5 => {
-                let field_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let (c, (f, t)) = context.stack.class().constant_pool.get_javaref(field_idx);
-                let (c, f, t) = (c.to_string(), f.to_string(), t.to_string());
-                let found = ClassArena::load_class(&c, context);
-                if found.is_err() {
-                    throw_vm_exception(context, "java/lang/ClassNotFoundException");
-                    continue;
+                    let target = Heap::ptr(objref + OBJ_HEADER_SIZE + offset);
+                    context.stack.operands().copy_from(target, len);
+                    context.stack.upward(len / PTR_SIZE);
                 }
-                let klass = found.unwrap().0;
-                let objref = context.stack.pop();
+                context.pc = context.pc + 1;
+            }
+            Insn::PutField(idx, offset) | Insn::PutFieldWide(idx, offset) => {
+                let len = match instruction {
+                    Insn::PutFieldWide(..) => 2 * PTR_SIZE,
+                    _ => PTR_SIZE,
+                };
+                let offset = match field_offset(context, *idx, offset) {
+                    Some(offset) => offset,
+                    None => continue,
+                };
+                // the objectref is beneath the value
+                let objref = *context.stack.top_n(len / PTR_SIZE + 1);
                 if objref == NULL {
-                    context.stack.upward(PTR_SIZE);
                     throw_vm_exception(context, "java/lang/NullPointerException");
                     continue;
                 }
                 let objref = u32::from_le_bytes(objref) as usize;
-                let found = klass.layout.get(&(c.as_ref(), f.as_ref(), t.as_ref()));
-                if found.is_none() {
-                    throw_vm_exception(context, "java/lang/NoSuchFieldError");
-                    continue;
-                }
-                // FIXME stack -> heap
-                let (offset, len) = found.unwrap();
                 unsafe {
-                    let target = Heap::ptr(objref + OBJ_HEADER_SIZE + *offset);
-                    context.stack.downward(*len / PTR_SIZE);
-                    target.copy_from(context.stack.operands(), *len);
+                    let target = Heap::ptr(objref + OBJ_HEADER_SIZE + offset);
+                    context.stack.downward(len / PTR_SIZE);
+                    target.copy_from(context.stack.operands(), len);
                 }
-                context.pc = context.pc + 3;
-            }
-            // invokevirtual
-            0xb6 => invoke_virtual(context),
-            // invokespecial
-            0xb7 => invoke_special(context),
-            // invokestatic
-            0xb8 => invoke_static(context),
-            // invokeinterface
-            0xb9 => invoke_interface(context),
-            // invokeasync
-            0xd3 => {
-                let method_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
+                context.stack.downward(1);
+                context.pc = context.pc + 1;
+            }
+            Insn::InvokeVirtual(idx, cache) => invoke_virtual(context, *idx, cache),
+            Insn::InvokeSpecial(idx) => invoke_special(context, *idx),
+            Insn::InvokeStatic(idx) => invoke_static(context, *idx),
+            Insn::InvokeInterface(idx, cache) => invoke_interface(context, *idx, cache),
+            Insn::InvokeDynamic(idx) => invoke_dynamic(context, *idx),
+            Insn::InvokeAsync(idx) => {
                 let class =
                     unsafe { context.stack.class_ptr().as_ref() }.expect("class_pointer_null");
-                let (c, (m, t)) = class.constant_pool.get_javaref(method_idx);
+                let (c, (m, t)) = class.constant_pool.get_javaref(*idx);
                 let found = ClassArena::load_class(c, context);
                 if found.is_err() {
                     throw_vm_exception(context, "java/lang/ClassNotFoundException");
@@ -602,82 +567,63 @@ pub fn execute(context: &mut ThreadContext) {
                 std::thread::spawn(move || {
                     thread::ThreadGroup::new_thread(ctx_classloader, c, m, t, true);
                 });
-                context.pc = context.pc + 3;
-            }
-            // new
-            0xbb => {
-                let class_index = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let class_name = context
-                    .stack
-                    .class()
-                    .constant_pool
-                    .get_str(class_index)
-                    .to_owned();
-                let found = ClassArena::load_class(&class_name, context);
-                if found.is_err() {
-                    throw_vm_exception(context, "java/lang/ClassNotFoundException");
-                    continue;
-                }
-                let (klass, initialized) = found.unwrap();
-                if !initialized {
-                    continue;
-                }
+                context.pc = context.pc + 1;
+            }
+            Insn::New(idx) => {
+                let klass = match resolve_class(context, *idx) {
+                    Some(klass) => klass,
+                    None => continue,
+                };
                 let obj = Heap::allocate_object(&klass);
                 let v = obj.to_le_bytes();
                 context.stack.push(&v);
                 trace!("allocate object, addr: {}", obj);
-                context.pc = context.pc + 3;
-            }
-            // newarray
-            0xbc => {
-                let atype = match context.stack.code_at(context.pc + 1) {
-                    4 => "[Z",
-                    5 => "[C",
-                    6 => "[F",
-                    7 => "[D",
-                    8 => "[B",
-                    9 => "[S",
-                    10 => "[I",
-                    11 => "[J",
-                    _ => unreachable!(),
-                };
+                context.pc = context.pc + 1;
+            }
+            Insn::NewArray(atype) => {
                 let (klass, _) =
                     ClassArena::load_class(atype, context).expect("primitive_types_array");
-                let size = u32::from_le_bytes(context.stack.pop());
-                let array = Heap::allocate_array(&klass, size);
+                let size = i32::from_le_bytes(*context.stack.top());
+                if size < 0 {
+                    throw_vm_exception(context, "java/lang/NegativeArraySizeException");
+                    continue;
+                }
+                let array = Heap::allocate_array(&klass, size as u32);
                 if array.is_none() {
                     gc::gc();
                     continue;
                 }
                 let array = array.unwrap();
+                context.stack.pop();
                 let v = array.to_le_bytes();
                 context.stack.push(&v);
                 trace!("allocate array {}, addr:{}, size:{}", atype, array, size);
-                context.pc = context.pc + 2;
-            }
-            // anewarray
-            0xbd => {
-                let class_index = (context.stack.code_at(context.pc + 1) as U2) << 8
-                    | context.stack.code_at(context.pc + 2) as U2;
-                let class_name =
-                    "[".to_owned() + context.stack.class().constant_pool.get_str(class_index);
-                let found = ClassArena::load_class(&class_name, context);
-                if found.is_err() {
-                    throw_vm_exception(context, "java/lang/ClassNotFoundException");
-                    continue;
-                }
-                let (klass, initialized) = found.unwrap();
-                if !initialized {
+                context.pc = context.pc + 1;
+            }
+            Insn::ANewArray(idx) => {
+                let component = match resolve_class(context, *idx) {
+                    Some(component) => component,
+                    None => continue,
+                };
+                let class_name = if component.name.starts_with("[") {
+                    format!("[{}", component.name)
+                } else {
+                    format!("[L{};", component.name)
+                };
+                let (klass, _) =
+                    ClassArena::load_class(&class_name, context).expect("component_resolved");
+                let size = i32::from_le_bytes(*context.stack.top());
+                if size < 0 {
+                    throw_vm_exception(context, "java/lang/NegativeArraySizeException");
                     continue;
                 }
-                let size = u32::from_le_bytes(context.stack.pop());
-                let array = Heap::allocate_array(&klass, size);
+                let array = Heap::allocate_array(&klass, size as u32);
                 if array.is_none() {
                     gc::gc();
                     continue;
                 }
                 let array = array.unwrap();
+                context.stack.pop();
                 let v = array.to_le_bytes();
                 context.stack.push(&v);
                 trace!(
@@ -686,10 +632,9 @@ pub fn execute(context: &mut ThreadContext) {
                     array,
                     size
                 );
-                context.pc = context.pc + 3;
+                context.pc = context.pc + 1;
             }
-            // arraylength
-            0xbe => {
+            Insn::ArrayLength => {
                 let addr = context.stack.pop();
                 if addr == NULL {
                     throw_vm_exception(context, "java/lang/NullPointerException");
@@ -699,26 +644,114 @@ pub fn execute(context: &mut ThreadContext) {
                 context.stack.push(&array.size.unwrap().to_le_bytes());
                 context.pc = context.pc + 1;
             }
-            // athrow
-            0xbf => {
+            Insn::AThrow => {
+                context.backtrace = context.stack.backtrace(context.pc);
                 context.exception_pending = true;
                 context.throwable_initialized = true;
             }
-            _ => panic!(format!(
-                "Instruction 0x{:2x?} not implemented yet.",
-                instruction
-            )),
+            Insn::CheckCast(idx) => {
+                let objref = *context.stack.top();
+                if objref != NULL {
+                    let obj =
+                        ObjHeader::from_vm_raw(Heap::ptr(Ref::from_le_bytes(objref) as usize));
+                    let klass = unsafe { &*obj.klass };
+                    let target = context.stack.class().constant_pool.get_str(*idx);
+                    if !klass.is_subtype_of(target) {
+                        throw_vm_exception(context, "java/lang/ClassCastException");
+                        continue;
+                    }
+                }
+                context.pc = context.pc + 1;
+            }
+            Insn::InstanceOf(idx) => {
+                let objref = context.stack.pop();
+                let v = if objref == NULL {
+                    0i32
+                } else {
+                    let obj =
+                        ObjHeader::from_vm_raw(Heap::ptr(Ref::from_le_bytes(objref) as usize));
+                    let klass = unsafe { &*obj.klass };
+                    let target = context.stack.class().constant_pool.get_str(*idx);
+                    klass.is_subtype_of(target) as i32
+                };
+                context.stack.push(&v.to_le_bytes());
+                context.pc = context.pc + 1;
+            }
+            Insn::MonitorEnter => {
+                if *context.stack.top() == NULL {
+                    throw_vm_exception(context, "java/lang/NullPointerException");
+                    continue;
+                }
+                // the object stays on the operand stack as a root until it's locked
+                let obj = context.stack.top().as_ptr() as *mut Ref;
+                monitor::monitor_enter(obj, context.id, || {
+                    safepoint(&context.rx, &context.tx, || {
+                        let mut roots = context.roots();
+                        roots.push(obj);
+                        roots
+                    })
+                });
+                context.stack.pop();
+                context.pc = context.pc + 1;
+            }
+            Insn::MonitorExit => {
+                if *context.stack.top() == NULL {
+                    throw_vm_exception(context, "java/lang/NullPointerException");
+                    continue;
+                }
+                let objref = Ref::from_le_bytes(*context.stack.top());
+                if !monitor::monitor_exit(objref, context.id) {
+                    throw_vm_exception(context, "java/lang/IllegalMonitorStateException");
+                    continue;
+                }
+                context.stack.pop();
+                context.pc = context.pc + 1;
+            }
+            Insn::MultiANewArray(idx, dimensions) => {
+                let dimensions = *dimensions;
+                let klass = match resolve_class(context, *idx) {
+                    Some(klass) => klass,
+                    None => continue,
+                };
+                // count1 is the outermost dimension and the deepest one on the stack
+                let counts = (0..dimensions)
+                    .map(|i| i32::from_le_bytes(*context.stack.top_n(dimensions - i)))
+                    .collect::<Vec<_>>();
+                if counts.iter().any(|c| *c < 0) {
+                    throw_vm_exception(context, "java/lang/NegativeArraySizeException");
+                    continue;
+                }
+                let counts = counts.iter().map(|c| *c as u32).collect::<Vec<_>>();
+                let array = Heap::allocate_multi_array(&klass, &counts);
+                if array.is_none() {
+                    gc::gc();
+                    continue;
+                }
+                let array = array.unwrap();
+                context.stack.downward(dimensions);
+                context.stack.push(&array.to_le_bytes());
+                trace!(
+                    "allocate array {}, addr:{}, dimensions:{:?}",
+                    klass.name,
+                    array,
+                    counts
+                );
+                context.pc = context.pc + 1;
+            }
         }
     }
 }
 
-fn invoke_virtual(context: &mut ThreadContext) {
-    let method_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-        | context.stack.code_at(context.pc + 2) as U2;
-    let (_, (m, t)) = context.stack.class().constant_pool.get_javaref(method_idx);
-    // 0 for indicating non-static method
-    let (_, slots, _) = bytecode::resolve_method_descriptor(t, 0);
-
+fn invoke_virtual(context: &mut ThreadContext, method_idx: U2, cache: &InlineCache) {
+    let (_, resolved, slot, slots) = match resolve_method(context, method_idx) {
+        Some(resolved) => resolved,
+        None => return,
+    };
+    let method = unsafe { &*resolved.1 };
+    if method.is_static() {
+        throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
+        return;
+    }
     let addr = *context.stack.top_n(slots);
     if addr == NULL {
         throw_vm_exception(context, "java/lang/NullPointerException");
@@ -726,104 +759,441 @@ fn invoke_virtual(context: &mut ThreadContext) {
     }
     let obj = ObjHeader::from_vm_raw(Heap::ptr(u32::from_le_bytes(addr) as usize));
     let klass = unsafe { obj.klass.as_ref() }.expect("obj_klass_pointer_null");
-    if let Some(method) = klass.get_method_in_vtable(m, t) {
-        context.pc = context
-            .stack
-            .invoke((*method).0, (*method).1, context.pc + 3, slots);
-        return;
-    }
-    if let Some(method) = klass.bytecode.as_ref().unwrap().get_method(m, t) {
-        if !method.is_final() {
-            throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
+    // private methods and final methods overriding nothing have no slot
+    let slot = match slot {
+        Some(slot) if !method.is_private() => slot,
+        _ => {
+            let (class, method) = resolved;
+            invoke(context, class, method, slots);
             return;
         }
-        let class = Arc::as_ptr(&klass.bytecode.as_ref().unwrap());
-        let method = Arc::as_ptr(&method);
-        context.pc = context.stack.invoke(class, method, context.pc + 3, slots);
-        return;
+    };
+    let (class, method) = match cache.get(klass) {
+        Some(selected) => selected,
+        None => match select_virtual(klass, slot) {
+            Ok(selected) => {
+                cache.put(klass, selected);
+                selected
+            }
+            Err(error) => {
+                throw_vm_exception(context, error);
+                return;
+            }
+        },
+    };
+    invoke(context, class, method, slots);
+}
+
+// the method of `slot` in the vtable of the receiver class `klass`, or the error to throw
+fn select_virtual(klass: &Klass, slot: usize) -> Result<MethodRef, &'static str> {
+    let (class, method) = *klass
+        .vtable
+        .get(slot)
+        .ok_or("java/lang/NoSuchMethodError")?;
+    let (declaring, selected) = unsafe { (&*class, &*method) };
+    if selected.is_abstract() {
+        return Err("java/lang/AbstractMethodError");
+    }
+    // conflicting default methods are told by the itable
+    if declaring.is_interface() {
+        let (name, desc, _) = selected.get_name_and_descriptor();
+        if let Some(Err(error)) = klass.get_method_in_itable(declaring.get_name(), name, desc) {
+            return Err(error);
+        }
     }
-    throw_vm_exception(context, "java/lang/NoSuchMethodError");
+    Ok((class, method))
 }
 
-fn invoke_interface(context: &mut ThreadContext) {
-    let method_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-        | context.stack.code_at(context.pc + 2) as U2;
-    let (c, (m, t)) = context.stack.class().constant_pool.get_javaref(method_idx);
-    let (_, slots, _) = bytecode::resolve_method_descriptor(t, 0);
+fn invoke_interface(context: &mut ThreadContext, method_idx: U2, cache: &InlineCache) {
+    let (_, resolved, slot, slots) = match resolve_method(context, method_idx) {
+        Some(resolved) => resolved,
+        None => return,
+    };
+    let (ifs, method) = unsafe { (&*resolved.0, &*resolved.1) };
+    if method.is_static() {
+        throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
+        return;
+    }
     let addr = *context.stack.top_n(slots);
     if addr == NULL {
         throw_vm_exception(context, "java/lang/NullPointerException");
         return;
     }
+    // private interface methods and final methods of java.lang.Object are invoked as resolved
+    let slot = match slot {
+        Some(slot) if !method.is_private() => slot,
+        _ => {
+            invoke(context, resolved.0, resolved.1, slots);
+            return;
+        }
+    };
     let addr = u32::from_le_bytes(addr);
     let obj = ObjHeader::from_vm_raw(Heap::ptr(addr as usize));
     let klass = unsafe { obj.klass.as_ref() }.expect("obj_klass_pointer_null");
-    if let Some(method) = klass.get_method_in_itable(c, m, t) {
-        context.pc = context
-            .stack
-            .invoke((*method).0, (*method).1, context.pc + 5, slots);
+    let (class, method) = match cache.get(klass) {
+        Some(selected) => selected,
+        None => match select_interface(klass, ifs, slot) {
+            Ok(selected) => {
+                cache.put(klass, selected);
+                selected
+            }
+            Err(error) => {
+                throw_vm_exception(context, error);
+                return;
+            }
+        },
+    };
+    invoke(context, class, method, slots);
+}
+
+// the method of `slot` in the itable block of the interface `ifs` for the receiver class `klass`,
+// or the error to throw
+fn select_interface(klass: &Klass, ifs: &Class, slot: usize) -> Result<MethodRef, &'static str> {
+    // the public methods of java.lang.Object are found in the vtable
+    if !ifs.is_interface() {
+        return klass
+            .vtable
+            .get(slot)
+            .copied()
+            .ok_or("java/lang/NoSuchMethodError");
+    }
+    let (_, block) = klass
+        .itable
+        .iter()
+        .find(|(class, _)| std::ptr::eq(*class, ifs))
+        .ok_or("java/lang/IncompatibleClassChangeError")?;
+    *block.get(slot).ok_or("java/lang/NoSuchMethodError")?
+}
+
+/// prints the state of every invokevirtual and invokeinterface that has run, with its hits and
+/// misses in debug builds, which count them
+pub fn print_inline_caches() {
+    for klass in ClassArena::loaded() {
+        let class = match &klass.bytecode {
+            Some(class) => class,
+            None => continue,
+        };
+        for method in &class.methods {
+            let code = match method.decoded.read().unwrap().as_ref() {
+                Some(code) => Arc::clone(code),
+                None => continue,
+            };
+            for (pc, insn) in code.insns.iter().enumerate() {
+                let (idx, cache) = match insn {
+                    Insn::InvokeVirtual(idx, cache) | Insn::InvokeInterface(idx, cache) => {
+                        (idx, cache)
+                    }
+                    _ => continue,
+                };
+                let (state, counts) = cache.stats();
+                if state == "uninitialized" {
+                    continue;
+                }
+                let (c, (m, t)) = class.constant_pool.get_javaref(*idx);
+                println!(
+                    "{}.{}{} #{} {}.{}{}: {}{}",
+                    class.get_name(),
+                    method.name,
+                    method.descriptor,
+                    pc,
+                    c,
+                    m,
+                    t,
+                    state,
+                    counts
+                        .map(|(hits, misses)| format!(", {} hits, {} misses", hits, misses))
+                        .unwrap_or_default()
+                );
+            }
+        }
+    }
+}
+
+fn invoke_static(context: &mut ThreadContext, method_idx: U2) {
+    let (_, (class, method), _, slots) = match resolve_method(context, method_idx) {
+        Some(resolved) => resolved,
+        None => return,
+    };
+    if !unsafe { &*method }.is_static() {
+        throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
         return;
     }
-    throw_vm_exception(context, "java/lang/NoSuchMethodError");
+    invoke(context, class, method, slots);
 }
 
-fn invoke_static(context: &mut ThreadContext) {
-    let method_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-        | context.stack.code_at(context.pc + 2) as U2;
+fn invoke_special(context: &mut ThreadContext, method_idx: U2) {
+    let (klass, resolved, _, slots) = match resolve_method(context, method_idx) {
+        Some(resolved) => resolved,
+        None => return,
+    };
+    let klass = unsafe { &*klass };
     let class = unsafe { context.stack.class_ptr().as_ref() }.expect("class_pointer_null");
-    let (c, (m, t)) = class.constant_pool.get_javaref(method_idx);
-    let found = ClassArena::load_class(c, context);
-    if found.is_err() {
-        throw_vm_exception(context, "java/lang/ClassNotFoundException");
+    let (m, t, _) = unsafe { &*resolved.1 }.get_name_and_descriptor();
+    let c = klass.name.as_ref();
+    // private methods and constructors are invoked exactly as resolved
+    let exact = m == "<init>" || unsafe { &*resolved.1 }.is_private();
+    let is_interface = klass.bytecode.as_ref().map_or(false, |c| c.is_interface());
+    // JVMS 6.5, a superclass method is looked up from the direct superclass of the caller
+    let found = if !exact && c != class.get_name() && class.is_super() && !is_interface {
+        match ClassArena::load_class(class.get_super_class(), context) {
+            Ok((superclass, _)) if superclass.is_subtype_of(c) => select_special(&superclass, m, t),
+            _ => select_special(klass, m, t),
+        }
+    } else {
+        Some(resolved)
+    };
+    let (class, method) = match found {
+        Some(found) => found,
+        None => {
+            throw_vm_exception(context, "java/lang/NoSuchMethodError");
+            return;
+        }
+    };
+    let method = unsafe { &*method };
+    if method.is_static() {
+        throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
         return;
     }
-    let (klass, initialized) = found.unwrap();
-    if !initialized {
+    if method.is_abstract() {
+        throw_vm_exception(context, "java/lang/AbstractMethodError");
         return;
     }
-    let method = klass.bytecode.as_ref().unwrap().get_method(m, t);
-    if method.is_none() {
-        throw_vm_exception(context, "java/lang/NoSuchMethodError");
-        return;
+    invoke(context, class, method, slots);
+}
+
+// the first declaration along the superclasses of `klass`, otherwise a default method of one of
+// their direct superinterfaces
+fn select_special(klass: &Klass, m: &str, t: &str) -> Option<MethodRef> {
+    let declared = |klass: &Klass| {
+        klass.bytecode.as_ref().and_then(|class| {
+            class
+                .get_method(m, t)
+                .map(|method| (Arc::as_ptr(class), Arc::as_ptr(&method)))
+        })
+    };
+    let mut current = Some(klass);
+    while let Some(klass) = current {
+        if let Some(found) = declared(klass) {
+            return Some(found);
+        }
+        current = klass.superclass.as_deref();
     }
-    let method = method.unwrap();
-    if !method.is_static() {
-        throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
-        return;
+    let mut abstract_method = None;
+    let mut current = Some(klass);
+    while let Some(klass) = current {
+        for interface in &klass.superinterfaces {
+            if let Some((class, method)) = declared(interface) {
+                let selected = unsafe { &*method };
+                if selected.is_static() {
+                    continue;
+                }
+                if !selected.is_abstract() {
+                    return Some((class, method));
+                }
+                abstract_method = abstract_method.or(Some((class, method)));
+            }
+        }
+        current = klass.superclass.as_deref();
     }
-    let (_, desc, access_flag) = method.get_name_and_descriptor();
-    let (_, slots, _) = bytecode::resolve_method_descriptor(desc, access_flag);
-    let class = Arc::as_ptr(&klass.bytecode.as_ref().unwrap());
-    let method = Arc::as_ptr(&method);
-    context.pc = context.stack.invoke(class, method, context.pc + 3, slots);
+    abstract_method
 }
 
-fn invoke_special(context: &mut ThreadContext) {
-    let method_idx = (context.stack.code_at(context.pc + 1) as U2) << 8
-        | context.stack.code_at(context.pc + 2) as U2;
-    let class = unsafe { context.stack.class_ptr().as_ref() }.expect("class_pointer_null");
-    let (c, (m, t)) = class.constant_pool.get_javaref(method_idx);
-    let found = ClassArena::load_class(c, context);
-    if found.is_err() {
-        throw_vm_exception(context, "java/lang/ClassNotFoundException");
-        return;
+fn invoke_dynamic(context: &mut ThreadContext, idx: U2) {
+    let site = match CallSites::link(context, context.pc, idx) {
+        Ok(Some(site)) => site,
+        Ok(None) => return,
+        Err(error) => {
+            throw_vm_exception(context, &error);
+            return;
+        }
+    };
+    match &*site {
+        CallSite::Static(class, method) => {
+            let (_, desc, access_flag) = method.get_name_and_descriptor();
+            let (_, slots, _) = bytecode::resolve_method_descriptor(desc, access_flag);
+            invoke(context, Arc::as_ptr(class), Arc::as_ptr(method), slots);
+        }
+        CallSite::Lambda(klass) => {
+            let obj = Heap::allocate_object(klass);
+            // the captured arguments are stored in the fields by declaration order
+            let class = klass.bytecode.as_ref().unwrap();
+            let slots = class
+                .fields
+                .iter()
+                .map(|f| f.memory_size() / PTR_SIZE)
+                .sum::<usize>();
+            context.stack.downward(slots);
+            let mut captured = context.stack.operands();
+            for f in &class.fields {
+                let (offset, len) = klass
+                    .layout
+                    .get(&(class.get_name(), f.name.as_ref(), f.descriptor.as_ref()))
+                    .unwrap();
+                unsafe {
+                    Heap::ptr(obj as usize + OBJ_HEADER_SIZE + *offset).copy_from(captured, *len);
+                    captured = captured.add(*len);
+                }
+            }
+            context.stack.push(&obj.to_le_bytes());
+            context.pc = context.pc + 1;
+        }
+        CallSite::Concat(chunks) => match concat::concat(context, chunks) {
+            Some(string) => {
+                context.stack.push(&string.to_le_bytes());
+                context.pc = context.pc + 1;
+            }
+            None if context.exception_pending => {}
+            None => gc::gc(),
+        },
     }
-    let (klass, initialized) = found.unwrap();
-    // redundant check
-    if !initialized {
-        return;
+}
+
+// pushes the constant at `idx` of the current class
+fn load_constant(context: &mut ThreadContext, idx: U2) {
+    let class = unsafe { &*context.stack.class_ptr() };
+    let v = match class.constant_pool.get(idx) {
+        ConstantItem::Float(f) => f.to_le_bytes(),
+        ConstantItem::Integer(i) => i.to_le_bytes(),
+        _ => match constants::resolve(context, class, idx) {
+            Ok(Some(obj)) => obj.to_le_bytes(),
+            Ok(None) => return,
+            Err(error) => {
+                throw_vm_exception(context, &error);
+                return;
+            }
+        },
+    };
+    context.stack.push(&v);
+    context.pc = context.pc + 1;
+}
+
+// the class at `idx` of the current class, `None` if the instruction is to be retried or an
+// error is thrown
+fn resolve_class(context: &mut ThreadContext, idx: U2) -> Option<Arc<Klass>> {
+    let class = unsafe { &*context.stack.class_ptr() };
+    match pool::resolve_class(context, class, idx) {
+        Ok(klass) => klass,
+        Err(error) => {
+            throw_vm_exception(context, &error);
+            None
+        }
     }
-    let method = klass.bytecode.as_ref().unwrap().get_method(m, t);
-    if method.is_none() {
-        throw_vm_exception(context, "java/lang/NoSuchMethodError");
-        return;
+}
+
+// the offset of the instance field at `idx` of the current class, cached by the instruction,
+// like `resolve_class`
+fn field_offset(context: &mut ThreadContext, idx: U2, cached: &Offset) -> Option<usize> {
+    if let Some(offset) = cached.get() {
+        return Some(offset);
+    }
+    let (offset, _) = resolve_instance_field(context, idx)?;
+    cached.set(offset);
+    Some(offset)
+}
+
+// the offset and width of the instance field at `idx` of the current class, like `resolve_class`
+fn resolve_instance_field(context: &mut ThreadContext, idx: U2) -> Option<(usize, usize)> {
+    let class = unsafe { &*context.stack.class_ptr() };
+    match pool::resolve_field(context, class, idx) {
+        Ok(Some(Entry::Field(offset, len))) => Some((offset, len)),
+        Ok(Some(_)) => {
+            throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
+            None
+        }
+        Ok(None) => None,
+        Err(error) => {
+            throw_vm_exception(context, &error);
+            None
+        }
+    }
+}
+
+// the static field at `idx` of the current class, like `resolve_class`
+fn resolve_static(context: &mut ThreadContext, idx: U2) -> Option<Arc<Field>> {
+    let class = unsafe { &*context.stack.class_ptr() };
+    match pool::resolve_field(context, class, idx) {
+        Ok(Some(Entry::Static(field))) => Some(field),
+        Ok(Some(_)) => {
+            throw_vm_exception(context, "java/lang/IncompatibleClassChangeError");
+            None
+        }
+        Ok(None) => None,
+        Err(error) => {
+            throw_vm_exception(context, &error);
+            None
+        }
+    }
+}
+
+// the referenced class, the resolved method, its vtable slot and the slots of its arguments,
+// like `resolve_class`
+fn resolve_method(
+    context: &mut ThreadContext,
+    idx: U2,
+) -> Option<(*const Klass, MethodRef, Option<usize>, usize)> {
+    let class = unsafe { &*context.stack.class_ptr() };
+    match pool::resolve_method(context, class, idx) {
+        Ok(Some(Entry::Method(klass, method, slot, slots))) => Some((klass, method, slot, slots)),
+        Ok(_) => None,
+        Err(error) => {
+            throw_vm_exception(context, &error);
+            None
+        }
+    }
+}
+
+// checks the arrayref and index beneath `slots` of value, throws if the element is inaccessible
+fn array_element(context: &mut ThreadContext, slots: usize) -> Option<(*mut u8, *const Klass)> {
+    let arrayref = Ref::from_le_bytes(*context.stack.top_n(slots + 2));
+    let index = i32::from_le_bytes(*context.stack.top_n(slots + 1));
+    if arrayref == 0 {
+        throw_vm_exception(context, "java/lang/NullPointerException");
+        return None;
+    }
+    let header = Heap::as_obj(arrayref);
+    if index < 0 || index as u32 >= header.size.unwrap() {
+        throw_vm_exception(context, "java/lang/ArrayIndexOutOfBoundsException");
+        return None;
+    }
+    let len = unsafe { &*header.klass }.len;
+    let element = Heap::ptr(arrayref as usize + OBJ_HEADER_SIZE + index as usize * len);
+    Some((element, header.klass))
+}
+
+// the next pc of a conditional branch
+fn branch(pc: usize, taken: bool, target: usize) -> usize {
+    if taken {
+        target
+    } else {
+        pc + 1
+    }
+}
+
+// `nan` is pushed when the operands are unordered, -1 for *cmpl and 1 for *cmpg
+fn compare<T: PartialOrd>(v1: T, v2: T, nan: i32) -> i32 {
+    match v1.partial_cmp(&v2) {
+        Some(std::cmp::Ordering::Less) => -1,
+        Some(std::cmp::Ordering::Equal) => 0,
+        Some(std::cmp::Ordering::Greater) => 1,
+        None => nan,
+    }
+}
+
+// enters `method` with its `slots` of arguments on the stack to return after the current
+// instruction, the malformed code of a method throws VerifyError
+fn invoke(context: &mut ThreadContext, class: *const Class, method: *const Method, slots: usize) {
+    match context.invoke(class, method, context.pc + 1, slots) {
+        Ok(pc) => context.pc = pc,
+        Err(message) => {
+            warn!(
+                "{}.{}: {}",
+                unsafe { &*class }.get_name(),
+                unsafe { &*method }.name,
+                message
+            );
+            throw_vm_exception(context, "java/lang/VerifyError");
+        }
     }
-    let method = method.unwrap();
-    let (_, desc, access_flag) = method.get_name_and_descriptor();
-    let (_, slots, _) = bytecode::resolve_method_descriptor(desc, access_flag);
-    let class = Arc::as_ptr(&klass.bytecode.as_ref().unwrap());
-    let method = Arc::as_ptr(&method);
-    context.pc = context.stack.invoke(class, method, context.pc + 3, slots);
 }
 
 fn throw_vm_exception(context: &mut ThreadContext, error_class: &str) {
@@ -833,6 +1203,7 @@ fn throw_vm_exception(context: &mut ThreadContext, error_class: &str) {
     }
     let exception = Heap::allocate_object(&error).to_le_bytes();
     context.stack.push(&exception);
+    context.backtrace = context.stack.backtrace(context.pc);
     context.exception_pending = true;
     context.throwable_initialized = false;
 }
@@ -852,7 +1223,28 @@ fn handle_exception(context: &mut ThreadContext) {
             context.pc = pc;
             context.exception_pending = false;
         }
-        None => context.pc = context.stack.fire_exception(),
+        None => {
+            context.pc = match context.stack.fire_exception() {
+                Ok(pc) => pc,
+                Err(error) => {
+                    // thrown by the same frame in place of the pending exception
+                    context.stack.pop();
+                    context.exception_pending = false;
+                    throw_vm_exception(context, &error);
+                    return;
+                }
+            };
+            if context.stack.is_empty() {
+                eprintln!(
+                    "Exception in thread \"{}\" {}",
+                    context.id,
+                    error_klass.name.replace('/', ".")
+                );
+                for frame in &context.backtrace {
+                    eprintln!("\tat {}", frame);
+                }
+            }
+        }
     }
 }
 
@@ -867,3 +1259,1028 @@ pub fn test_shift() {
     assert_eq!(test as i32, sfr);
     assert_eq!(0x80000000, i32::MIN as u32);
 }
+
+#[cfg(test)]
+mod test {
+
+    use super::thread::ThreadContext;
+    use crate::bytecode::{
+        atom::*, attribute::Attribute, class::Class, code::Insn, method::Method,
+    };
+    use crate::mem::{
+        heap::{test::reset_heap, Heap},
+        klass::{test::define_object, Klass, ObjHeader, OBJ_HEADER_SIZE},
+        metaspace::test::define_class,
+        stack::JavaStack,
+        strings::test::define_string,
+        Ref, NULL, PTR_SIZE,
+    };
+    use std::sync::{atomic::AtomicU32, mpsc::channel, Arc, RwLock};
+
+    // magic, version 52.0, `public class Test` with no members
+    const EMPTY_CLASS: [u8; 34] = [
+        0xca, 0xfe, 0xba, 0xbe, 0x00, 0x00, 0x00, 0x34, 0x00, 0x03, 0x01, 0x00, 0x04, 0x54, 0x65,
+        0x73, 0x74, 0x07, 0x00, 0x01, 0x00, 0x21, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    pub fn method(code: Vec<U1>, max_locals: U2) -> Arc<Method> {
+        Arc::new(Method {
+            access_flag: crate::bytecode::METHOD_ACC_STATIC,
+            name: "test".to_owned(),
+            descriptor: "()V".to_owned(),
+            attributes: vec![Attribute::Code(
+                16,
+                max_locals,
+                Arc::new(code),
+                Arc::new(vec![]),
+                Arc::new(vec![]),
+            )],
+            decoded: RwLock::new(None),
+        })
+    }
+
+    /// run `code` as the body of a static method with `locals` as its local variables,
+    /// the method runs out of code instead of returning so its operands stay on the stack,
+    /// an uncaught exception is left on the stack of a caller
+    pub fn run<F, R>(code: Vec<U1>, locals: &[u8], f: F) -> R
+    where
+        F: FnOnce(&mut ThreadContext) -> R,
+    {
+        let class = Arc::new(Class::from_vec(EMPTY_CLASS.to_vec()).unwrap());
+        let method = method(code, (locals.len() / PTR_SIZE) as U2);
+        run_method(&class, &method, locals, f)
+    }
+
+    /// run `method` of `class` like `run`
+    pub fn run_method<F, R>(class: &Arc<Class>, method: &Arc<Method>, locals: &[u8], f: F) -> R
+    where
+        F: FnOnce(&mut ThreadContext) -> R,
+    {
+        let caller = self::method(vec![0x00], 0);
+        let (_sig_tx, sig_rx) = channel();
+        let (col_tx, _col_rx) = channel();
+        let mut context = ThreadContext {
+            pc: 0,
+            stack: JavaStack::new(1),
+            classloader: crate::mem::metaspace::ROOT_CLASSLOADER,
+            exception_pending: false,
+            throwable_initialized: false,
+            backtrace: vec![],
+            status: AtomicU32::new(super::thread::THREAD_RUNNING),
+            id: 1,
+            rx: sig_rx,
+            tx: col_tx,
+        };
+        context
+            .invoke(Arc::as_ptr(class), Arc::as_ptr(&caller), 0, 0)
+            .unwrap();
+        // return to the end of the caller
+        context
+            .invoke(Arc::as_ptr(class), Arc::as_ptr(method), 1, 0)
+            .unwrap();
+        for (i, slot) in locals.chunks(PTR_SIZE).enumerate() {
+            let mut v = [0u8; PTR_SIZE];
+            v.copy_from_slice(slot);
+            context.stack.set(i, &v);
+        }
+        super::execute(&mut context);
+        f(&mut context)
+    }
+
+    /// the class name of the uncaught exception
+    pub fn exception(context: &ThreadContext) -> Option<String> {
+        if !context.exception_pending {
+            return None;
+        }
+        let error = Heap::as_obj(Ref::from_le_bytes(*context.stack.top()));
+        Some(unsafe { &*error.klass }.name.clone())
+    }
+
+    fn cmp_long(v1: i64, v2: i64) -> i32 {
+        let locals = [v1.to_le_bytes(), v2.to_le_bytes()].concat();
+        // lload_0, lload_2, lcmp
+        run(vec![0x1e, 0x20, 0x94], &locals, |ctx| {
+            i32::from_le_bytes(ctx.stack.pop())
+        })
+    }
+
+    fn cmp_float(op: U1, v1: f32, v2: f32) -> i32 {
+        let locals = [v1.to_le_bytes(), v2.to_le_bytes()].concat();
+        // fload_0, fload_1, fcmp<op>
+        run(vec![0x22, 0x23, op], &locals, |ctx| {
+            i32::from_le_bytes(ctx.stack.pop())
+        })
+    }
+
+    fn cmp_double(op: U1, v1: f64, v2: f64) -> i32 {
+        let locals = [v1.to_le_bytes(), v2.to_le_bytes()].concat();
+        // dload_0, dload_2, dcmp<op>
+        run(vec![0x26, 0x28, op], &locals, |ctx| {
+            i32::from_le_bytes(ctx.stack.pop())
+        })
+    }
+
+    #[test]
+    pub fn test_lcmp() {
+        assert_eq!(0, cmp_long(0, 0));
+        assert_eq!(-1, cmp_long(1, 2));
+        assert_eq!(1, cmp_long(2, 1));
+        assert_eq!(-1, cmp_long(-1, 0));
+        assert_eq!(-1, cmp_long(i64::MIN, i64::MAX));
+        assert_eq!(1, cmp_long(i64::MAX, i64::MIN));
+        assert_eq!(0, cmp_long(i64::MIN, i64::MIN));
+        assert_eq!(1, cmp_long(i64::MIN + 1, i64::MIN));
+        assert_eq!(1, cmp_long(0, i64::MIN));
+    }
+
+    #[test]
+    pub fn test_fcmp() {
+        for &op in &[0x95, 0x96] {
+            assert_eq!(0, cmp_float(op, 1.5, 1.5));
+            assert_eq!(-1, cmp_float(op, 0.5, 1.5));
+            assert_eq!(1, cmp_float(op, 1.5, 0.5));
+            assert_eq!(0, cmp_float(op, 0.0, -0.0));
+            assert_eq!(0, cmp_float(op, -0.0, 0.0));
+            assert_eq!(-1, cmp_float(op, f32::NEG_INFINITY, f32::MIN));
+            assert_eq!(1, cmp_float(op, f32::INFINITY, f32::MAX));
+        }
+        assert_eq!(-1, cmp_float(0x95, f32::NAN, 0.0));
+        assert_eq!(-1, cmp_float(0x95, 0.0, f32::NAN));
+        assert_eq!(-1, cmp_float(0x95, f32::NAN, f32::NAN));
+        assert_eq!(1, cmp_float(0x96, f32::NAN, 0.0));
+        assert_eq!(1, cmp_float(0x96, 0.0, f32::NAN));
+        assert_eq!(1, cmp_float(0x96, f32::NAN, f32::NAN));
+    }
+
+    #[test]
+    pub fn test_dcmp() {
+        for &op in &[0x97, 0x98] {
+            assert_eq!(0, cmp_double(op, 1.5, 1.5));
+            assert_eq!(-1, cmp_double(op, 0.5, 1.5));
+            assert_eq!(1, cmp_double(op, 1.5, 0.5));
+            assert_eq!(0, cmp_double(op, 0.0, -0.0));
+            assert_eq!(0, cmp_double(op, -0.0, 0.0));
+            assert_eq!(-1, cmp_double(op, f64::NEG_INFINITY, f64::MIN));
+            assert_eq!(1, cmp_double(op, f64::INFINITY, f64::MAX));
+        }
+        assert_eq!(-1, cmp_double(0x97, f64::NAN, 0.0));
+        assert_eq!(-1, cmp_double(0x97, 0.0, f64::NAN));
+        assert_eq!(-1, cmp_double(0x97, f64::NAN, f64::NAN));
+        assert_eq!(1, cmp_double(0x98, f64::NAN, 0.0));
+        assert_eq!(1, cmp_double(0x98, 0.0, f64::NAN));
+        assert_eq!(1, cmp_double(0x98, f64::NAN, f64::NAN));
+    }
+
+    /// iload_0 then a switch whose default branch pushes 99 and whose i-th branch pushes i,
+    /// every branch jumps to the end of the code afterwards
+    fn switch(nops: usize, op: U1, header: &[i32], branches: usize) -> Vec<U1> {
+        let mut code = vec![0x00; nops];
+        code.push(0x1a);
+        let op_pc = code.len();
+        code.push(op);
+        while code.len() % 4 != 0 {
+            code.push(0x00);
+        }
+        // default, the header and one offset per branch
+        let blocks_start = code.len() + 4 * (header.len() + 1) + 4 * branches;
+        let end = blocks_start + 5 * (branches + 1);
+        // default
+        code.extend_from_slice(&((blocks_start - op_pc) as i32).to_be_bytes());
+        for (i, v) in header.iter().enumerate() {
+            code.extend_from_slice(&v.to_be_bytes());
+            if op == 0xab && i > 0 {
+                let target = blocks_start + 5 * i;
+                code.extend_from_slice(&((target - op_pc) as i32).to_be_bytes());
+            }
+        }
+        if op == 0xaa {
+            for i in 1..=branches {
+                let target = blocks_start + 5 * i;
+                code.extend_from_slice(&((target - op_pc) as i32).to_be_bytes());
+            }
+        }
+        assert_eq!(blocks_start, code.len());
+        for i in 0..=branches {
+            let v = if i == 0 { 99 } else { i as U1 };
+            let goto_pc = code.len() + 2;
+            code.extend_from_slice(&[0x10, v, 0xa7]);
+            code.extend_from_slice(&((end - goto_pc) as i16).to_be_bytes());
+        }
+        code
+    }
+
+    fn run_switch(code: &[U1], key: i32) -> i32 {
+        run(code.to_vec(), &key.to_le_bytes(), |ctx| {
+            i32::from_le_bytes(ctx.stack.pop())
+        })
+    }
+
+    #[test]
+    pub fn test_tableswitch() {
+        for nops in 0..4 {
+            // default, low, high
+            let code = switch(nops, 0xaa, &[-2, 1], 4);
+            assert_eq!(1, run_switch(&code, -2));
+            assert_eq!(2, run_switch(&code, -1));
+            assert_eq!(3, run_switch(&code, 0));
+            assert_eq!(4, run_switch(&code, 1));
+            assert_eq!(99, run_switch(&code, -3));
+            assert_eq!(99, run_switch(&code, 2));
+            assert_eq!(99, run_switch(&code, i32::MIN));
+            assert_eq!(99, run_switch(&code, i32::MAX));
+        }
+    }
+
+    #[test]
+    pub fn test_lookupswitch() {
+        for nops in 0..4 {
+            // npairs, then sorted keys
+            let code = switch(nops, 0xab, &[5, i32::MIN, -1000, -1, 7, 100000], 5);
+            assert_eq!(1, run_switch(&code, i32::MIN));
+            assert_eq!(2, run_switch(&code, -1000));
+            assert_eq!(3, run_switch(&code, -1));
+            assert_eq!(4, run_switch(&code, 7));
+            assert_eq!(5, run_switch(&code, 100000));
+            assert_eq!(99, run_switch(&code, 0));
+            assert_eq!(99, run_switch(&code, 8));
+            assert_eq!(99, run_switch(&code, -999));
+            assert_eq!(99, run_switch(&code, i32::MAX));
+        }
+        let empty = switch(1, 0xab, &[0], 0);
+        assert_eq!(99, run_switch(&empty, 0));
+    }
+
+    /// push local 0 then test it with the one-operand branch `op`
+    fn branch(op: U1, local: &[u8]) -> bool {
+        let code = vec![0x2a, op, 0x00, 0x07, 0x03, 0xa7, 0x00, 0x04, 0x04];
+        run(code, local, |ctx| i32::from_le_bytes(ctx.stack.pop()) == 1)
+    }
+
+    #[test]
+    pub fn test_if() {
+        let expected = [
+            (0x99, [false, true, false]),
+            (0x9a, [true, false, true]),
+            (0x9b, [true, false, false]),
+            (0x9c, [false, true, true]),
+            (0x9d, [false, false, true]),
+            (0x9e, [true, true, false]),
+        ];
+        for (op, results) in &expected {
+            for (v, result) in [-1i32, 0, 1].iter().zip(results) {
+                assert_eq!(*result, branch(*op, &v.to_le_bytes()), "0x{:x} {}", op, v);
+            }
+        }
+        assert!(branch(0xc6, &NULL));
+        assert!(!branch(0xc6, &4u32.to_le_bytes()));
+        assert!(!branch(0xc7, &NULL));
+        assert!(branch(0xc7, &4u32.to_le_bytes()));
+    }
+
+    #[test]
+    pub fn test_backward_branch() {
+        // for (sum = 0; n != 0; n--) sum += n;
+        let code = vec![
+            0x03, 0x3c, 0x1b, 0x1a, 0x60, 0x3c, 0x1a, 0x02, 0x60, 0x3b, 0x1a, 0x9a, 0xff, 0xf7,
+            0x1b,
+        ];
+        let mut locals = 100i32.to_le_bytes().to_vec();
+        locals.extend_from_slice(&NULL);
+        let sum = run(code, &locals, |ctx| i32::from_le_bytes(ctx.stack.pop()));
+        assert_eq!(5050, sum);
+    }
+
+    #[test]
+    pub fn test_subroutine() {
+        let locals = [0u8; PTR_SIZE * 2];
+        // jsr sub; iconst_2; goto end; sub: astore_1; bipush 40; ret 1; end:
+        let code = vec![
+            0xa8, 0x00, 0x07, 0x05, 0xa7, 0x00, 0x08, 0x4c, 0x10, 0x28, 0xa9, 0x01,
+        ];
+        let (v, from_sub) = run(code, &locals, |ctx| {
+            (
+                i32::from_le_bytes(ctx.stack.pop()),
+                i32::from_le_bytes(ctx.stack.pop()),
+            )
+        });
+        assert_eq!((2, 40), (v, from_sub));
+        // the same with jsr_w and goto_w
+        let code = vec![
+            0xc9, 0x00, 0x00, 0x00, 0x0b, 0x05, 0xc8, 0x00, 0x00, 0x00, 0x0a, 0x4c, 0x10, 0x28,
+            0xa9, 0x01,
+        ];
+        let (v, from_sub, ret) = run(code, &locals, |ctx| {
+            (
+                i32::from_le_bytes(ctx.stack.pop()),
+                i32::from_le_bytes(ctx.stack.pop()),
+                u32::from_le_bytes(ctx.stack.get(1)),
+            )
+        });
+        // the return address is the index of the instruction after jsr_w
+        assert_eq!((2, 40, 1), (v, from_sub, ret));
+    }
+
+    /// load `values` then run `op` on them, returns the top `slots` slots of the stack
+    fn apply(op: U1, values: &[Vec<u8>], slots: usize) -> Vec<u8> {
+        let mut code = vec![];
+        let mut index = 0;
+        for v in values {
+            code.push(if v.len() == PTR_SIZE { 0x15 } else { 0x16 });
+            code.push(index as U1);
+            index += v.len() / PTR_SIZE;
+        }
+        code.push(op);
+        run(code, &values.concat(), |ctx| {
+            let mut slots = (0..slots).map(|_| ctx.stack.pop()).collect::<Vec<_>>();
+            slots.reverse();
+            slots.concat()
+        })
+    }
+
+    #[test]
+    pub fn test_stack_manipulation() {
+        let int = |v: i32| v.to_le_bytes().to_vec();
+        let long = |v: i64| v.to_le_bytes().to_vec();
+        let (l1, l2, l3) = (
+            long(0x1111_2222_3333_4444),
+            long(-0x5555_6666_7777_8888),
+            long(i64::MIN + 3),
+        );
+        let (i1, i2, i3, i4) = (int(1), int(-2), int(3), int(i32::MAX));
+        let cases = vec![
+            // dup
+            (0x59, vec![&i1], vec![&i1, &i1]),
+            // dup_x1
+            (0x5a, vec![&i1, &i2], vec![&i2, &i1, &i2]),
+            // dup_x2
+            (0x5b, vec![&i1, &i2, &i3], vec![&i3, &i1, &i2, &i3]),
+            (0x5b, vec![&l1, &i3], vec![&i3, &l1, &i3]),
+            // dup2
+            (0x5c, vec![&i1, &i2], vec![&i1, &i2, &i1, &i2]),
+            (0x5c, vec![&l1], vec![&l1, &l1]),
+            // dup2_x1
+            (0x5d, vec![&i1, &i2, &i3], vec![&i2, &i3, &i1, &i2, &i3]),
+            (0x5d, vec![&i1, &l2], vec![&l2, &i1, &l2]),
+            // dup2_x2
+            (
+                0x5e,
+                vec![&i1, &i2, &i3, &i4],
+                vec![&i3, &i4, &i1, &i2, &i3, &i4],
+            ),
+            (0x5e, vec![&i1, &i2, &l3], vec![&l3, &i1, &i2, &l3]),
+            (0x5e, vec![&l1, &i3, &i4], vec![&i3, &i4, &l1, &i3, &i4]),
+            (0x5e, vec![&l1, &l2], vec![&l2, &l1, &l2]),
+            // swap
+            (0x5f, vec![&i1, &i2], vec![&i2, &i1]),
+        ];
+        for (op, values, expected) in cases {
+            let values = values.into_iter().cloned().collect::<Vec<_>>();
+            let expected = expected.into_iter().cloned().collect::<Vec<_>>();
+            let expected = expected.concat();
+            let slots = expected.len() / PTR_SIZE;
+            assert_eq!(expected, apply(op, &values, slots), "0x{:x}", op);
+        }
+    }
+
+    #[test]
+    pub fn test_arithmetic() {
+        let i = |v: i32| v.to_le_bytes().to_vec();
+        let l = |v: i64| v.to_le_bytes().to_vec();
+        let f = |v: f32| v.to_le_bytes().to_vec();
+        let d = |v: f64| v.to_le_bytes().to_vec();
+        let cases = vec![
+            // iadd, ladd, fadd, dadd
+            (0x60, vec![i(i32::MAX), i(1)], i(i32::MIN)),
+            (0x61, vec![l(i64::MAX), l(2)], l(i64::MIN + 1)),
+            (0x62, vec![f(1.5), f(-0.25)], f(1.25)),
+            (0x63, vec![d(f64::MAX), d(f64::MAX)], d(f64::INFINITY)),
+            // isub, lsub, fsub, dsub
+            (0x64, vec![i(3), i(5)], i(-2)),
+            (0x64, vec![i(i32::MIN), i(1)], i(i32::MAX)),
+            (0x65, vec![l(3), l(5)], l(-2)),
+            (0x66, vec![f(3.0), f(5.0)], f(-2.0)),
+            (0x67, vec![d(3.0), d(5.0)], d(-2.0)),
+            // imul, lmul, fmul, dmul
+            (0x68, vec![i(0x10000), i(0x10000)], i(0)),
+            (0x68, vec![i(-7), i(6)], i(-42)),
+            (0x69, vec![l(i64::MIN), l(-1)], l(i64::MIN)),
+            (0x6a, vec![f(-2.0), f(0.0)], f(-0.0)),
+            (0x6b, vec![d(1.5), d(4.0)], d(6.0)),
+            // idiv, ldiv, fdiv, ddiv
+            (0x6c, vec![i(7), i(-2)], i(-3)),
+            (0x6c, vec![i(i32::MIN), i(-1)], i(i32::MIN)),
+            (0x6d, vec![l(-7), l(2)], l(-3)),
+            (0x6d, vec![l(i64::MIN), l(-1)], l(i64::MIN)),
+            (0x6e, vec![f(1.0), f(-0.0)], f(f32::NEG_INFINITY)),
+            (0x6f, vec![d(1.0), d(4.0)], d(0.25)),
+            // irem, lrem, frem, drem
+            (0x70, vec![i(-7), i(2)], i(-1)),
+            (0x70, vec![i(7), i(-2)], i(1)),
+            (0x70, vec![i(i32::MIN), i(-1)], i(0)),
+            (0x71, vec![l(-7), l(3)], l(-1)),
+            (0x71, vec![l(i64::MIN), l(-1)], l(0)),
+            (0x72, vec![f(-5.5), f(2.0)], f(-1.5)),
+            (0x73, vec![d(5.5), d(-2.0)], d(1.5)),
+            // ineg, lneg, fneg, dneg
+            (0x74, vec![i(i32::MIN)], i(i32::MIN)),
+            (0x74, vec![i(5)], i(-5)),
+            (0x75, vec![l(i64::MIN)], l(i64::MIN)),
+            (0x76, vec![f(0.0)], f(-0.0)),
+            (0x77, vec![d(-2.5)], d(2.5)),
+            // ishl, lshl
+            (0x78, vec![i(1), i(33)], i(2)),
+            (0x78, vec![i(1), i(31)], i(i32::MIN)),
+            (0x78, vec![i(3), i(-1)], i(i32::MIN)),
+            (0x79, vec![l(1), i(65)], l(2)),
+            (0x79, vec![l(1), i(63)], l(i64::MIN)),
+            // ishr, lshr
+            (0x7a, vec![i(-16), i(2)], i(-4)),
+            (0x7a, vec![i(i32::MIN), i(32)], i(i32::MIN)),
+            (0x7b, vec![l(-16), i(2)], l(-4)),
+            (0x7b, vec![l(i64::MIN), i(127)], l(-1)),
+            // iushr, lushr
+            (0x7c, vec![i(-1), i(28)], i(0xf)),
+            (0x7c, vec![i(-1), i(0x20)], i(-1)),
+            (0x7d, vec![l(-1), i(60)], l(0xf)),
+            (0x7d, vec![l(i64::MIN), i(0x7f)], l(1)),
+            // iand, land, ior, lor, ixor, lxor
+            (0x7e, vec![i(0b1100), i(0b1010)], i(0b1000)),
+            (0x7f, vec![l(-1), l(0x1234_5678_9abc)], l(0x1234_5678_9abc)),
+            (0x80, vec![i(0b1100), i(0b1010)], i(0b1110)),
+            (0x81, vec![l(i64::MIN), l(1)], l(i64::MIN + 1)),
+            (0x82, vec![i(0b1100), i(0b1010)], i(0b0110)),
+            (0x83, vec![l(-1), l(0)], l(-1)),
+            // i2l, i2f, i2d
+            (0x85, vec![i(-1)], l(-1)),
+            (0x86, vec![i(16777217)], f(16777216.0)),
+            (0x87, vec![i(i32::MIN)], d(-2147483648.0)),
+            // l2i, l2f, l2d
+            (0x88, vec![l(0x1_8000_0000)], i(i32::MIN)),
+            (0x89, vec![l(-3)], f(-3.0)),
+            (0x8a, vec![l(i64::MIN)], d(-9223372036854775808.0)),
+            // f2i, f2l, f2d
+            (0x8b, vec![f(-1.9)], i(-1)),
+            (0x8b, vec![f(f32::NAN)], i(0)),
+            (0x8b, vec![f(1e10)], i(i32::MAX)),
+            (0x8b, vec![f(f32::NEG_INFINITY)], i(i32::MIN)),
+            (0x8c, vec![f(f32::NAN)], l(0)),
+            (0x8c, vec![f(-1e20)], l(i64::MIN)),
+            (0x8d, vec![f(0.5)], d(0.5)),
+            // d2i, d2l, d2f
+            (0x8e, vec![d(f64::NAN)], i(0)),
+            (0x8e, vec![d(-1e10)], i(i32::MIN)),
+            (0x8e, vec![d(2.99)], i(2)),
+            (0x8f, vec![d(f64::INFINITY)], l(i64::MAX)),
+            (0x8f, vec![d(f64::NAN)], l(0)),
+            (0x8f, vec![d(-2.5)], l(-2)),
+            (0x90, vec![d(1e300)], f(f32::INFINITY)),
+            // i2b, i2c, i2s
+            (0x91, vec![i(0x180)], i(-128)),
+            (0x91, vec![i(0x7f)], i(127)),
+            (0x92, vec![i(-1)], i(0xffff)),
+            (0x92, vec![i(0x12345)], i(0x2345)),
+            (0x93, vec![i(0x18000)], i(-32768)),
+            (0x93, vec![i(0x7fff)], i(32767)),
+        ];
+        for op in 0x60..=0x93 {
+            assert!(
+                op == 0x84 || cases.iter().any(|(o, _, _)| *o == op),
+                "0x{:x} is not covered",
+                op
+            );
+        }
+        for (op, values, expected) in cases {
+            let slots = expected.len() / PTR_SIZE;
+            assert_eq!(expected, apply(op, &values, slots), "0x{:x}", op);
+        }
+    }
+
+    #[test]
+    pub fn test_divided_by_zero() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let arithmetic = Some("java/lang/ArithmeticException".to_owned());
+        let int = [7i32.to_le_bytes(), 0i32.to_le_bytes()].concat();
+        for &op in &[0x6c, 0x70] {
+            let e = run(vec![0x1a, 0x1b, op], &int, |ctx| exception(ctx));
+            assert_eq!(arithmetic, e);
+        }
+        let long = [7i64.to_le_bytes(), 0i64.to_le_bytes()].concat();
+        for &op in &[0x6d, 0x71] {
+            let e = run(vec![0x1e, 0x20, op], &long, |ctx| exception(ctx));
+            assert_eq!(arithmetic, e);
+        }
+    }
+
+    // javac --release 8 -g
+    //
+    // public class Lines {
+    //     static int divide(int a, int b) {
+    //         int c = a + b;
+    //         return c / b;
+    //     }
+    // }
+    const LINES: &'static str = "yv66vgAAADQAFgoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQAFTGluZXMBAARDb2RlAQAPTGluZU51bWJlclRhYmxlAQASTG9jYWxWYXJpYWJsZVRhYmxlAQAEdGhpcwEAB0xMaW5lczsBAAZkaXZpZGUBAAUoSUkpSQEAAWEBAAFJAQABYgEAAWMBAApTb3VyY2VGaWxlAQAKTGluZXMuamF2YQAhAAcAAgAAAAAAAgABAAUABgABAAkAAAAvAAEAAQAAAAUqtwABsQAAAAIACgAAAAYAAQAAAAEACwAAAAwAAQAAAAUADAANAAAACAAOAA8AAQAJAAAASgACAAMAAAAIGhtgPRwbbKwAAAACAAoAAAAKAAIAAAADAAQABAALAAAAIAADAAAACAAQABEAAAAAAAgAEgARAAEABAAEABMAEQACAAEAFAAAAAIAFQ==";
+
+    #[test]
+    pub fn test_exception_line() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let class = Arc::new(Class::from_vec(base64::decode(LINES).unwrap()).unwrap());
+        let method = class.get_method("divide", "(II)I").unwrap();
+        let locals = [7i32.to_le_bytes(), 0i32.to_le_bytes(), 0i32.to_le_bytes()].concat();
+        let (e, backtrace) = run_method(&class, &method, &locals, |ctx| {
+            (exception(ctx), ctx.backtrace.clone())
+        });
+        assert_eq!(Some("java/lang/ArithmeticException".to_owned()), e);
+        // the idiv on line 4, called from the end of the caller
+        assert_eq!(
+            vec!["Lines.divide(Lines.java:4)", "Lines.test(Lines.java)"],
+            backtrace
+        );
+    }
+
+    #[test]
+    pub fn test_wide() {
+        let mut locals = vec![0u8; PTR_SIZE * 300];
+        locals[..PTR_SIZE].copy_from_slice(&5i32.to_le_bytes());
+        locals[PTR_SIZE * 299..].copy_from_slice(&7i32.to_le_bytes());
+        let code = vec![
+            // wide iload 299; wide iinc 299 -1000; wide iload 299
+            0xc4, 0x15, 0x01, 0x2b, 0xc4, 0x84, 0x01, 0x2b, 0xfc, 0x18, 0xc4, 0x15, 0x01, 0x2b,
+            // iinc 0 -1; iload_0
+            0x84, 0x00, 0xff, 0x1a, // lconst_1; wide lstore 297; wide lload 297
+            0x0a, 0xc4, 0x37, 0x01, 0x29, 0xc4, 0x16, 0x01, 0x29,
+        ];
+        let values = run(code, &locals, |ctx| {
+            let long = i64::from_le_bytes(ctx.stack.pop_w());
+            let mut ints = (0..3)
+                .map(|_| i32::from_le_bytes(ctx.stack.pop()))
+                .collect::<Vec<_>>();
+            ints.reverse();
+            (ints, long)
+        });
+        assert_eq!((vec![7, -993, 4], 1), values);
+        // jsr sub; iconst_2; goto end; sub: wide astore 298; wide ret 298; end:
+        let code = vec![
+            0xa8, 0x00, 0x07, 0x05, 0xa7, 0x00, 0x0b, 0xc4, 0x3a, 0x01, 0x2a, 0xc4, 0xa9, 0x01,
+            0x2a,
+        ];
+        let v = run(code, &locals, |ctx| i32::from_le_bytes(ctx.stack.pop()));
+        assert_eq!(2, v);
+    }
+
+    fn new_array(component: Arc<Klass>, descriptor: &str, size: u32) -> Ref {
+        let klass = define_class(Klass::new_array_klass(
+            &format!("[{}", descriptor),
+            component,
+        ));
+        Heap::allocate_array(&klass, size).unwrap()
+    }
+
+    fn new_primitive_array(descriptor: &str, size: u32) -> Ref {
+        let component = define_class(Klass::new_phantom_klass(descriptor));
+        new_array(component, descriptor, size)
+    }
+
+    pub fn define_exceptions() {
+        for e in &[
+            "java/lang/NullPointerException",
+            "java/lang/ArrayIndexOutOfBoundsException",
+            "java/lang/ArrayStoreException",
+            "java/lang/ArithmeticException",
+            "java/lang/BootstrapMethodError",
+            "java/lang/NoClassDefFoundError",
+            "java/lang/IncompatibleClassChangeError",
+            "java/lang/AbstractMethodError",
+        ] {
+            define_class(Klass::new_phantom_klass(e));
+        }
+    }
+
+    /// store `value` into `array[index]` then load it back
+    fn store_and_load(
+        store: U1,
+        load: U1,
+        array: Ref,
+        index: i32,
+        value: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let value_load = match store {
+            0x50 => 0x20,
+            0x51 => 0x24,
+            0x52 => 0x28,
+            0x53 => 0x2c,
+            _ => 0x1c,
+        };
+        let locals = [&array.to_le_bytes()[..], &index.to_le_bytes(), value].concat();
+        // aload_0, iload_1, <load value>, <store>, aload_0, iload_1, <load>
+        let code = vec![0x2a, 0x1b, value_load, store, 0x2a, 0x1b, load];
+        run(code, &locals, |ctx| match exception(ctx) {
+            Some(e) => Err(e),
+            None => Ok(match load {
+                0x2f | 0x31 => ctx.stack.pop_w().to_vec(),
+                _ => ctx.stack.pop().to_vec(),
+            }),
+        })
+    }
+
+    fn load_element(op: U1, array: Ref, index: i32) -> Result<Vec<u8>, String> {
+        let locals = [array.to_le_bytes(), index.to_le_bytes()].concat();
+        run(vec![0x2a, 0x1b, op], &locals, |ctx| match exception(ctx) {
+            Some(e) => Err(e),
+            None => Ok(ctx.stack.pop().to_vec()),
+        })
+    }
+
+    #[test]
+    pub fn test_primitive_array() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let int = |v: i32| v.to_le_bytes().to_vec();
+
+        let bytes = new_primitive_array("B", 3);
+        assert_eq!(
+            Ok(int(-128)),
+            store_and_load(0x54, 0x33, bytes, 2, &int(0x180))
+        );
+        assert_eq!(
+            Ok(int(127)),
+            store_and_load(0x54, 0x33, bytes, 0, &int(127))
+        );
+        assert_eq!(Ok(int(0)), load_element(0x33, bytes, 1));
+        let booleans = new_primitive_array("Z", 2);
+        assert_eq!(Ok(int(1)), store_and_load(0x54, 0x33, booleans, 1, &int(3)));
+        assert_eq!(Ok(int(0)), store_and_load(0x54, 0x33, booleans, 0, &int(2)));
+
+        let chars = new_primitive_array("C", 4);
+        assert_eq!(
+            Ok(int(0xffff)),
+            store_and_load(0x55, 0x34, chars, 3, &int(-1))
+        );
+        assert_eq!(
+            Ok(int(0x1234)),
+            store_and_load(0x55, 0x34, chars, 0, &int(0x51234))
+        );
+        let shorts = new_primitive_array("S", 4);
+        assert_eq!(
+            Ok(int(-1)),
+            store_and_load(0x56, 0x35, shorts, 3, &int(0xffff))
+        );
+        assert_eq!(
+            Ok(int(i16::MIN as i32)),
+            store_and_load(0x56, 0x35, shorts, 1, &int(0x8000))
+        );
+
+        let ints = new_primitive_array("I", 5);
+        assert_eq!(
+            Ok(int(i32::MIN)),
+            store_and_load(0x4f, 0x2e, ints, 4, &int(i32::MIN))
+        );
+        assert_eq!(Ok(int(0)), load_element(0x2e, ints, 3));
+        let floats = new_primitive_array("F", 5);
+        let f = 1.5f32.to_le_bytes().to_vec();
+        assert_eq!(Ok(f.clone()), store_and_load(0x51, 0x30, floats, 4, &f));
+
+        let longs = new_primitive_array("J", 3);
+        let l = i64::MIN.to_le_bytes().to_vec();
+        assert_eq!(Ok(l.clone()), store_and_load(0x50, 0x2f, longs, 2, &l));
+        let l = (-2i64).to_le_bytes().to_vec();
+        assert_eq!(Ok(l.clone()), store_and_load(0x50, 0x2f, longs, 0, &l));
+        let doubles = new_primitive_array("D", 3);
+        let d = f64::MAX.to_le_bytes().to_vec();
+        assert_eq!(Ok(d.clone()), store_and_load(0x52, 0x31, doubles, 1, &d));
+        // neighbours are untouched
+        assert_eq!(
+            Ok(vec![0u8; 8]),
+            store_and_load(0x52, 0x31, doubles, 2, &[0u8; 8])
+        );
+        assert_eq!(Ok(int(-2)), load_element(0x2e, longs, 0));
+    }
+
+    #[test]
+    pub fn test_array_exceptions() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let aioobe = Err("java/lang/ArrayIndexOutOfBoundsException".to_owned());
+        let npe = Err("java/lang/NullPointerException".to_owned());
+        let ints = new_primitive_array("I", 5);
+        let longs = new_primitive_array("J", 2);
+        let value = 1i32.to_le_bytes();
+        let wide = 1i64.to_le_bytes();
+        for &(load, store) in &[(0x2e, 0x4f), (0x33, 0x54), (0x34, 0x55), (0x35, 0x56)] {
+            let array = match load {
+                0x2e => ints,
+                _ => new_primitive_array(if load == 0x34 { "C" } else { "B" }, 5),
+            };
+            assert_eq!(aioobe, load_element(load, array, 5));
+            assert_eq!(aioobe, load_element(load, array, -1));
+            assert_eq!(aioobe, store_and_load(store, load, array, 5, &value));
+            assert_eq!(aioobe, store_and_load(store, load, array, i32::MIN, &value));
+            assert_eq!(npe, load_element(load, 0, 0));
+            assert_eq!(npe, store_and_load(store, load, 0, 0, &value));
+        }
+        assert_eq!(aioobe, store_and_load(0x50, 0x2f, longs, 2, &wide));
+        assert_eq!(npe, store_and_load(0x52, 0x31, 0, 0, &wide));
+        let empty = new_primitive_array("I", 0);
+        assert_eq!(aioobe, load_element(0x2e, empty, 0));
+    }
+
+    #[test]
+    pub fn test_reference_array() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let object = define_object();
+        let mut number = Klass::new_phantom_klass("java/lang/Number");
+        number.superclass = Some(object.clone());
+        let number = define_class(number);
+        let mut integer = Klass::new_phantom_klass("java/lang/Integer");
+        integer.superclass = Some(number.clone());
+        let integer = define_class(integer);
+        let string = define_string();
+
+        let numbers = new_array(number.clone(), "Ljava/lang/Number;", 3);
+        let i = Heap::allocate_object(&integer).to_le_bytes().to_vec();
+        let s = Heap::allocate_object(&string).to_le_bytes().to_vec();
+        assert_eq!(Ok(i.clone()), store_and_load(0x53, 0x32, numbers, 0, &i));
+        assert_eq!(
+            Err("java/lang/ArrayStoreException".to_owned()),
+            store_and_load(0x53, 0x32, numbers, 1, &s)
+        );
+        assert_eq!(Ok(vec![0u8; 4]), load_element(0x32, numbers, 1));
+        assert_eq!(
+            Ok(vec![0u8; 4]),
+            store_and_load(0x53, 0x32, numbers, 0, &[0u8; 4])
+        );
+
+        let objects = new_array(object.clone(), "Ljava/lang/Object;", 2);
+        assert_eq!(Ok(s.clone()), store_and_load(0x53, 0x32, objects, 1, &s));
+        let ints = new_primitive_array("I", 1).to_le_bytes().to_vec();
+        assert_eq!(
+            Ok(ints.clone()),
+            store_and_load(0x53, 0x32, objects, 0, &ints)
+        );
+
+        let int_array = define_class(Klass::new_phantom_klass("[I"));
+        let int_arrays = new_array(int_array, "[I", 2);
+        assert_eq!(
+            Ok(ints.clone()),
+            store_and_load(0x53, 0x32, int_arrays, 0, &ints)
+        );
+        let longs = new_primitive_array("J", 1).to_le_bytes().to_vec();
+        assert_eq!(
+            Err("java/lang/ArrayStoreException".to_owned()),
+            store_and_load(0x53, 0x32, int_arrays, 1, &longs)
+        );
+        assert_eq!(
+            Err("java/lang/ArrayIndexOutOfBoundsException".to_owned()),
+            store_and_load(0x53, 0x32, int_arrays, 2, &ints)
+        );
+    }
+
+    // javac --release 8 -g:none, `SpecialParent.other` was made abstract after `Special` was compiled
+    //
+    // public class SpecialBase {
+    //     public int value() { return 1; }
+    // }
+    // public abstract class SpecialParent extends SpecialBase {
+    //     public abstract int other();
+    // }
+    // public interface Greeter {
+    //     default int greet() { return 1000; }
+    // }
+    // public class Special extends SpecialParent implements Greeter {
+    //     public int value() { return super.value() + 10; }
+    //     private int secret() { return 100; }
+    //     public int greet() { return Greeter.super.greet() + 1; }
+    //     public int other() { return super.other(); }
+    //     public static int run() { Special s = new Special(); return s.value() + s.secret() + s.greet(); }
+    //     public static int abstractCall() { return new Special().other(); }
+    // }
+    const SPECIAL_BASE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQALU3BlY2lhbEJhc2UBAARDb2RlAQAFdmFsdWUBAAMoKUkAIQAHAAIAAAAAAAIAAQAFAAYAAQAJAAAAEQABAAEAAAAFKrcAAbEAAAAAAAEACgALAAEACQAAAA4AAQABAAAAAgSsAAAAAAAA";
+
+    const SPECIAL_PARENT: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAtTcGVjaWFsQmFzZQEABjxpbml0PgEAAygpVgcACAEADVNwZWNpYWxQYXJlbnQBAARDb2RlAQAFb3RoZXIBAAMoKUkEIQAHAAIAAAAAAAIAAQAFAAYAAQAJAAAAEQABAAEAAAAFKrcAAbEAAAAABAEACgALAAAAAA==";
+
+    const GREETER: &'static str = "yv66vgAAADQACAcAAgEAB0dyZWV0ZXIHAAQBABBqYXZhL2xhbmcvT2JqZWN0AQAFZ3JlZXQBAAMoKUkBAARDb2RlBgEAAQADAAAAAAABAAEABQAGAAEABwAAABAAAQABAAAABBED6KwAAAAAAAA=";
+
+    const SPECIAL: &'static str = "yv66vgAAADQAHwoAAgADBwAEDAAFAAYBAA1TcGVjaWFsUGFyZW50AQAGPGluaXQ+AQADKClWCgACAAgMAAkACgEABXZhbHVlAQADKClJCwAMAA0HAA4MAA8ACgEAB0dyZWV0ZXIBAAVncmVldAoAAgARDAASAAoBAAVvdGhlcgcAFAEAB1NwZWNpYWwKABMAAwoAEwAICgATABgMABkACgEABnNlY3JldAoAEwANCgATABEBAARDb2RlAQADcnVuAQAMYWJzdHJhY3RDYWxsACEAEwACAAEADAAAAAcAAQAFAAYAAQAcAAAAEQABAAEAAAAFKrcAAbEAAAAAAAEACQAKAAEAHAAAABQAAgABAAAACCq3AAcQCmCsAAAAAAACABkACgABABwAAAAPAAEAAQAAAAMQZKwAAAAAAAEADwAKAAEAHAAAABMAAgABAAAAByq3AAsEYKwAAAAAAAEAEgAKAAEAHAAAABEAAQABAAAABSq3ABCsAAAAAAAJAB0ACgABABwAAAAjAAIAAQAAABe7ABNZtwAVSyq2ABYqtwAXYCq2ABpgrAAAAAAACQAeAAoAAQAcAAAAFwACAAAAAAALuwATWbcAFbYAG6wAAAAAAAA=";
+
+    #[test]
+    pub fn test_synchronized() {
+        let _heap = reset_heap();
+        let object = define_object();
+        let class = Arc::new(Class::from_vec(EMPTY_CLASS.to_vec()).unwrap());
+        // synchronized void test() { return; }
+        let synchronized = Arc::new(Method {
+            access_flag: 0x0020,
+            name: "test".to_owned(),
+            descriptor: "()V".to_owned(),
+            attributes: vec![Attribute::Code(
+                1,
+                1,
+                Arc::new(vec![0xb1]),
+                Arc::new(vec![]),
+                Arc::new(vec![]),
+            )],
+            decoded: RwLock::new(None),
+        });
+        let (_, rx) = channel();
+        let mut context = ThreadContext::new(1, 0, rx, channel().0);
+        let caller = method(vec![0x00], 0);
+        context
+            .invoke(Arc::as_ptr(&class), Arc::as_ptr(&caller), 0, 0)
+            .unwrap();
+        let this = Heap::allocate_object(&object);
+        context.stack.push(&this.to_le_bytes());
+        context
+            .invoke(Arc::as_ptr(&class), Arc::as_ptr(&synchronized), 1, 1)
+            .unwrap();
+        let locked = |obj: Ref| !ObjHeader::from_vm_raw(Heap::ptr(obj as usize)).is_lock_free();
+        assert!(locked(this));
+        // the locked object is a root even when its local is overwritten
+        context.stack.set(0, &NULL);
+        let root = context
+            .stack
+            .collect_tracing_roots()
+            .into_iter()
+            .find(|root| unsafe { **root } == this)
+            .unwrap();
+        // moved by the collector, the mark word goes along
+        let moved = Heap::allocate_object(&object);
+        unsafe {
+            Heap::ptr(moved as usize).copy_from(Heap::ptr(this as usize), OBJ_HEADER_SIZE);
+            *root = moved;
+        }
+        // the frame unlocks where the object is now
+        context.stack.return_normal().unwrap();
+        assert!(!locked(moved));
+        assert!(locked(this));
+        // a monitor exited unbalanced is an error of the return, thrown once
+        context.stack.push(&moved.to_le_bytes());
+        context
+            .invoke(Arc::as_ptr(&class), Arc::as_ptr(&synchronized), 1, 1)
+            .unwrap();
+        assert!(crate::mem::monitor::monitor_exit(moved, 1));
+        assert_eq!(
+            Err("java/lang/IllegalMonitorStateException".to_owned()),
+            context.stack.return_normal()
+        );
+        assert_eq!(Ok(1), context.stack.return_normal());
+    }
+
+    #[test]
+    pub fn test_invoke_special() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let define = |bytecode: &str, superclass: &Arc<Klass>, interfaces: Vec<Arc<Klass>>| {
+            let class = Arc::new(Class::from_vec(base64::decode(bytecode).unwrap()).unwrap());
+            define_class(Klass::new(
+                class,
+                0,
+                Some(Arc::clone(superclass)),
+                interfaces,
+            ))
+        };
+        let object = define_object();
+        let base = define(SPECIAL_BASE, &object, vec![]);
+        let parent = define(SPECIAL_PARENT, &base, vec![]);
+        let greeter = define(GREETER, &object, vec![]);
+        let special = define(SPECIAL, &parent, vec![greeter]);
+        let call = |name: &str| {
+            let class = special.bytecode.as_ref().unwrap();
+            let method = class.get_method(name, "()I").unwrap();
+            run_method(class, &method, &[], |ctx| match exception(ctx) {
+                Some(e) => Err(e),
+                None => Ok(i32::from_le_bytes(ctx.stack.pop())),
+            })
+        };
+        assert_eq!(Ok(1112), call("run"));
+        assert_eq!(
+            Err("java/lang/AbstractMethodError".to_owned()),
+            call("abstractCall")
+        );
+    }
+
+    // javac --release 11 -g:none, the default `Right.side` was added after `Clicker` was compiled
+    //
+    // public interface Counter {
+    //     int base();
+    //     default int count() { return twice(base()) + offset(); }
+    //     private int twice(int x) { return x * 2; }
+    //     static int offset() { return 7; }
+    // }
+    // public interface Left { default int side() { return 1; } }
+    // public interface Right { default int side() { return 2; } }
+    // public class Clicker implements Counter, Left, Right {
+    //     public int base() { return 5; }
+    //     public static int run() { Counter c = new Clicker(); return c.count(); }
+    //     public static int conflict() { Left l = new Clicker(); return l.side(); }
+    // }
+    const COUNTER: &'static str = "yv66vgAAADcAEgsAAgADBwAEDAAFAAYBAAdDb3VudGVyAQAEYmFzZQEAAygpSQsAAgAIDAAJAAoBAAV0d2ljZQEABChJKUkLAAIADAwADQAGAQAGb2Zmc2V0BwAPAQAQamF2YS9sYW5nL09iamVjdAEABWNvdW50AQAEQ29kZQYBAAIADgAAAAAABAQBAAUABgAAAAEAEAAGAAEAEQAAAB0AAgABAAAAESoquQABAQC5AAcCALgAC2CsAAAAAAACAAkACgABABEAAAAQAAIAAgAAAAQbBWisAAAAAAAJAA0ABgABABEAAAAPAAEAAAAAAAMQB6wAAAAAAAA=";
+
+    const LEFT: &'static str = "yv66vgAAADcACAcAAgEABExlZnQHAAQBABBqYXZhL2xhbmcvT2JqZWN0AQAEc2lkZQEAAygpSQEABENvZGUGAQABAAMAAAAAAAEAAQAFAAYAAQAHAAAADgABAAEAAAACBKwAAAAAAAA=";
+
+    const RIGHT: &'static str = "yv66vgAAADcACAcAAgEABVJpZ2h0BwAEAQAQamF2YS9sYW5nL09iamVjdAEABHNpZGUBAAMoKUkBAARDb2RlBgEAAQADAAAAAAABAAEABQAGAAEABwAAAA4AAQABAAAAAgWsAAAAAAAA";
+
+    const CLICKER: &'static str = "yv66vgAAADcAGwoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQAHQ2xpY2tlcgoABwADCwALAAwHAA0MAA4ADwEAB0NvdW50ZXIBAAVjb3VudAEAAygpSQsAEQASBwATDAAUAA8BAARMZWZ0AQAEc2lkZQcAFgEABVJpZ2h0AQAEQ29kZQEABGJhc2UBAANydW4BAAhjb25mbGljdAAhAAcAAgADAAsAEQAVAAAABAABAAUABgABABcAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAYAA8AAQAXAAAADgABAAEAAAACCKwAAAAAAAkAGQAPAAEAFwAAABsAAgABAAAAD7sAB1m3AAlLKrkACgEArAAAAAAACQAaAA8AAQAXAAAAGwACAAEAAAAPuwAHWbcACUsquQAQAQCsAAAAAAAA";
+
+    #[test]
+    pub fn test_invoke_interface() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let define = |bytecode: &str, interfaces: Vec<Arc<Klass>>| {
+            let class = Arc::new(Class::from_vec(base64::decode(bytecode).unwrap()).unwrap());
+            define_class(Klass::new(class, 0, Some(define_object()), interfaces))
+        };
+        let interfaces = vec![
+            define(COUNTER, vec![]),
+            define(LEFT, vec![]),
+            define(RIGHT, vec![]),
+        ];
+        let clicker = define(CLICKER, interfaces);
+        let call = |name: &str| {
+            let class = clicker.bytecode.as_ref().unwrap();
+            let method = class.get_method(name, "()I").unwrap();
+            run_method(class, &method, &[], |ctx| match exception(ctx) {
+                Some(e) => Err(e),
+                None => Ok(i32::from_le_bytes(ctx.stack.pop())),
+            })
+        };
+        assert_eq!(Ok(17), call("run"));
+        assert_eq!(
+            Err("java/lang/IncompatibleClassChangeError".to_owned()),
+            call("conflict")
+        );
+    }
+
+    // javac --release 8 -g:none, each of the static methods has one invokevirtual
+    //
+    // public abstract class IcShape {
+    //     public abstract int area();
+    //     public static int mono() {
+    //         IcShape[] shapes = { new IcOne(), new IcOne(), new IcOne() };
+    //         int total = 0;
+    //         for (IcShape s : shapes) total += s.area();
+    //         return total;
+    //     }
+    //     // the same loop over { IcOne, IcTwo, IcOne, IcTwo }
+    //     public static int poly() { ... }
+    //     // the same loop over { IcOne, IcTwo, IcThree, IcFour, IcFive, IcOne }
+    //     public static int mega() { ... }
+    // }
+    // class IcOne extends IcShape { public int area() { return 1; } }
+    // and IcTwo to IcFive returning 2 to 5
+
+    const IC_SHAPE: &'static str = "yv66vgAAADQAIwoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQAHSWNTaGFwZQcACgEABUljT25lCgAJAAMKAAcADQwADgAPAQAEYXJlYQEAAygpSQcAEQEABUljVHdvCgAQAAMHABQBAAdJY1RocmVlCgATAAMHABcBAAZJY0ZvdXIKABYAAwcAGgEABkljRml2ZQoAGQADAQAEQ29kZQEABG1vbm8BAA1TdGFja01hcFRhYmxlBwAgAQAKW0xJY1NoYXBlOwEABHBvbHkBAARtZWdhBCEABwACAAAAAAAFAAEABQAGAAEAHAAAABEAAQABAAAABSq3AAGxAAAAAAQBAA4ADwAAAAkAHQAPAAEAHAAAAHAABQAGAAAASQa9AAdZA7sACVm3AAtTWQS7AAlZtwALU1kFuwAJWbcAC1NLAzwqTSy+PgM2BBUEHaIAFywVBDI6BRsZBbYADGA8hAQBp//pG6wAAAABAB4AAAAVAAL/AC0ABQcAHwEHAB8BAQAA+AAZAAkAIQAPAAEAHAAAAHoABQAGAAAAUwe9AAdZA7sACVm3AAtTWQS7ABBZtwASU1kFuwAJWbcAC1NZBrsAEFm3ABJTSwM8Kk0svj4DNgQVBB2iABcsFQQyOgUbGQW2AAxgPIQEAaf/6RusAAAAAQAeAAAAFQAC/wA3AAUHAB8BBwAfAQEAAPgAGQAJACIADwABABwAAACPAAUABgAAAGgQBr0AB1kDuwAJWbcAC1NZBLsAEFm3ABJTWQW7ABNZtwAVU1kGuwAWWbcAGFNZB7sAGVm3ABtTWQi7AAlZtwALU0sDPCpNLL4+AzYEFQQdogAXLBUEMjoFGxkFtgAMYDyEBAGn/+kbrAAAAAEAHgAAABUAAv8ATAAFBwAfAQcAHwEBAAD4ABkAAA==";
+
+    const IC_ONE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAdJY1NoYXBlAQAGPGluaXQ+AQADKClWBwAIAQAFSWNPbmUBAARDb2RlAQAEYXJlYQEAAygpSQAgAAcAAgAAAAAAAgAAAAUABgABAAkAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAKAAsAAQAJAAAADgABAAEAAAACBKwAAAAAAAA=";
+
+    const IC_TWO: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAdJY1NoYXBlAQAGPGluaXQ+AQADKClWBwAIAQAFSWNUd28BAARDb2RlAQAEYXJlYQEAAygpSQAgAAcAAgAAAAAAAgAAAAUABgABAAkAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAKAAsAAQAJAAAADgABAAEAAAACBawAAAAAAAA=";
+
+    const IC_THREE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAdJY1NoYXBlAQAGPGluaXQ+AQADKClWBwAIAQAHSWNUaHJlZQEABENvZGUBAARhcmVhAQADKClJACAABwACAAAAAAACAAAABQAGAAEACQAAABEAAQABAAAABSq3AAGxAAAAAAABAAoACwABAAkAAAAOAAEAAQAAAAIGrAAAAAAAAA==";
+
+    const IC_FOUR: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAdJY1NoYXBlAQAGPGluaXQ+AQADKClWBwAIAQAGSWNGb3VyAQAEQ29kZQEABGFyZWEBAAMoKUkAIAAHAAIAAAAAAAIAAAAFAAYAAQAJAAAAEQABAAEAAAAFKrcAAbEAAAAAAAEACgALAAEACQAAAA4AAQABAAAAAgesAAAAAAAA";
+
+    const IC_FIVE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAdJY1NoYXBlAQAGPGluaXQ+AQADKClWBwAIAQAGSWNGaXZlAQAEQ29kZQEABGFyZWEBAAMoKUkAIAAHAAIAAAAAAAIAAAAFAAYAAQAJAAAAEQABAAEAAAAFKrcAAbEAAAAAAAEACgALAAEACQAAAA4AAQABAAAAAgisAAAAAAAA";
+
+    #[test]
+    pub fn test_inline_cache() {
+        let _heap = reset_heap();
+        define_exceptions();
+        let define = |bytecode: &str, superclass: Arc<Klass>| {
+            let class = Arc::new(Class::from_vec(base64::decode(bytecode).unwrap()).unwrap());
+            define_class(Klass::new(class, 0, Some(superclass), vec![]))
+        };
+        let shape = define(IC_SHAPE, define_object());
+        for bytecode in &[IC_ONE, IC_TWO, IC_THREE, IC_FOUR, IC_FIVE] {
+            define(bytecode, Arc::clone(&shape));
+        }
+        let call = |name: &str| {
+            let class = shape.bytecode.as_ref().unwrap();
+            let method = class.get_method(name, "()I").unwrap();
+            let total = run_method(class, &method, &[], |ctx| {
+                assert_eq!(None, exception(ctx));
+                i32::from_le_bytes(ctx.stack.pop())
+            });
+            let code = method
+                .decoded
+                .read()
+                .unwrap()
+                .as_ref()
+                .map(Arc::clone)
+                .unwrap();
+            let stats = code.insns.iter().find_map(|insn| match insn {
+                Insn::InvokeVirtual(_, cache) => Some(cache.stats()),
+                _ => None,
+            });
+            (total, stats.unwrap())
+        };
+        // release builds don't count
+        let counted = |hits, misses| Some((hits, misses)).filter(|_| cfg!(debug_assertions));
+        assert_eq!((3, ("monomorphic", counted(2, 1))), call("mono"));
+        assert_eq!((6, ("polymorphic", counted(2, 2))), call("poly"));
+        // a megamorphic site looks every receiver up
+        assert_eq!((16, ("megamorphic", counted(0, 6))), call("mega"));
+    }
+}
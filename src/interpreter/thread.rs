@@ -1,3 +1,4 @@
+use crate::bytecode::{class::Class, method::Method};
 use crate::interpreter;
 use crate::mem::{metaspace::*, stack::*, *};
 use std::cell::RefCell;
@@ -42,7 +43,7 @@ impl ThreadGroup {
     pub fn new_thread(classloader: Ref, class_name: &str, method_name: &str, method_descriptor: &str, init: bool) {
         let context = {
             let mut threads = jvm_threads!().threads.lock().unwrap();
-            let id = *threads.deref().keys().last().unwrap_or(&0);
+            let id = *threads.deref().keys().last().unwrap_or(&0) + 1;
             let (sig_tx, sig_rx) = channel();
             let (col_tx, col_rx) = channel();
             let thread = ThreadContext::new(id, classloader, sig_rx, col_tx);
@@ -53,7 +54,7 @@ impl ThreadGroup {
         };
         let mut context = context.borrow_mut();
         let class = match ClassArena::load_class(class_name, &mut context) {
-            Err(no_class) => panic!(format!("ClassNotFoundException: {}", no_class)),
+            Err(error) => panic!(format!("{}: {}", error, class_name)),
             Ok((class, _)) => class,
         };
         if init {
@@ -65,13 +66,18 @@ impl ThreadGroup {
             .unwrap()
             .get_method(method_name, method_descriptor)
             .expect("Method not found");
-        context.stack.invoke(
+        match context.invoke(
             Arc::as_ptr(&class.bytecode.as_ref().unwrap()),
             Arc::as_ptr(&method),
             0,
             1,
-        );
-        interpreter::execute(&mut context);
+        ) {
+            Ok(_) => interpreter::execute(&mut context),
+            Err(message) => eprintln!(
+                "Exception in thread \"{}\" java.lang.VerifyError: {}",
+                context.id, message
+            ),
+        }
         Self::remove_thread(context.id);
     }
 
@@ -108,6 +114,8 @@ pub struct ThreadContext {
     pub classloader: Ref,
     pub exception_pending: bool,
     pub throwable_initialized: bool,
+    /// the frames the pending exception was thrown from, the innermost first
+    pub backtrace: Vec<String>,
     pub status: AtomicU32,
     pub id: u32,
     pub rx: Receiver<u32>,
@@ -115,13 +123,14 @@ pub struct ThreadContext {
 }
 
 impl ThreadContext {
-    fn new(id: u32, classloader: Ref, rx: Receiver<u32>, tx: Sender<Vec<*mut Ref>>) -> Self {
+    pub fn new(id: u32, classloader: Ref, rx: Receiver<u32>, tx: Sender<Vec<*mut Ref>>) -> Self {
         Self {
             pc: 0,
-            stack: JavaStack::new(),
+            stack: JavaStack::new(id),
             classloader: classloader,
             exception_pending: false,
             throwable_initialized: false,
+            backtrace: vec![],
             status: AtomicU32::new(THREAD_RUNNING),
             id: id,
             rx: rx,
@@ -132,4 +141,43 @@ impl ThreadContext {
     pub fn roots(&self) -> Vec<*mut Ref> {
         self.stack.collect_tracing_roots()
     }
+
+    /// answers the pause of a collection, if one was requested, with the roots of the stack
+    pub fn safepoint(&self) {
+        safepoint(&self.rx, &self.tx, || self.roots());
+    }
+
+    /// `JavaStack::invoke`, answering the pauses of collections while a synchronized method
+    /// waits for its monitor, static ones lock the mirror of their class
+    pub fn invoke(
+        &mut self,
+        class: *const Class,
+        method: *const Method,
+        pc: usize,
+        locals: usize,
+    ) -> Result<usize, String> {
+        let m = unsafe { &*method };
+        let mirror = if m.is_static() && m.is_synchronized() {
+            Some(interpreter::class_mirror(self, unsafe { &*class })?)
+        } else {
+            None
+        };
+        let (rx, tx) = (&self.rx, &self.tx);
+        self.stack.invoke(class, method, pc, locals, mirror, &|stack| {
+            safepoint(rx, tx, || stack.collect_tracing_roots())
+        })
+    }
+}
+
+/// answers the pause of a collection, if one was requested, with `roots` and waits until the
+/// collection is done
+pub fn safepoint<F>(rx: &Receiver<u32>, tx: &Sender<Vec<*mut Ref>>, roots: F)
+where
+    F: FnOnce() -> Vec<*mut Ref>,
+{
+    if rx.try_recv().is_ok() {
+        tx.send(roots()).unwrap();
+        // waiting signal
+        let _ = rx.recv().unwrap();
+    }
 }
@@ -1,7 +1,7 @@
 // #![feature(weak_into_raw)]
 use azerothvm::{
     gc,
-    interpreter::thread::ThreadGroup,
+    interpreter::{self, callsite::CallSites, thread::ThreadGroup},
     mem::{
         heap::Heap,
         metaspace::{ClassArena, *},
@@ -15,16 +15,22 @@ fn main() {
             if let Some(cp) = dir.to_str() {
                 let mut main_class = String::new();
                 let mut cp = cp.to_string();
+                let mut print_inline_caches = false;
                 {
                     let mut args = argparse::ArgumentParser::new();
                     args.refer(&mut cp)
                         .add_option(&["--classpath"], argparse::Store, "");
+                    args.refer(&mut print_inline_caches).add_option(
+                        &["--print-inline-caches"],
+                        argparse::StoreTrue,
+                        "print the receivers seen by virtual and interface calls on exit",
+                    );
                     args.refer(&mut main_class)
                         .add_argument("", argparse::Store, "");
                     args.parse_args_or_exit();
                 }
                 match std::env::var("JAVA_HOME") {
-                    Ok(home) => start_vm(&main_class, &cp, &home),
+                    Ok(home) => start_vm(&main_class, &cp, &home, print_inline_caches),
                     Err(_) => panic!("JAVA_HOME not set"),
                 }
             }
@@ -68,12 +74,13 @@ fn resolve_user_classpath(user_classpath: &str) -> Vec<String> {
         .collect::<Vec<String>>();
 }
 
-fn start_vm(class_name: &str, user_classpath: &str, java_home: &str) {
+fn start_vm(class_name: &str, user_classpath: &str, java_home: &str, print_inline_caches: bool) {
     let system_paths = resolve_system_classpath(java_home);
     let user_paths = resolve_user_classpath(user_classpath);
     ClassArena::init(user_paths, system_paths);
     Heap::init(10 * 1024 * 1024, 1024 * 1024, 1024 * 1024);
     Strings::init();
+    CallSites::init();
     ThreadGroup::init();
     gc::init();
     ThreadGroup::new_thread(
@@ -83,4 +90,7 @@ fn start_vm(class_name: &str, user_classpath: &str, java_home: &str) {
         "([Ljava/lang/String;)V",
         true,
     );
+    if print_inline_caches {
+        interpreter::print_inline_caches();
+    }
 }
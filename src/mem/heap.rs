@@ -78,9 +78,9 @@ impl Heap {
         }
         // array and instance
         let instance_len = match obj.is_instance() {
-            true => unsafe { &*obj.klass }.len as usize,
-            false => obj.size.unwrap() as usize * unsafe { &*obj.klass }.len,
-        } + OBJ_HEADER_SIZE;
+            true => unsafe { &*obj.klass }.len as usize + OBJ_HEADER_SIZE,
+            false => Heap::array_len(unsafe { &*obj.klass }, obj.size.unwrap()),
+        };
         let free = Heap::ptr(region.offset as usize);
         unsafe { free.copy_from(Heap::ptr(*obj_ref as usize), instance_len) };
         let addr = region.offset;
@@ -135,21 +135,25 @@ impl Heap {
         size: u32,
     ) -> Option<Ref> {
         let mut region = region.write().unwrap();
-        let array_len = OBJ_HEADER_SIZE + klass.ref_len * size as usize;
+        let array_len = Heap::array_len(klass, size);
         if region.offset + array_len as u32 >= region.limit {
             return None;
         }
         let array_header = ObjHeader::new_array(Arc::as_ptr(klass), size);
         let array_ptr = array_header.into_vm_raw().as_ptr();
         let free = Heap::ptr(region.offset as usize);
-        unsafe { free.copy_from(array_ptr, OBJ_HEADER_SIZE) };
+        unsafe {
+            free.copy_from(array_ptr, OBJ_HEADER_SIZE);
+            free.add(OBJ_HEADER_SIZE)
+                .write_bytes(0, array_len - OBJ_HEADER_SIZE);
+        }
         let addr = region.offset;
         region.offset = region.offset + array_len as u32;
         Some(addr)
     }
 
     pub fn allocate_array(klass: &Arc<Klass>, size: u32) -> Option<Ref> {
-        let array_len = OBJ_HEADER_SIZE + klass.ref_len * size as usize;
+        let array_len = Heap::array_len(klass, size);
         let mut eden = jvm_heap!().eden.write().unwrap();
         // ensure enough space to allocate object
         if eden.offset + array_len as u32 >= eden.limit {
@@ -160,12 +164,41 @@ impl Heap {
             let eden_ptr = jvm_heap!().base.add(eden.offset as usize);
             let array_header_ptr = array_header.into_vm_raw().as_ptr();
             eden_ptr.copy_from(array_header_ptr, OBJ_HEADER_SIZE);
+            eden_ptr
+                .add(OBJ_HEADER_SIZE)
+                .write_bytes(0, array_len - OBJ_HEADER_SIZE);
             let addr = eden.offset;
             eden.offset = eden.offset + array_len as u32;
             Some(addr)
         }
     }
 
+    pub fn allocate_array_directly(klass: &Arc<Klass>, size: u32) -> Option<Ref> {
+        Self::allocate_array_in_region(klass, &jvm_heap!().oldgen, size)
+    }
+
+    /// allocate an array of `counts[0]` elements, each filled with sub-arrays of the rest dimensions
+    pub fn allocate_multi_array(klass: &Arc<Klass>, counts: &[u32]) -> Option<Ref> {
+        let array = Self::allocate_array(klass, counts[0])?;
+        if counts.len() > 1 {
+            let component = klass.component.as_ref().expect("multi_array_component");
+            for i in 0..counts[0] as usize {
+                let sub = Self::allocate_multi_array(component, &counts[1..])?;
+                unsafe {
+                    Heap::ptr(array as usize + OBJ_HEADER_SIZE + i * PTR_SIZE)
+                        .copy_from(sub.to_le_bytes().as_ptr(), PTR_SIZE);
+                }
+            }
+        }
+        Some(array)
+    }
+
+    // header and elements, padded to keep the next object aligned
+    pub fn array_len(klass: &Klass, size: u32) -> usize {
+        let len = klass.len * size as usize;
+        OBJ_HEADER_SIZE + (len + PTR_SIZE - 1) / PTR_SIZE * PTR_SIZE
+    }
+
     pub fn ptr(offset: usize) -> *mut u8 {
         unsafe { jvm_heap!().base.add(offset) }
     }
@@ -190,17 +223,29 @@ macro_rules! jvm_heap {
 }
 
 #[cfg(test)]
-mod test {
+pub mod test {
 
     use crate::mem::heap;
-    use std::sync::Arc;
+    use lazy_static::lazy_static;
+    use std::sync::{Arc, Mutex, MutexGuard};
+
+    lazy_static! {
+        static ref HEAP_LOCK: Mutex<()> = Mutex::new(());
+    }
+
+    /// tests using the global heap run one by one, each on a fresh heap
+    pub fn reset_heap() -> MutexGuard<'static, ()> {
+        let guard = HEAP_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        super::Heap::init(10 * 1024 * 1024, 1024 * 1024, 1024 * 1024);
+        guard
+    }
 
     #[test]
     pub fn test() {
-        super::Heap::init(10 * 1024 * 1024, 1024 * 1024, 1024 * 1024);
+        let _heap = reset_heap();
         let java_lang_object = "yv66vgAAADQATgcAMQoAAQAyCgARADMKADQANQoAAQA2CAA3CgARADgKADkAOgoAAQA7BwA8CAA9CgAKAD4DAA9CPwgAPwoAEQBACgARAEEHAEIBAAY8aW5pdD4BAAMoKVYBAARDb2RlAQAPTGluZU51bWJlclRhYmxlAQAPcmVnaXN0ZXJOYXRpdmVzAQAIZ2V0Q2xhc3MBABMoKUxqYXZhL2xhbmcvQ2xhc3M7AQAJU2lnbmF0dXJlAQAWKClMamF2YS9sYW5nL0NsYXNzPCo+OwEACGhhc2hDb2RlAQADKClJAQAGZXF1YWxzAQAVKExqYXZhL2xhbmcvT2JqZWN0OylaAQANU3RhY2tNYXBUYWJsZQEABWNsb25lAQAUKClMamF2YS9sYW5nL09iamVjdDsBAApFeGNlcHRpb25zBwBDAQAIdG9TdHJpbmcBABQoKUxqYXZhL2xhbmcvU3RyaW5nOwEABm5vdGlmeQEACW5vdGlmeUFsbAEABHdhaXQBAAQoSilWBwBEAQAFKEpJKVYBAAhmaW5hbGl6ZQcARQEACDxjbGluaXQ+AQAKU291cmNlRmlsZQEAC09iamVjdC5qYXZhAQAXamF2YS9sYW5nL1N0cmluZ0J1aWxkZXIMABIAEwwAFwAYBwBGDABHACUMAEgASQEAAUAMABsAHAcASgwASwBMDAAkACUBACJqYXZhL2xhbmcvSWxsZWdhbEFyZ3VtZW50RXhjZXB0aW9uAQAZdGltZW91dCB2YWx1ZSBpcyBuZWdhdGl2ZQwAEgBNAQAlbmFub3NlY29uZCB0aW1lb3V0IHZhbHVlIG91dCBvZiByYW5nZQwAKAApDAAWABMBABBqYXZhL2xhbmcvT2JqZWN0AQAkamF2YS9sYW5nL0Nsb25lTm90U3VwcG9ydGVkRXhjZXB0aW9uAQAeamF2YS9sYW5nL0ludGVycnVwdGVkRXhjZXB0aW9uAQATamF2YS9sYW5nL1Rocm93YWJsZQEAD2phdmEvbGFuZy9DbGFzcwEAB2dldE5hbWUBAAZhcHBlbmQBAC0oTGphdmEvbGFuZy9TdHJpbmc7KUxqYXZhL2xhbmcvU3RyaW5nQnVpbGRlcjsBABFqYXZhL2xhbmcvSW50ZWdlcgEAC3RvSGV4U3RyaW5nAQAVKEkpTGphdmEvbGFuZy9TdHJpbmc7AQAVKExqYXZhL2xhbmcvU3RyaW5nOylWACEAEQAAAAAAAAAOAAEAEgATAAEAFAAAABkAAAABAAAAAbEAAAABABUAAAAGAAEAAAAlAQoAFgATAAABEQAXABgAAQAZAAAAAgAaAQEAGwAcAAAAAQAdAB4AAQAUAAAALgACAAIAAAALKiumAAcEpwAEA6wAAAACABUAAAAGAAEAAACVAB8AAAAFAAIJQAEBBAAgACEAAQAiAAAABAABACMAAQAkACUAAQAUAAAAPAACAAEAAAAkuwABWbcAAiq2AAO2AAS2AAUSBrYABSq2AAe4AAi2AAW2AAmwAAAAAQAVAAAABgABAAAA7AERACYAEwAAAREAJwATAAABEQAoACkAAQAiAAAABAABACoAEQAoACsAAgAUAAAAcgAEAAQAAAAyHwmUnAANuwAKWRILtwAMvx2bAAkdEg2kAA27AApZEg63AAy/HZ4ABx8KYUAqH7YAD7EAAAACABUAAAAiAAgAAAG/AAYBwAAQAcMAGgHEACQByAAoAckALAHMADEBzQAfAAAABgAEEAkJBwAiAAAABAABACoAEQAoABMAAgAUAAAAIgADAAEAAAAGKgm2AA+xAAAAAQAVAAAACgACAAAB9gAFAfcAIgAAAAQAAQAqAAQALAATAAIAFAAAABkAAAABAAAAAbEAAAABABUAAAAGAAEAAAIrACIAAAAEAAEALQAIAC4AEwABABQAAAAgAAAAAAAAAAS4ABCxAAAAAQAVAAAACgACAAAAKQADACoAAQAvAAAAAgAw";
         let class_vec = base64::decode(java_lang_object).unwrap();
-        let bytecode = super::Class::from_vec(class_vec);
+        let bytecode = super::Class::from_vec(class_vec).unwrap();
         let klass = super::Klass::new(
             Arc::new(bytecode),
             super::metaspace::ROOT_CLASSLOADER,
@@ -221,4 +266,79 @@ mod test {
         let java_lang_object_klass = unsafe { &*obj_header.klass };
         assert_eq!("java/lang/Object", java_lang_object_klass.name);
     }
+
+    #[test]
+    pub fn test_array() {
+        let _heap = reset_heap();
+        let boolean = Arc::new(super::Klass::new_phantom_klass("Z"));
+        let char = Arc::new(super::Klass::new_phantom_klass("C"));
+        let int = Arc::new(super::Klass::new_phantom_klass("I"));
+        let double = Arc::new(super::Klass::new_phantom_klass("D"));
+        let string = Arc::new(super::Klass::new_phantom_klass("java/lang/String"));
+        let arrays = vec![
+            (super::Klass::new_array_klass("[Z", boolean), 1),
+            (super::Klass::new_array_klass("[C", char), 2),
+            (super::Klass::new_array_klass("[I", int.clone()), 4),
+            (super::Klass::new_array_klass("[D", double), 8),
+            (
+                super::Klass::new_array_klass("[Ljava/lang/String;", string),
+                4,
+            ),
+        ];
+        for (klass, width) in arrays {
+            assert_eq!(width, klass.len);
+            assert_eq!(super::PTR_SIZE, klass.ref_len);
+            let klass = Arc::new(klass);
+            // dirty the memory to be allocated
+            let free = jvm_heap!().eden.read().unwrap().offset as usize;
+            unsafe { super::Heap::ptr(free).write_bytes(0xff, 1024) };
+            let array = super::Heap::allocate_array(&klass, 7).unwrap() as usize;
+            assert_eq!(free, array);
+            let header = super::Heap::as_obj(array as u32);
+            assert_eq!(Some(7), header.size);
+            assert!(std::ptr::eq(Arc::as_ptr(&klass), header.klass));
+            let elements = unsafe {
+                std::slice::from_raw_parts(
+                    super::Heap::ptr(array + super::OBJ_HEADER_SIZE),
+                    7 * width,
+                )
+            };
+            assert!(elements.iter().all(|b| *b == 0));
+            let next = super::Heap::allocate_array(&klass, 1).unwrap() as usize;
+            let padded = (7 * width + super::PTR_SIZE - 1) / super::PTR_SIZE * super::PTR_SIZE;
+            assert_eq!(array + super::OBJ_HEADER_SIZE + padded, next);
+        }
+
+        let int_array = Arc::new(super::Klass::new_array_klass("[I", int));
+        let int_array_2d = Arc::new(super::Klass::new_array_klass("[[I", int_array.clone()));
+        let int_array_3d = Arc::new(super::Klass::new_array_klass("[[[I", int_array_2d));
+        let array = super::Heap::allocate_multi_array(&int_array_3d, &[2, 3, 4]).unwrap();
+        let header = super::Heap::as_obj(array);
+        assert_eq!(Some(2), header.size);
+        for i in 0..2 {
+            let sub = unsafe {
+                *super::Heap::ptr(array as usize + super::OBJ_HEADER_SIZE + i * super::PTR_SIZE)
+                    .cast::<u32>()
+            };
+            let header = super::Heap::as_obj(sub);
+            assert_eq!(Some(3), header.size);
+            assert_eq!("[[I", unsafe { &*header.klass }.name);
+            for j in 0..3 {
+                let leaf = unsafe {
+                    *super::Heap::ptr(sub as usize + super::OBJ_HEADER_SIZE + j * super::PTR_SIZE)
+                        .cast::<u32>()
+                };
+                let header = super::Heap::as_obj(leaf);
+                assert_eq!(Some(4), header.size);
+                assert!(std::ptr::eq(Arc::as_ptr(&int_array), header.klass));
+            }
+        }
+        // trailing dimensions are left null
+        let array = super::Heap::allocate_multi_array(&int_array_3d, &[2]).unwrap();
+        let sub =
+            unsafe { *super::Heap::ptr(array as usize + super::OBJ_HEADER_SIZE).cast::<u32>() };
+        assert_eq!(0, sub);
+        let empty = super::Heap::allocate_multi_array(&int_array_3d, &[0, 3]).unwrap();
+        assert_eq!(Some(0), super::Heap::as_obj(empty).size);
+    }
 }
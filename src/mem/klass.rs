@@ -3,7 +3,10 @@ use crate::bytecode::{class::Class, method::Method};
 use crate::mem::{metaspace::*, Ref, PTR_SIZE};
 use std::collections::HashMap;
 use std::mem::{size_of, transmute};
-use std::sync::{atomic::AtomicBool, Arc, Mutex};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32},
+    Arc, Mutex,
+};
 
 pub type MethodRef = (*const Class, *const Method);
 
@@ -11,18 +14,29 @@ pub struct Klass {
     pub bytecode: Option<Arc<Class>>,
     pub name: String,
     pub classloader: Ref,
-    pub vtable: HashMap<RefKey, MethodRef>,
-    pub itable: HashMap<RefKey, MethodRef>,
+    /// slots are inherited from the superclass, overriding methods take the slots they override
+    pub vtable: Vec<MethodRef>,
+    /// the slot a call through this class dispatches on, keyed by `("", name, descriptor)`
+    pub vtable_slots: HashMap<RefKey, usize>,
+    /// a block for every interface the class implements, indexed by the itable slots of the
+    /// interface, `Err` carries the error an invocation throws, e.g. for conflicting default
+    /// methods
+    pub itable: Vec<(*const Class, Vec<Result<MethodRef, &'static str>>)>,
     pub layout: HashMap<RefKey, (usize, usize)>,
     pub len: usize,
     pub ref_len: usize,
     pub superclass: Option<Arc<Klass>>,
     pub superinterfaces: Vec<Arc<Klass>>,
+    pub component: Option<Arc<Klass>>,
     pub initialized: AtomicBool,
     pub mutex: Mutex<u8>,
+    /// the `java.lang.Class` instance of this class, 0 until it's first asked for
+    pub mirror: AtomicU32,
 }
 
+// the mark word must stay at offset 0, see `monitor`
 #[derive(Clone)]
+#[repr(C)]
 pub struct ObjHeader {
     pub mark: u32,
     pub size: Option<u32>,
@@ -35,13 +49,13 @@ pub const OBJ_HEADER_PADDING: usize = OBJ_HEADER_SIZE % PTR_SIZE;
 
 pub type ObjHeaderRaw = [u8; OBJ_HEADER_SIZE];
 
-const LOCK_STATE_MASK: u32 = 0x07;
+pub const LOCK_STATE_MASK: u32 = 0x07;
 
 const GC_STATE_MASK: u32 = 0x03;
 
-const LOCK_FREE_FLAG: u32 = 0x01;
+pub const LOCK_FREE_FLAG: u32 = 0x01;
 
-const GC_AGE_MASK: u32 = 0x78;
+pub const GC_AGE_MASK: u32 = 0x78;
 
 impl ObjHeader {
 
@@ -75,7 +89,7 @@ impl ObjHeader {
 
     pub fn new_instance(klass: *const Klass) -> Self {
         Self {
-            mark: 0,
+            mark: LOCK_FREE_FLAG,
             size: None,
             klass: klass,
         }
@@ -83,7 +97,7 @@ impl ObjHeader {
 
     pub fn new_array(klass: *const Klass, size: u32) -> Self {
         Self {
-            mark: 0,
+            mark: LOCK_FREE_FLAG,
             size: Some(size),
             klass: klass,
         }
@@ -115,15 +129,18 @@ impl Klass {
             bytecode: Some(bytecode),
             name: name,
             classloader: classloader,
-            vtable: HashMap::new(),
-            itable: HashMap::new(),
+            vtable: vec![],
+            vtable_slots: HashMap::new(),
+            itable: vec![],
             layout: HashMap::new(),
             len: 0,
             ref_len: PTR_SIZE,
             superclass: superclass,
             superinterfaces: interfaces,
+            component: None,
             initialized: AtomicBool::new(false),
             mutex: Mutex::<u8>::new(0),
+            mirror: AtomicU32::new(0),
         };
         &klass.build_vtable();
         &klass.build_itable();
@@ -136,46 +153,106 @@ impl Klass {
             bytecode: None,
             name: name.to_owned(),
             classloader: ROOT_CLASSLOADER,
-            vtable: HashMap::new(),
-            itable: HashMap::new(),
+            vtable: vec![],
+            vtable_slots: HashMap::new(),
+            itable: vec![],
             layout: HashMap::new(),
-            len: match name {
-                "I" | "F" | "Z" | "B" | "S" | "C" => PTR_SIZE,
-                "D" | "J" => 2 * PTR_SIZE,
-                _ => PTR_SIZE,
-            },
-            ref_len: match name {
-                "I" | "F" | "Z" | "B" | "S" | "C" => PTR_SIZE,
-                "D" | "J" => 2 * PTR_SIZE,
-                _ => PTR_SIZE,
-            },
+            len: Self::width(name),
+            ref_len: Self::width(name),
             superclass: None,
             superinterfaces: vec![],
+            component: None,
             initialized: AtomicBool::new(true),
             mutex: Mutex::<u8>::new(0),
+            mirror: AtomicU32::new(0),
         }
     }
 
-    pub fn is_superclass(&self, target: &str) -> bool {
-        let mut thisclass = self;
-        loop {
-            if &thisclass.name == target {
+    // the `len` of an array klass is the width of its elements
+    pub fn new_array_klass(name: &str, component: Arc<Klass>) -> Self {
+        let mut klass = Self::new_phantom_klass(name);
+        klass.len = component.ref_len;
+        klass.component = Some(component);
+        klass
+    }
+
+    fn width(descriptor: &str) -> usize {
+        match descriptor {
+            "Z" | "B" => 1,
+            "C" | "S" => 2,
+            "D" | "J" => 2 * PTR_SIZE,
+            _ => PTR_SIZE,
+        }
+    }
+
+    pub fn is_array(&self) -> bool {
+        self.component.is_some()
+    }
+
+    // `target` is a class name or an array descriptor, e.g. `java/lang/String` or `[[I`
+    pub fn is_subtype_of(&self, target: &str) -> bool {
+        if self.name == target {
+            return true;
+        }
+        if let Some(component) = &self.component {
+            return match target {
+                "java/lang/Object" | "java/lang/Cloneable" | "java/io/Serializable" => true,
+                _ if target.starts_with("[") => {
+                    let target = &target[1..];
+                    if target.starts_with("L") && target.ends_with(";") {
+                        component.is_subtype_of(&target[1..target.len() - 1])
+                    } else {
+                        component.is_subtype_of(target)
+                    }
+                }
+                _ => false,
+            };
+        }
+        let mut thisclass = Some(self);
+        while let Some(klass) = thisclass {
+            if klass.name == target
+                || klass
+                    .superinterfaces
+                    .iter()
+                    .any(|ifs| ifs.is_subtype_of(target))
+            {
                 return true;
             }
-            if thisclass.superclass.is_none() {
-                break;
-            }
-            thisclass = thisclass.superclass.as_ref().unwrap();
+            thisclass = klass.superclass.as_deref();
         }
         false
     }
 
+    pub fn get_vtable_slot(&self, name: &str, desc: &str) -> Option<usize> {
+        self.vtable_slots.get(&("", name, desc)).copied()
+    }
+
     pub fn get_method_in_vtable(&self, name: &str, desc: &str) -> Option<&MethodRef> {
-        self.vtable.get(&("", name, desc))
+        self.vtable.get(self.get_vtable_slot(name, desc)?)
+    }
+
+    /// the slot of a method declared by this interface in the itable blocks of its implementors
+    pub fn get_itable_slot(&self, name: &str, desc: &str) -> Option<usize> {
+        let class = self.bytecode.as_ref()?;
+        if !class.is_interface() {
+            return None;
+        }
+        itable_methods(class).position(|m| m.name == name && m.descriptor == desc)
     }
 
-    pub fn get_method_in_itable(&self, ifs: &str, name: &str, desc: &str) -> Option<&MethodRef> {
-        self.itable.get(&(ifs, name, desc))
+    pub fn get_method_in_itable(
+        &self,
+        ifs: &str,
+        name: &str,
+        desc: &str,
+    ) -> Option<&Result<MethodRef, &'static str>> {
+        let (ifs, block) = self
+            .itable
+            .iter()
+            .find(|(class, _)| unsafe { &**class }.get_name() == ifs)?;
+        let slot = itable_methods(unsafe { &**ifs })
+            .position(|m| m.name == name && m.descriptor == desc)?;
+        block.get(slot)
     }
 
     pub fn get_holding_refs(&self, obj: Ref) -> Vec<*mut Ref> {
@@ -186,54 +263,155 @@ impl Klass {
             .collect::<_>()
     }
 
+    // JVMS 5.4.5, a final method overriding nothing gets no slot since it's never dispatched on
     fn build_vtable(&mut self) {
-        match &self.superclass {
-            Some(klass) => {
-                for (k, v) in &klass.vtable {
-                    self.vtable.insert(k.clone(), v.clone());
-                }
-            }
-            None => {}
+        if let Some(klass) = &self.superclass {
+            self.vtable = klass.vtable.clone();
+            self.vtable_slots = klass.vtable_slots.clone();
         }
-        for m in &self.bytecode.as_ref().unwrap().methods {
-            if (m.is_public() || m.is_protected())
-                && !m.is_final()
-                && !m.is_static()
-                && m.name != "<init>"
-            {
-                self.vtable.insert(
-                    RefKey::new("".to_string(), m.name.clone(), m.descriptor.clone()),
-                    (
-                        Arc::as_ptr(&self.bytecode.as_ref().unwrap()),
-                        Arc::as_ptr(m),
-                    ),
-                );
+        let class = Arc::clone(self.bytecode.as_ref().unwrap());
+        if class.is_interface() {
+            return;
+        }
+        for m in &class.methods {
+            if m.is_static() || m.is_private() || m.name == "<init>" || m.name == "<clinit>" {
+                continue;
+            }
+            let method = (Arc::as_ptr(&class), Arc::as_ptr(m));
+            let overridden = (0..self.vtable.len())
+                .filter(|i| self.overrides(m, &self.vtable[*i]))
+                .collect::<Vec<_>>();
+            for i in &overridden {
+                self.vtable[*i] = method;
+            }
+            let key = RefKey::new("".to_string(), m.name.clone(), m.descriptor.clone());
+            match overridden.first() {
+                Some(i) => {
+                    self.vtable_slots.insert(key, *i);
+                }
+                None if m.is_final() => {
+                    self.vtable_slots.remove(&key);
+                }
+                None => {
+                    self.vtable.push(method);
+                    self.vtable_slots.insert(key, self.vtable.len() - 1);
+                }
             }
         }
     }
 
+    // a package-private method is only overridden within its package
+    fn overrides(&self, m: &Method, slot: &MethodRef) -> bool {
+        let (class, method) = unsafe { (&*slot.0, &*slot.1) };
+        method.name == m.name
+            && method.descriptor == m.descriptor
+            && (method.is_public()
+                || method.is_protected()
+                || package(class.get_name()) == package(&self.name))
+    }
+
+    // a block of the methods each interface declares, interface methods not implemented by a
+    // class also get vtable slots
     fn build_itable(&mut self) {
-        match &self.superclass {
-            Some(klass) => {
-                for (k, v) in &klass.itable {
-                    self.itable.insert(k.clone(), v.clone());
+        if self.bytecode.as_ref().unwrap().is_interface() {
+            return;
+        }
+        let interfaces = self.all_interfaces();
+        for ifs in &interfaces {
+            let class = match &ifs.bytecode {
+                Some(class) => class,
+                None => continue,
+            };
+            let mut block = vec![];
+            for m in itable_methods(class) {
+                let selected = self.select_interface_method(&interfaces, &m.name, &m.descriptor);
+                block.push(selected);
+                // a default method inherited from the superclass may be overridden here, a
+                // conflicting or abstract one is left in the slot and checked at invocation
+                match self.get_vtable_slot(&m.name, &m.descriptor) {
+                    Some(i) if unsafe { &*self.vtable[i].0 }.is_interface() => {
+                        if let Ok(method) = selected {
+                            self.vtable[i] = method;
+                        }
+                    }
+                    Some(_) => {}
+                    None => {
+                        self.vtable
+                            .push(selected.unwrap_or((Arc::as_ptr(class), Arc::as_ptr(m))));
+                        let key = RefKey::new("".to_string(), m.name.clone(), m.descriptor.clone());
+                        self.vtable_slots.insert(key, self.vtable.len() - 1);
+                    }
                 }
             }
-            None => {}
+            self.itable.push((Arc::as_ptr(class), block));
         }
-        let current = &*self.bytecode.as_ref().unwrap();
-        for ifs in &self.superinterfaces {
-            for m in &current.methods {
-                if let Some(implement) = current.get_method(&m.name, &m.descriptor) {
-                    self.itable.insert(
-                        RefKey::new(ifs.name.clone(), m.name.clone(), m.descriptor.clone()),
-                        (
-                            Arc::as_ptr(&self.bytecode.as_ref().unwrap()),
-                            Arc::as_ptr(&implement),
-                        ),
-                    );
+    }
+
+    // every interface the class implements, directly or not
+    fn all_interfaces(&self) -> Vec<Arc<Klass>> {
+        let mut pending = vec![];
+        let mut thisclass = Some(self);
+        while let Some(klass) = thisclass {
+            pending.extend(klass.superinterfaces.iter().cloned());
+            thisclass = klass.superclass.as_deref();
+        }
+        let mut interfaces: Vec<Arc<Klass>> = vec![];
+        while let Some(ifs) = pending.pop() {
+            if interfaces.iter().all(|i| i.name != ifs.name) {
+                pending.extend(ifs.superinterfaces.iter().cloned());
+                interfaces.push(ifs);
+            }
+        }
+        interfaces
+    }
+
+    // JVMS 5.4.6, a method of the class or its superclasses, otherwise the only non-abstract one
+    // among the maximally-specific superinterface methods
+    fn select_interface_method(
+        &self,
+        interfaces: &[Arc<Klass>],
+        name: &str,
+        desc: &str,
+    ) -> Result<MethodRef, &'static str> {
+        let mut thisclass = Some(self);
+        while let Some(klass) = thisclass {
+            if let Some(class) = &klass.bytecode {
+                if let Some(m) = class.get_method(name, desc) {
+                    if m.is_abstract() {
+                        return Err("java/lang/AbstractMethodError");
+                    }
+                    if !m.is_static() && !m.is_private() {
+                        return Ok((Arc::as_ptr(class), Arc::as_ptr(&m)));
+                    }
                 }
             }
+            thisclass = klass.superclass.as_deref();
+        }
+        let candidates = interfaces
+            .iter()
+            .filter_map(|ifs| {
+                let class = ifs.bytecode.as_ref()?;
+                let m = class.get_method(name, desc)?;
+                if m.is_static() || m.is_private() {
+                    None
+                } else {
+                    Some((ifs, class, m))
+                }
+            })
+            .collect::<Vec<_>>();
+        let specific = candidates
+            .iter()
+            .filter(|(ifs, _, m)| {
+                !m.is_abstract()
+                    && !candidates
+                        .iter()
+                        .any(|(sub, _, _)| sub.name != ifs.name && sub.is_subtype_of(&ifs.name))
+            })
+            .collect::<Vec<_>>();
+        match specific.as_slice() {
+            [(_, class, m)] => Ok((Arc::as_ptr(class), Arc::as_ptr(m))),
+            [] => Err("java/lang/AbstractMethodError"),
+            _ => Err("java/lang/IncompatibleClassChangeError"),
         }
     }
 
@@ -266,6 +444,22 @@ impl Klass {
     }
 }
 
+// the methods an interface dispatches, in the order of its itable slots
+fn itable_methods(class: &Class) -> impl Iterator<Item = &Arc<Method>> {
+    class
+        .methods
+        .iter()
+        .filter(|m| !m.is_static() && !m.is_private() && m.name != "<clinit>")
+}
+
+// the runtime package of a class, all classes share the root classloader
+fn package(class: &str) -> &str {
+    match class.rfind('/') {
+        Some(i) => &class[..i],
+        None => "",
+    }
+}
+
 #[cfg(test)]
 pub mod test {
 
@@ -280,9 +474,65 @@ pub mod test {
 
     const DEFAULT_EXTENDS_TEST: &'static str = "yv66vgAAADQAFwoAAwAUBwAVBwAWAQABYQEAAUkBAAFiAQABWgEAAWMBAAFKAQABcwEAAVMBAANzdHIBABJMamF2YS9sYW5nL1N0cmluZzsBAAY8aW5pdD4BAAMoKVYBAARDb2RlAQAPTGluZU51bWJlclRhYmxlAQAKU291cmNlRmlsZQEAFUV4dGVuZFRlc3RWVGFibGUuamF2YQwADgAPAQAQRXh0ZW5kVGVzdFZUYWJsZQEAClRlc3RWVGFibGUAIQACAAMAAAAFAAAABAAFAAAAAAAGAAcAAAAAAAgACQAAAAAACgALAAAAAAAMAA0AAAABAAEADgAPAAEAEAAAAB0AAQABAAAABSq3AAGxAAAAAQARAAAABgABAAAAAQABABIAAAACABM=";
 
+    // interface Animal {}
+    const ANIMAL: &'static str = "yv66vgAAADQABwcAAgEABkFuaW1hbAcABAEAEGphdmEvbGFuZy9PYmplY3QBAApTb3VyY2VGaWxlAQAIU3ViLmphdmEGAAABAAMAAAAAAAAAAQAFAAAAAgAG";
+
+    // interface Pet extends Animal {}
+    const PET: &'static str = "yv66vgAAADQACQcAAgEAA1BldAcABAEAEGphdmEvbGFuZy9PYmplY3QHAAYBAAZBbmltYWwBAApTb3VyY2VGaWxlAQAIU3ViLmphdmEGAAABAAMAAQAFAAAAAAABAAcAAAACAAg=";
+
+    // class Dog implements Pet {}
+    const DOG: &'static str = "yv66vgAAADQADwoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQADRG9nBwAKAQADUGV0AQAEQ29kZQEAD0xpbmVOdW1iZXJUYWJsZQEAClNvdXJjZUZpbGUBAAhTdWIuamF2YQAgAAcAAgABAAkAAAABAAAABQAGAAEACwAAAB0AAQABAAAABSq3AAGxAAAAAQAMAAAABgABAAAAAwABAA0AAAACAA4=";
+
+    // class Puppy extends Dog {}
+    const PUPPY: &'static str = "yv66vgAAADQADQoAAgADBwAEDAAFAAYBAANEb2cBAAY8aW5pdD4BAAMoKVYHAAgBAAVQdXBweQEABENvZGUBAA9MaW5lTnVtYmVyVGFibGUBAApTb3VyY2VGaWxlAQAIU3ViLmphdmEAIAAHAAIAAAAAAAEAAAAFAAYAAQAJAAAAHQABAAEAAAAFKrcAAbEAAAABAAoAAAAGAAEAAAAEAAEACwAAAAIADA==";
+
+    // javac --release 8 -g:none, the default `ItTagged.id` was added after `ItBoth` was compiled
+    //
+    // public interface ItNamed { default int id() { return 1; } }
+    // public interface ItLabeled extends ItNamed { default int id() { return 2; } int label(); }
+    // public interface ItTagged { default int id() { return 3; } }
+    // public class ItBase implements ItLabeled { public int label() { return 10; } }
+    // public class ItDerived extends ItBase {}
+    // public class ItBoth extends ItBase implements ItTagged {}
+    // public abstract class ItPartial implements ItLabeled {}
+    const IT_NAMED: &'static str = "yv66vgAAADQACAcAAgEAB0l0TmFtZWQHAAQBABBqYXZhL2xhbmcvT2JqZWN0AQACaWQBAAMoKUkBAARDb2RlBgEAAQADAAAAAAABAAEABQAGAAEABwAAAA4AAQABAAAAAgSsAAAAAAAA";
+
+    const IT_LABELED: &'static str = "yv66vgAAADQACwcAAgEACUl0TGFiZWxlZAcABAEAEGphdmEvbGFuZy9PYmplY3QHAAYBAAdJdE5hbWVkAQACaWQBAAMoKUkBAARDb2RlAQAFbGFiZWwGAQABAAMAAQAFAAAAAgABAAcACAABAAkAAAAOAAEAAQAAAAIFrAAAAAAEAQAKAAgAAAAA";
+
+    const IT_TAGGED: &'static str = "yv66vgAAADQACAcAAgEACEl0VGFnZ2VkBwAEAQAQamF2YS9sYW5nL09iamVjdAEAAmlkAQADKClJAQAEQ29kZQYBAAEAAwAAAAAAAQABAAUABgABAAcAAAAOAAEAAQAAAAIGrAAAAAAAAA==";
+
+    const IT_BASE: &'static str = "yv66vgAAADQADgoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQAGSXRCYXNlBwAKAQAJSXRMYWJlbGVkAQAEQ29kZQEABWxhYmVsAQADKClJACEABwACAAEACQAAAAIAAQAFAAYAAQALAAAAEQABAAEAAAAFKrcAAbEAAAAAAAEADAANAAEACwAAAA8AAQABAAAAAxAKrAAAAAAAAA==";
+
+    const IT_DERIVED: &'static str = "yv66vgAAADQACgoAAgADBwAEDAAFAAYBAAZJdEJhc2UBAAY8aW5pdD4BAAMoKVYHAAgBAAlJdERlcml2ZWQBAARDb2RlACEABwACAAAAAAABAAEABQAGAAEACQAAABEAAQABAAAABSq3AAGxAAAAAAAA";
+
+    const IT_BOTH: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAZJdEJhc2UBAAY8aW5pdD4BAAMoKVYHAAgBAAZJdEJvdGgHAAoBAAhJdFRhZ2dlZAEABENvZGUAIQAHAAIAAQAJAAAAAQABAAUABgABAAsAAAARAAEAAQAAAAUqtwABsQAAAAAAAA==";
+
+    const IT_PARTIAL: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQAJSXRQYXJ0aWFsBwAKAQAJSXRMYWJlbGVkAQAEQ29kZQQhAAcAAgABAAkAAAABAAEABQAGAAEACwAAABEAAQABAAAABSq3AAGxAAAAAAAA";
+
+    // javac --release 8 -g:none
+    //
+    // package a; public class PkgBase { int id() { return 1; } }
+    // package b; public class PkgMid extends a.PkgBase { public int id() { return 2; } }
+    // package a; public class PkgLeaf extends b.PkgMid { public int id() { return 3; } }
+    const PKG_BASE: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBABBqYXZhL2xhbmcvT2JqZWN0AQAGPGluaXQ+AQADKClWBwAIAQAJYS9Qa2dCYXNlAQAEQ29kZQEAAmlkAQADKClJACEABwACAAAAAAACAAEABQAGAAEACQAAABEAAQABAAAABSq3AAGxAAAAAAAAAAoACwABAAkAAAAOAAEAAQAAAAIErAAAAAAAAA==";
+
+    const PKG_MID: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAlhL1BrZ0Jhc2UBAAY8aW5pdD4BAAMoKVYHAAgBAAhiL1BrZ01pZAEABENvZGUBAAJpZAEAAygpSQAhAAcAAgAAAAAAAgABAAUABgABAAkAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAKAAsAAQAJAAAADgABAAEAAAACBawAAAAAAAA=";
+
+    const PKG_LEAF: &'static str = "yv66vgAAADQADAoAAgADBwAEDAAFAAYBAAhiL1BrZ01pZAEABjxpbml0PgEAAygpVgcACAEACWEvUGtnTGVhZgEABENvZGUBAAJpZAEAAygpSQAhAAcAAgAAAAAAAgABAAUABgABAAkAAAARAAEAAQAAAAUqtwABsQAAAAAAAQAKAAsAAQAJAAAADgABAAEAAAACBqwAAAAAAAA=";
+
     fn parse_class(bytecode: &str) -> Class {
         let class_vec = base64::decode(bytecode).unwrap();
-        Class::from_vec(class_vec)
+        Class::from_vec(class_vec).unwrap()
+    }
+
+    /// define `java/lang/Object` in the arena shared by tests
+    pub fn define_object() -> Arc<super::Klass> {
+        crate::mem::metaspace::test::define_class(super::Klass::new(
+            Arc::new(parse_class(JAVA_LANG_OBJECT)),
+            crate::mem::metaspace::ROOT_CLASSLOADER,
+            None,
+            vec![],
+        ))
     }
 
     #[test]
@@ -305,12 +555,10 @@ pub mod test {
         );
         assert_eq!(5, default_simple_klass.vtable.len());
         let to_string_method0 = java_lang_object_klass
-            .vtable
-            .get(&("", "toString", "()Ljava/lang/String;"))
+            .get_method_in_vtable("toString", "()Ljava/lang/String;")
             .unwrap();
         let to_string_method1 = default_simple_klass
-            .vtable
-            .get(&("", "toString", "()Ljava/lang/String;"))
+            .get_method_in_vtable("toString", "()Ljava/lang/String;")
             .unwrap();
         assert_eq!(
             true,
@@ -329,8 +577,7 @@ pub mod test {
         );
         assert_eq!(6, default_test_klass.vtable.len());
         let to_string_method2 = default_test_klass
-            .vtable
-            .get(&("", "toString", "()Ljava/lang/String;"))
+            .get_method_in_vtable("toString", "()Ljava/lang/String;")
             .unwrap();
         assert_eq!(
             false,
@@ -340,10 +587,198 @@ pub mod test {
             false,
             std::ptr::eq((*to_string_method0).1, (*to_string_method2).1)
         );
+        // the override takes over the slot of the superclass
+        assert_eq!(
+            java_lang_object_klass.get_vtable_slot("toString", "()Ljava/lang/String;"),
+            default_test_klass.get_vtable_slot("toString", "()Ljava/lang/String;")
+        );
+    }
+
+    #[test]
+    pub fn test_vtable_package_private() {
+        use super::Klass;
+        use crate::mem::metaspace::ROOT_CLASSLOADER;
+        let new = |bytecode: &str, superclass: &Arc<Klass>| {
+            Arc::new(Klass::new(
+                Arc::new(parse_class(bytecode)),
+                ROOT_CLASSLOADER,
+                Some(superclass.clone()),
+                vec![],
+            ))
+        };
+        let object = Arc::new(Klass::new(
+            Arc::new(parse_class(JAVA_LANG_OBJECT)),
+            ROOT_CLASSLOADER,
+            None,
+            vec![],
+        ));
+        let base = new(PKG_BASE, &object);
+        let mid = new(PKG_MID, &base);
+        let leaf = new(PKG_LEAF, &mid);
+        let id = |klass: &Klass| {
+            let class = klass.bytecode.as_ref().unwrap();
+            (
+                Arc::as_ptr(class),
+                Arc::as_ptr(&class.get_method("id", "()I").unwrap()),
+            )
+        };
+        let slot = base.get_vtable_slot("id", "()I").unwrap();
+        // `b.PkgMid.id` can't see `a.PkgBase.id` and takes a new slot
+        let mid_slot = mid.get_vtable_slot("id", "()I").unwrap();
+        assert_ne!(slot, mid_slot);
+        assert_eq!(Some(&id(&base)), mid.vtable.get(slot));
+        assert_eq!(Some(&id(&mid)), mid.vtable.get(mid_slot));
+        // `a.PkgLeaf.id` overrides both
+        assert_eq!(mid.vtable.len(), leaf.vtable.len());
+        assert_eq!(Some(&id(&leaf)), leaf.vtable.get(slot));
+        assert_eq!(Some(&id(&leaf)), leaf.vtable.get(mid_slot));
+    }
+
+    #[test]
+    pub fn test_itable() {
+        use super::Klass;
+        use crate::mem::metaspace::ROOT_CLASSLOADER;
+        let new = |bytecode: &str, superclass: Option<&Arc<Klass>>, interfaces: &[&Arc<Klass>]| {
+            Arc::new(Klass::new(
+                Arc::new(parse_class(bytecode)),
+                ROOT_CLASSLOADER,
+                superclass.cloned(),
+                interfaces.iter().map(|i| Arc::clone(i)).collect(),
+            ))
+        };
+        let object = new(JAVA_LANG_OBJECT, None, &[]);
+        let named = new(IT_NAMED, Some(&object), &[]);
+        let labeled = new(IT_LABELED, Some(&object), &[&named]);
+        let tagged = new(IT_TAGGED, Some(&object), &[]);
+        let base = new(IT_BASE, Some(&object), &[&labeled]);
+        let derived = new(IT_DERIVED, Some(&base), &[]);
+        let both = new(IT_BOTH, Some(&base), &[&tagged]);
+        let partial = new(IT_PARTIAL, Some(&object), &[&labeled]);
+        assert!(named.itable.is_empty());
+        // the blocks follow the order the interface declares its methods in
+        assert_eq!(Some(0), labeled.get_itable_slot("id", "()I"));
+        assert_eq!(Some(1), labeled.get_itable_slot("label", "()I"));
+        assert_eq!(None, base.get_itable_slot("label", "()I"));
+        let method = |klass: &Klass, name: &str| {
+            let class = klass.bytecode.as_ref().unwrap();
+            Ok((
+                Arc::as_ptr(class),
+                Arc::as_ptr(&class.get_method(name, "()I").unwrap()),
+            ))
+        };
+        let entry = |klass: &Klass, ifs: &str, name: &str| {
+            klass.get_method_in_itable(ifs, name, "()I").cloned()
+        };
+        // the default of the more specific interface wins, also through the superinterface
+        for klass in &[&base, &derived] {
+            assert_eq!(
+                Some(method(&labeled, "id")),
+                entry(klass, "ItLabeled", "id")
+            );
+            assert_eq!(Some(method(&labeled, "id")), entry(klass, "ItNamed", "id"));
+            assert_eq!(
+                Some(method(&base, "label")),
+                entry(klass, "ItLabeled", "label")
+            );
+            assert_eq!(
+                Some(&method(&labeled, "id").unwrap()),
+                klass.get_method_in_vtable("id", "()I")
+            );
+        }
+        let conflict = Err("java/lang/IncompatibleClassChangeError");
+        assert_eq!(Some(conflict), entry(&both, "ItTagged", "id"));
+        assert_eq!(Some(conflict), entry(&both, "ItNamed", "id"));
+        // the inherited default keeps its slot, the conflict is told by the itable
+        assert_eq!(
+            Some(&method(&labeled, "id").unwrap()),
+            both.get_method_in_vtable("id", "()I")
+        );
+        let abstract_method = Err("java/lang/AbstractMethodError");
+        assert_eq!(Some(abstract_method), entry(&partial, "ItLabeled", "label"));
+        assert_eq!(
+            Some(method(&labeled, "id")),
+            entry(&partial, "ItLabeled", "id")
+        );
     }
 
     #[test]
-    pub fn test_itable() {}
+    pub fn test_subtype() {
+        use super::Klass;
+        use crate::mem::metaspace::ROOT_CLASSLOADER;
+        let object = Arc::new(Klass::new(
+            Arc::new(parse_class(JAVA_LANG_OBJECT)),
+            ROOT_CLASSLOADER,
+            None,
+            vec![],
+        ));
+        let animal = Arc::new(Klass::new(
+            Arc::new(parse_class(ANIMAL)),
+            ROOT_CLASSLOADER,
+            Some(object.clone()),
+            vec![],
+        ));
+        let pet = Arc::new(Klass::new(
+            Arc::new(parse_class(PET)),
+            ROOT_CLASSLOADER,
+            Some(object.clone()),
+            vec![animal.clone()],
+        ));
+        let dog = Arc::new(Klass::new(
+            Arc::new(parse_class(DOG)),
+            ROOT_CLASSLOADER,
+            Some(object.clone()),
+            vec![pet.clone()],
+        ));
+        let puppy = Arc::new(Klass::new(
+            Arc::new(parse_class(PUPPY)),
+            ROOT_CLASSLOADER,
+            Some(dog.clone()),
+            vec![],
+        ));
+        assert!(puppy.is_subtype_of("Puppy"));
+        assert!(puppy.is_subtype_of("Dog"));
+        assert!(puppy.is_subtype_of("Pet"));
+        assert!(puppy.is_subtype_of("Animal"));
+        assert!(puppy.is_subtype_of("java/lang/Object"));
+        assert!(pet.is_subtype_of("Animal"));
+        assert!(pet.is_subtype_of("java/lang/Object"));
+        assert!(!dog.is_subtype_of("Puppy"));
+        assert!(!animal.is_subtype_of("Pet"));
+        assert!(!object.is_subtype_of("Dog"));
+        assert!(!puppy.is_subtype_of("java/lang/Cloneable"));
+
+        let int = Arc::new(Klass::new_phantom_klass("I"));
+        let long = Arc::new(Klass::new_phantom_klass("J"));
+        let int_array = Arc::new(Klass::new_array_klass("[I", int.clone()));
+        let long_array = Arc::new(Klass::new_array_klass("[J", long.clone()));
+        let int_array_2d = Klass::new_array_klass("[[I", int_array.clone());
+        let puppy_array = Arc::new(Klass::new_array_klass("[LPuppy;", puppy.clone()));
+        let puppy_array_2d = Klass::new_array_klass("[[LPuppy;", puppy_array.clone());
+        let pet_array = Klass::new_array_klass("[LPet;", pet.clone());
+        for array in &[&*int_array, &*puppy_array, &pet_array, &puppy_array_2d] {
+            assert!(array.is_subtype_of("java/lang/Object"));
+            assert!(array.is_subtype_of("java/lang/Cloneable"));
+            assert!(array.is_subtype_of("java/io/Serializable"));
+            assert!(!array.is_subtype_of("Animal"));
+        }
+        assert!(int_array.is_subtype_of("[I"));
+        assert!(!int_array.is_subtype_of("[J"));
+        assert!(!long_array.is_subtype_of("[I"));
+        assert!(!int_array.is_subtype_of("[Ljava/lang/Object;"));
+        assert!(int_array_2d.is_subtype_of("[Ljava/lang/Object;"));
+        assert!(int_array_2d.is_subtype_of("[Ljava/lang/Cloneable;"));
+        assert!(!int_array_2d.is_subtype_of("[[J"));
+        assert!(puppy_array.is_subtype_of("[LDog;"));
+        assert!(puppy_array.is_subtype_of("[LAnimal;"));
+        assert!(puppy_array.is_subtype_of("[Ljava/lang/Object;"));
+        assert!(!puppy_array.is_subtype_of("[[LDog;"));
+        assert!(!pet_array.is_subtype_of("[LDog;"));
+        assert!(puppy_array_2d.is_subtype_of("[[LPet;"));
+        assert!(puppy_array_2d.is_subtype_of("[Ljava/lang/Object;"));
+        assert!(puppy_array_2d.is_subtype_of("[[Ljava/lang/Object;"));
+        assert!(puppy_array_2d.is_subtype_of("[Ljava/io/Serializable;"));
+        assert!(!puppy_array_2d.is_subtype_of("[LPuppy;"));
+    }
 
     #[test]
     pub fn test_layout() {
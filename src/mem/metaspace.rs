@@ -1,6 +1,7 @@
+use crate::bytecode::ClassFormatError;
 use crate::interpreter::thread::ThreadContext;
 use crate::mem::{klass::Klass, *};
-use log::trace;
+use log::{trace, warn};
 use std::sync::{Arc, Mutex};
 
 pub struct ClassArena {
@@ -59,7 +60,7 @@ impl ClassArena {
         unsafe { CLASSES.replace(Arc::new(arena)) };
     }
 
-    fn parse_class(class_name: &str) -> Option<Class> {
+    fn parse_class(class_name: &str) -> Option<Result<Class, ClassFormatError>> {
         if let Some(bytecode) = class_arena!().cp.find_app_class(class_name) {
             return Some(Class::from_vec(bytecode));
         }
@@ -72,14 +73,12 @@ impl ClassArena {
         None
     }
 
+    /// the error is the name of the Java error to throw if the class can't be loaded
     pub fn load_class(
         class_name: &str,
         context: &mut ThreadContext,
     ) -> Result<(Arc<Klass>, bool), String> {
-        let class_name = Regex::new(r"\.")
-            .unwrap()
-            .replace_all(class_name, "/")
-            .into_owned();
+        let class_name = class_name.replace('.', "/");
         match class_arena!().classes.get(&class_name) {
             Some(klass) => Ok((Arc::clone(&klass), true)),
             None => {
@@ -88,17 +87,27 @@ impl ClassArena {
                     return Ok((loaded.clone(), true));
                 }
                 if &class_name[..1] == "[" {
-                    let (_, initialized) = Self::load_class(&class_name[1..], context)?;
-                    let array_klass = Arc::new(Klass::new_phantom_klass(&class_name));
+                    let component = &class_name[1..];
+                    let component = if component.starts_with("L") && component.ends_with(";") {
+                        &component[1..component.len() - 1]
+                    } else {
+                        component
+                    };
+                    let (component, initialized) = Self::load_class(component, context)?;
+                    let array_klass = Arc::new(Klass::new_array_klass(&class_name, component));
                     class_arena!()
                         .classes
                         .insert(class_name, array_klass.clone());
                     return Ok((array_klass, initialized));
                 }
                 let class = match Self::parse_class(&class_name) {
-                    Some(class) => Arc::new(class),
+                    Some(Ok(class)) => Arc::new(class),
+                    Some(Err(error)) => {
+                        warn!("{}: {}", class_name, error);
+                        return Err("java/lang/ClassFormatError".to_owned());
+                    }
                     None => {
-                        return Err(class_name.to_owned());
+                        return Err("java/lang/NoClassDefFoundError".to_owned());
                     }
                 };
                 let superclass = if !class.get_super_class().is_empty() {
@@ -110,24 +119,63 @@ impl ClassArena {
                 for interface in class.get_interfaces() {
                     interfaces.push(Self::load_class(interface, context)?.0);
                 }
-                initialize_class(&class, context);
+                initialize_class(&class, context)?;
                 let klass = Arc::new(Klass::new(class, ROOT_CLASSLOADER, superclass, interfaces));
                 class_arena!().classes.insert(class_name, klass.clone());
                 Ok((klass, false))
             }
         }
     }
+
+    /// registers a class spun by the VM itself, the first definition of a name wins
+    pub fn define(klass: Klass) -> Arc<Klass> {
+        let name = klass.name.clone();
+        class_arena!()
+            .classes
+            .upsert(name.clone(), || Arc::new(klass), |_| {});
+        Arc::clone(&class_arena!().classes.get(&name).unwrap())
+    }
+
+    /// the classes loaded so far
+    pub fn loaded() -> Vec<Arc<Klass>> {
+        class_arena!()
+            .classes
+            .clone()
+            .into_iter()
+            .map(|(_, klass)| klass)
+            .collect()
+    }
 }
 
-fn initialize_class(class: &Arc<Class>, context: &mut ThreadContext) {
+// the error is the name of the Java error to throw if `<clinit>` can't run
+fn initialize_class(class: &Arc<Class>, context: &mut ThreadContext) -> Result<(), String> {
     trace!("initializing class {}", class.get_name());
     match class.get_method("<clinit>", "()V") {
         Some(clinit) => {
-            context.pc =
-                context
-                    .stack
-                    .invoke(Arc::as_ptr(&class), Arc::as_ptr(&clinit), context.pc, 0);
+            match context.invoke(Arc::as_ptr(&class), Arc::as_ptr(&clinit), context.pc, 0) {
+                Ok(pc) => context.pc = pc,
+                Err(message) => {
+                    warn!("{}: {}", class.get_name(), message);
+                    return Err("java/lang/VerifyError".to_owned());
+                }
+            }
         }
         None => {}
     }
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod test {
+
+    use super::*;
+    use std::sync::Once;
+
+    static INIT: Once = Once::new();
+
+    /// define `klass` in an arena without classpath, the first definition of a name wins
+    pub fn define_class(klass: Klass) -> Arc<Klass> {
+        INIT.call_once(|| ClassArena::init(vec![], vec![]));
+        ClassArena::define(klass)
+    }
 }
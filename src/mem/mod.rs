@@ -5,11 +5,12 @@ use crate::bytecode::class::Class;
 use crate::classpath::Classpath;
 
 use chashmap::CHashMap;
-use regex::Regex;
 
 pub mod heap;
 pub mod metaspace;
+pub mod monitor;
 pub mod klass;
+pub mod pool;
 pub mod stack;
 pub mod strings;
 
@@ -0,0 +1,368 @@
+use crate::mem::{
+    heap::Heap,
+    klass::{GC_AGE_MASK, LOCK_FREE_FLAG, LOCK_STATE_MASK},
+    Ref,
+};
+use lazy_static::lazy_static;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Condvar, Mutex, RwLock,
+};
+use std::time::Duration;
+
+// mark word layout, the gc age bits(3~6) are preserved in every state
+//
+// | owner thread(16~31) | recursions(7~15) | age | 000 |  thin locked
+// | monitor index(7~31)                     | age | 010 |  inflated
+// | unused(7~31)                            | age | 001 |  lock free
+
+const THIN_LOCKED_FLAG: u32 = 0x00;
+
+const INFLATED_FLAG: u32 = 0x02;
+
+const PAYLOAD_SHIFT: u32 = 7;
+
+const OWNER_SHIFT: u32 = 16;
+
+const RECURSION_MASK: u32 = 0xff80;
+
+const MAX_RECURSIONS: u32 = RECURSION_MASK >> PAYLOAD_SHIFT;
+
+// how long a blocked thread waits before it checks for a pause of the collector
+const SAFEPOINT_INTERVAL: Duration = Duration::from_millis(1);
+
+lazy_static! {
+    static ref MONITORS: RwLock<Monitors> = RwLock::new(Monitors {
+        inflated: vec![],
+        free: vec![],
+    });
+}
+
+// the inflated monitors by the index in their mark words, the indexes of deflated ones are
+// given to the next inflated
+struct Monitors {
+    inflated: Vec<Option<Arc<Monitor>>>,
+    free: Vec<usize>,
+}
+
+/// heavyweight reentrant monitor
+pub struct Monitor {
+    state: Mutex<State>,
+    cond: Condvar,
+}
+
+// `owner` 0 means nobody holds it, a deflated monitor is detached from its object
+struct State {
+    owner: u32,
+    recursions: u32,
+    waiters: u32,
+    deflated: bool,
+}
+
+impl Monitor {
+    fn new(owner: u32, recursions: u32) -> Self {
+        Self {
+            state: Mutex::new(State {
+                owner: owner,
+                recursions: recursions,
+                waiters: 0,
+                deflated: false,
+            }),
+            cond: Condvar::new(),
+        }
+    }
+
+    /// blocks until the monitor is entered by `thread` and calls `safepoint` meanwhile, false if
+    /// it was deflated before, which class monitors never are
+    pub fn enter<F: FnMut()>(&self, thread: u32, mut safepoint: F) -> bool {
+        let mut state = self.state.lock().unwrap();
+        state.waiters += 1;
+        while !state.deflated && state.owner != 0 && state.owner != thread {
+            state = self.cond.wait_timeout(state, SAFEPOINT_INTERVAL).unwrap().0;
+            // the owner must be able to exit while this thread is paused by the collector
+            drop(state);
+            safepoint();
+            state = self.state.lock().unwrap();
+        }
+        state.waiters -= 1;
+        if state.deflated {
+            return false;
+        }
+        state.owner = thread;
+        state.recursions += 1;
+        true
+    }
+
+    pub fn exit(&self, thread: u32) -> bool {
+        self.release(thread, || false)
+    }
+
+    // `deflate` is called once the monitor is free and nobody waits for it, it returns whether
+    // the monitor was detached from its object
+    fn release<F: FnOnce() -> bool>(&self, thread: u32, deflate: F) -> bool {
+        let mut state = self.state.lock().unwrap();
+        if state.owner != thread {
+            return false;
+        }
+        state.recursions -= 1;
+        if state.recursions == 0 {
+            state.owner = 0;
+            if state.waiters == 0 {
+                state.deflated = deflate();
+            } else {
+                self.cond.notify_one();
+            }
+        }
+        true
+    }
+}
+
+fn mark_word(obj: Ref) -> &'static AtomicU32 {
+    // the mark word is the first field of `ObjHeader`
+    unsafe { &*(Heap::ptr(obj as usize) as *const AtomicU32) }
+}
+
+// `None` if the monitor has been deflated since `mark` was read
+fn get_monitor(mark: u32) -> Option<Arc<Monitor>> {
+    let monitors = MONITORS.read().unwrap();
+    monitors.inflated[(mark >> PAYLOAD_SHIFT) as usize].clone()
+}
+
+// install a heavyweight monitor which inherits the thin lock, the caller retries on failure
+fn inflate(mark_word: &AtomicU32, mark: u32) {
+    let monitor = Arc::new(Monitor::new(
+        mark >> OWNER_SHIFT,
+        (mark & RECURSION_MASK) >> PAYLOAD_SHIFT,
+    ));
+    let mut monitors = MONITORS.write().unwrap();
+    let index = match monitors.free.last() {
+        Some(index) => *index,
+        None => monitors.inflated.len(),
+    };
+    let inflated = (index as u32) << PAYLOAD_SHIFT | (mark & GC_AGE_MASK) | INFLATED_FLAG;
+    if mark_word
+        .compare_exchange(mark, inflated, Ordering::AcqRel, Ordering::Acquire)
+        .is_ok()
+    {
+        if index == monitors.inflated.len() {
+            monitors.inflated.push(Some(monitor));
+        } else {
+            monitors.free.pop();
+            monitors.inflated[index] = Some(monitor);
+        }
+    }
+}
+
+// the owner leaves the object lock free once its monitor is detached
+fn deflate(mark_word: &AtomicU32, mark: u32) -> bool {
+    let mut monitors = MONITORS.write().unwrap();
+    let unlocked = (mark & GC_AGE_MASK) | LOCK_FREE_FLAG;
+    if mark_word
+        .compare_exchange(mark, unlocked, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        return false;
+    }
+    let index = (mark >> PAYLOAD_SHIFT) as usize;
+    monitors.inflated[index] = None;
+    monitors.free.push(index);
+    true
+}
+
+// `mark_word` is read again after every `safepoint`, which may move the object
+fn enter<'a, M, F>(mark_word: M, thread: u32, mut safepoint: F)
+where
+    M: Fn() -> &'a AtomicU32,
+    F: FnMut(),
+{
+    loop {
+        let word = mark_word();
+        let mark = word.load(Ordering::Acquire);
+        match mark & LOCK_STATE_MASK {
+            LOCK_FREE_FLAG => {
+                let locked = thread << OWNER_SHIFT
+                    | 1 << PAYLOAD_SHIFT
+                    | (mark & GC_AGE_MASK)
+                    | THIN_LOCKED_FLAG;
+                if word
+                    .compare_exchange(mark, locked, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    return;
+                }
+            }
+            THIN_LOCKED_FLAG => {
+                let recursions = (mark & RECURSION_MASK) >> PAYLOAD_SHIFT;
+                if mark >> OWNER_SHIFT == thread && recursions < MAX_RECURSIONS {
+                    let locked = mark + (1 << PAYLOAD_SHIFT);
+                    if word
+                        .compare_exchange(mark, locked, Ordering::AcqRel, Ordering::Acquire)
+                        .is_ok()
+                    {
+                        return;
+                    }
+                } else {
+                    inflate(word, mark);
+                }
+            }
+            INFLATED_FLAG => {
+                if let Some(monitor) = get_monitor(mark) {
+                    if monitor.enter(thread, &mut safepoint) {
+                        // the index may belong to the monitor of another object by now
+                        let current = mark_word().load(Ordering::Acquire);
+                        if current & !GC_AGE_MASK == mark & !GC_AGE_MASK {
+                            return;
+                        }
+                        monitor.exit(thread);
+                    }
+                }
+            }
+            _ => unreachable!(),
+        }
+        safepoint();
+    }
+}
+
+fn exit(mark_word: &AtomicU32, thread: u32) -> bool {
+    loop {
+        let mark = mark_word.load(Ordering::Acquire);
+        match mark & LOCK_STATE_MASK {
+            THIN_LOCKED_FLAG => {
+                if mark >> OWNER_SHIFT != thread {
+                    return false;
+                }
+                let unlocked = if (mark & RECURSION_MASK) >> PAYLOAD_SHIFT == 1 {
+                    (mark & GC_AGE_MASK) | LOCK_FREE_FLAG
+                } else {
+                    mark - (1 << PAYLOAD_SHIFT)
+                };
+                if mark_word
+                    .compare_exchange(mark, unlocked, Ordering::AcqRel, Ordering::Acquire)
+                    .is_ok()
+                {
+                    return true;
+                }
+            }
+            INFLATED_FLAG => {
+                return match get_monitor(mark) {
+                    Some(monitor) => monitor.release(thread, || deflate(mark_word, mark)),
+                    None => false,
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// monitorenter, blocks until the object `obj` points to is locked by `thread`, `obj` must be a
+/// root since `safepoint` is called while the thread is blocked
+pub fn monitor_enter<F: FnMut()>(obj: *const Ref, thread: u32, safepoint: F) {
+    enter(|| mark_word(unsafe { *obj }), thread, safepoint);
+}
+
+/// monitorexit, returns false if `thread` doesn't own the object's monitor
+pub fn monitor_exit(obj: Ref, thread: u32) -> bool {
+    exit(mark_word(obj), thread)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use std::sync::atomic::AtomicU32;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    pub fn test_thin_lock() {
+        let mark = AtomicU32::new(LOCK_FREE_FLAG | 0x28);
+        assert!(!exit(&mark, 1));
+        enter(|| &mark, 1, || {});
+        assert_eq!(
+            THIN_LOCKED_FLAG,
+            mark.load(Ordering::Acquire) & LOCK_STATE_MASK
+        );
+        enter(|| &mark, 1, || {});
+        assert!(!exit(&mark, 2));
+        assert!(exit(&mark, 1));
+        assert!(exit(&mark, 1));
+        assert!(!exit(&mark, 1));
+        assert_eq!(LOCK_FREE_FLAG | 0x28, mark.load(Ordering::Acquire));
+    }
+
+    #[test]
+    pub fn test_recursion_overflow() {
+        let mark = AtomicU32::new(LOCK_FREE_FLAG);
+        for _ in 0..MAX_RECURSIONS + 10 {
+            enter(|| &mark, 3, || {});
+        }
+        assert_eq!(
+            INFLATED_FLAG,
+            mark.load(Ordering::Acquire) & LOCK_STATE_MASK
+        );
+        for _ in 0..MAX_RECURSIONS + 10 {
+            assert!(exit(&mark, 3));
+        }
+        // deflated by the final exit
+        assert_eq!(LOCK_FREE_FLAG, mark.load(Ordering::Acquire));
+        assert!(!exit(&mark, 3));
+    }
+
+    #[test]
+    pub fn test_contention() {
+        let mark = Arc::new(AtomicU32::new(LOCK_FREE_FLAG | 0x78));
+        let counter = Arc::new(AtomicU32::new(0));
+        let threads = (1..=4)
+            .map(|thread| {
+                let mark = Arc::clone(&mark);
+                let counter = Arc::clone(&counter);
+                std::thread::spawn(move || {
+                    for _ in 0..200 {
+                        enter(|| &*mark, thread, || {});
+                        enter(|| &*mark, thread, || {});
+                        // not atomic as a whole, only safe under the monitor
+                        let v = counter.load(Ordering::Relaxed);
+                        std::thread::yield_now();
+                        counter.store(v + 1, Ordering::Relaxed);
+                        assert!(exit(&mark, thread));
+                        assert!(exit(&mark, thread));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(800, counter.load(Ordering::Relaxed));
+        assert_eq!(LOCK_FREE_FLAG | 0x78, mark.load(Ordering::Acquire));
+        assert!(!exit(&mark, 1));
+    }
+
+    #[test]
+    pub fn test_safepoint() {
+        let mark = Arc::new(AtomicU32::new(LOCK_FREE_FLAG));
+        enter(|| &*mark, 1, || {});
+        let (pause_tx, pause_rx) = channel();
+        let (roots_tx, roots_rx) = channel();
+        let blocked = {
+            let mark = Arc::clone(&mark);
+            std::thread::spawn(move || {
+                enter(
+                    || &*mark,
+                    2,
+                    || {
+                        if pause_rx.try_recv().is_ok() {
+                            roots_tx.send(()).unwrap();
+                        }
+                    },
+                );
+                assert!(exit(&mark, 2));
+            })
+        };
+        // a collection is answered by the thread waiting for the monitor
+        pause_tx.send(()).unwrap();
+        roots_rx.recv().unwrap();
+        assert!(exit(&mark, 1));
+        blocked.join().unwrap();
+        assert_eq!(LOCK_FREE_FLAG, mark.load(Ordering::Acquire));
+    }
+}
@@ -0,0 +1,490 @@
+use crate::{
+    bytecode::{
+        self, atom::*, class::Class, constant_pool::ConstantItem, field::Field, method::Method,
+    },
+    interpreter::thread::ThreadContext,
+    mem::{
+        klass::{Klass, MethodRef},
+        metaspace::ClassArena,
+        Ref,
+    },
+};
+use std::sync::{Arc, RwLock};
+
+// The runtime constant pool of a class resolves a symbolic reference the first time an
+// instruction uses it, later executions take the resolved entry. A linkage error is kept the
+// same way, so a failed resolution throws the same error every time without being retried.
+
+/// a resolved entry of the runtime constant pool
+#[derive(Clone)]
+pub enum Entry {
+    /// a class, interface or array class
+    Class(Arc<Klass>),
+    /// an instance field by its offset in the object and its width
+    Field(usize, usize),
+    /// a static field, which holds its value
+    Static(Arc<Field>),
+    /// the referenced class, the resolved method, its vtable slot if it's dispatched on, or its
+    /// itable slot if it's declared by an interface and referenced through one, and the slots
+    /// of its arguments
+    Method(*const Klass, MethodRef, Option<usize>, usize),
+    /// a method type or method handle
+    Object(Ref),
+}
+
+// indexed like the constant pool, grown on demand
+pub struct RuntimeConstantPool(RwLock<Vec<Option<Result<Entry, String>>>>);
+
+// unwraps a loaded value, or returns early when a class initialization has to run first
+#[macro_export]
+macro_rules! ready {
+    ($e:expr) => {
+        match $e? {
+            Some(v) => v,
+            None => return Ok(None),
+        }
+    };
+}
+
+impl RuntimeConstantPool {
+    pub fn new() -> Self {
+        RuntimeConstantPool(RwLock::new(vec![]))
+    }
+
+    fn get(&self, idx: U2) -> Option<Result<Entry, String>> {
+        self.0.read().unwrap().get(idx as usize).cloned().flatten()
+    }
+
+    // the first resolution wins when threads race
+    fn put(&self, idx: U2, resolved: Result<Entry, String>) -> Result<Entry, String> {
+        let mut entries = self.0.write().unwrap();
+        if entries.len() <= idx as usize {
+            entries.resize(idx as usize + 1, None);
+        }
+        entries[idx as usize].get_or_insert(resolved).clone()
+    }
+}
+
+/// the class at `idx` of `class`, all the `resolve_*` functions return `Ok(None)` if
+/// resolution triggered a class initialization and the instruction must be retried, `Err`
+/// carries the name of the error to throw
+pub fn resolve_class(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+) -> Result<Option<Arc<Klass>>, String> {
+    let name = class.constant_pool.get_str(idx);
+    let link = |context: &mut ThreadContext| link_class(context, name);
+    match ready!(resolve(context, class, idx, link)) {
+        Entry::Class(klass) => Ok(Some(klass)),
+        _ => panic!("invalid class file"),
+    }
+}
+
+/// the field at `idx` of `class`, either `Entry::Field` or `Entry::Static`
+pub fn resolve_field(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+) -> Result<Option<Entry>, String> {
+    resolve(context, class, idx, |context| {
+        let (c, (name, descriptor)) = match class.constant_pool.get(idx) {
+            ConstantItem::FieldRef(c, nt) => (*c, class.constant_pool.get_name_and_type(*nt)),
+            _ => panic!("invalid class file"),
+        };
+        let klass = ready!(resolve_class(context, class, c));
+        let (declaring, field) =
+            find_field(&klass, name, descriptor).ok_or("java/lang/NoSuchFieldError")?;
+        if field.is_static() {
+            return Ok(Some(Entry::Static(field)));
+        }
+        match klass
+            .layout
+            .get(&(declaring.name.as_ref(), name, descriptor))
+        {
+            Some((offset, width)) => Ok(Some(Entry::Field(*offset, *width))),
+            None => Err("java/lang/NoSuchFieldError".to_owned()),
+        }
+    })
+}
+
+/// the method at `idx` of `class` by JVMS 5.4.3.3 and 5.4.3.4, always `Entry::Method`
+pub fn resolve_method(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+) -> Result<Option<Entry>, String> {
+    resolve(context, class, idx, |context| {
+        let (c, nt, interface) = match class.constant_pool.get(idx) {
+            ConstantItem::MethodRef(c, nt) => (*c, *nt, false),
+            ConstantItem::InterfaceMethodRef(c, nt) => (*c, *nt, true),
+            _ => panic!("invalid class file"),
+        };
+        let (name, descriptor) = class.constant_pool.get_name_and_type(nt);
+        let klass = ready!(resolve_class(context, class, c));
+        let is_interface = klass
+            .bytecode
+            .as_ref()
+            .map_or(false, |named| named.is_interface());
+        if is_interface != interface {
+            return Err("java/lang/IncompatibleClassChangeError".to_owned());
+        }
+        let (declaring, method) =
+            find_method(&klass, name, descriptor).ok_or("java/lang/NoSuchMethodError")?;
+        let slot = if method.is_private() || method.is_static() {
+            None
+        } else if declaring.bytecode.as_ref().unwrap().is_interface() {
+            if interface {
+                declaring.get_itable_slot(name, descriptor)
+            } else {
+                // a class holds a slot for the interface methods it doesn't implement
+                klass.get_vtable_slot(name, descriptor)
+            }
+        } else {
+            declaring.get_vtable_slot(name, descriptor)
+        };
+        let (_, slots, _) = bytecode::resolve_method_descriptor(descriptor, method.access_flag);
+        let method = (
+            Arc::as_ptr(declaring.bytecode.as_ref().unwrap()),
+            Arc::as_ptr(&method),
+        );
+        Ok(Some(Entry::Method(
+            Arc::as_ptr(&klass),
+            method,
+            slot,
+            slots,
+        )))
+    })
+}
+
+/// the string, method type or method handle at `idx` of `class`, built by `link` the first time
+pub fn resolve_object<F>(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+    link: F,
+) -> Result<Option<Ref>, String>
+where
+    F: FnOnce(&mut ThreadContext) -> Result<Option<Ref>, String>,
+{
+    let linked = |context: &mut ThreadContext| Ok(Some(Entry::Object(ready!(link(context)))));
+    match ready!(resolve(context, class, idx, linked)) {
+        Entry::Object(obj) => Ok(Some(obj)),
+        _ => panic!("invalid class file"),
+    }
+}
+
+fn resolve<F>(
+    context: &mut ThreadContext,
+    class: &Class,
+    idx: U2,
+    link: F,
+) -> Result<Option<Entry>, String>
+where
+    F: FnOnce(&mut ThreadContext) -> Result<Option<Entry>, String>,
+{
+    if let Some(resolved) = class.runtime_pool.get(idx) {
+        return resolved.map(Some);
+    }
+    let resolved = match link(context) {
+        Ok(None) => return Ok(None),
+        Ok(Some(entry)) => Ok(entry),
+        Err(error) => Err(error),
+    };
+    class.runtime_pool.put(idx, resolved).map(Some)
+}
+
+fn link_class(context: &mut ThreadContext, name: &str) -> Result<Option<Entry>, String> {
+    match ClassArena::load_class(name, context) {
+        Ok((klass, true)) => Ok(Some(Entry::Class(klass))),
+        Ok(_) => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// fields are looked up in the class, then its superinterfaces, then its superclass
+pub fn find_field<'a>(
+    klass: &'a Klass,
+    name: &str,
+    descriptor: &str,
+) -> Option<(&'a Klass, Arc<Field>)> {
+    klass
+        .bytecode
+        .as_ref()
+        .and_then(|class| class.get_field(name, descriptor))
+        .map(|field| (klass, field))
+        .or_else(|| {
+            klass
+                .superinterfaces
+                .iter()
+                .find_map(|interface| find_field(interface, name, descriptor))
+        })
+        .or_else(|| {
+            klass
+                .superclass
+                .as_ref()
+                .and_then(|superclass| find_field(superclass, name, descriptor))
+        })
+}
+
+/// methods are looked up in the class and its superclasses, then among the superinterface
+/// methods by JVMS 5.4.3.3 and 5.4.3.4, the only non-abstract maximally-specific one is preferred
+pub fn find_method<'a>(
+    klass: &'a Klass,
+    name: &str,
+    descriptor: &str,
+) -> Option<(&'a Klass, Arc<Method>)> {
+    let is_interface = klass
+        .bytecode
+        .as_ref()
+        .map_or(false, |class| class.is_interface());
+    let mut current = Some(klass);
+    while let Some(thisclass) = current {
+        if let Some(method) = thisclass
+            .bytecode
+            .as_ref()
+            .and_then(|class| class.get_method(name, descriptor))
+        {
+            // an interface only sees the public instance methods of java.lang.Object
+            let visible = !is_interface
+                || std::ptr::eq(thisclass, klass)
+                || (method.is_public() && !method.is_static());
+            if visible {
+                return Some((thisclass, method));
+            }
+        }
+        current = thisclass.superclass.as_deref();
+    }
+    let candidates = superinterfaces(klass)
+        .into_iter()
+        .filter_map(|ifs| {
+            let method = ifs.bytecode.as_ref()?.get_method(name, descriptor)?;
+            if method.is_private() || method.is_static() {
+                None
+            } else {
+                Some((ifs, method))
+            }
+        })
+        .collect::<Vec<_>>();
+    let specific = candidates
+        .iter()
+        .filter(|(ifs, _)| {
+            !candidates
+                .iter()
+                .any(|(sub, _)| sub.name != ifs.name && sub.is_subtype_of(&ifs.name))
+        })
+        .collect::<Vec<_>>();
+    let mut concrete = specific.iter().filter(|(_, method)| !method.is_abstract());
+    match (concrete.next(), concrete.next()) {
+        (Some(found), None) => Some(*found),
+        // otherwise any of them
+        _ => specific.first().copied(),
+    }
+    .map(|(ifs, method)| (*ifs, Arc::clone(method)))
+}
+
+// the superinterfaces of `klass` and its superclasses, each once
+fn superinterfaces(klass: &Klass) -> Vec<&Klass> {
+    let mut pending = vec![];
+    let mut current = Some(klass);
+    while let Some(thisclass) = current {
+        pending.extend(thisclass.superinterfaces.iter().map(|ifs| ifs.as_ref()));
+        current = thisclass.superclass.as_deref();
+    }
+    let mut interfaces: Vec<&Klass> = vec![];
+    while let Some(ifs) = pending.pop() {
+        if interfaces.iter().all(|i| i.name != ifs.name) {
+            pending.extend(ifs.superinterfaces.iter().map(|i| i.as_ref()));
+            interfaces.push(ifs);
+        }
+    }
+    interfaces
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::bytecode::constant_pool::ConstantPool;
+    use crate::mem::{klass::test::define_object, metaspace::test::define_class};
+    use std::cell::Cell;
+    use std::sync::mpsc::channel;
+
+    fn class(
+        name: &str,
+        superclass: &str,
+        items: Vec<ConstantItem>,
+        fields: &[(U2, &str)],
+    ) -> Class {
+        Class {
+            constant_pool: ConstantPool::new(items),
+            access_flag: 0x0021,
+            this_class_name: name.to_owned(),
+            super_class_name: superclass.to_owned(),
+            interfaces: vec![],
+            fields: fields
+                .iter()
+                .map(|(access_flag, name)| {
+                    Arc::new(Field {
+                        access_flag: *access_flag,
+                        name: name.to_string(),
+                        descriptor: "I".to_owned(),
+                        attributes: vec![],
+                        value: Cell::new(None),
+                    })
+                })
+                .collect(),
+            methods: vec![],
+            attributes: vec![],
+            runtime_pool: RuntimeConstantPool::new(),
+        }
+    }
+
+    #[test]
+    pub fn test_resolve() {
+        let object = define_object();
+        let pool_base = class(
+            "PoolBase",
+            "java/lang/Object",
+            vec![ConstantItem::NIL],
+            &[(0x0001, "value"), (0x0009, "count")],
+        );
+        let pool_base = define_class(Klass::new(Arc::new(pool_base), 0, Some(object), vec![]));
+        let pool_sub = class("PoolSub", "PoolBase", vec![ConstantItem::NIL], &[]);
+        let pool_sub = define_class(Klass::new(
+            Arc::new(pool_sub),
+            0,
+            Some(Arc::clone(&pool_base)),
+            vec![],
+        ));
+        let utf8 = |s: &str| ConstantItem::UTF8(s.to_owned());
+        let caller = class(
+            "PoolCaller",
+            "java/lang/Object",
+            vec![
+                ConstantItem::NIL,
+                utf8("PoolSub"),
+                ConstantItem::Class(1),
+                utf8("value"),
+                utf8("I"),
+                ConstantItem::NameAndType(3, 4),
+                ConstantItem::FieldRef(2, 5),
+                utf8("count"),
+                ConstantItem::NameAndType(7, 4),
+                ConstantItem::FieldRef(2, 8),
+                utf8("toString"),
+                utf8("()Ljava/lang/String;"),
+                ConstantItem::NameAndType(10, 11),
+                ConstantItem::MethodRef(2, 12),
+                utf8("PoolMissing"),
+                ConstantItem::Class(14),
+                ConstantItem::FieldRef(15, 5),
+                ConstantItem::InterfaceMethodRef(2, 12),
+            ],
+            &[],
+        );
+        let (_, rx) = channel();
+        let mut context = ThreadContext::new(1, 0, rx, channel().0);
+        let klass = resolve_class(&mut context, &caller, 2).unwrap().unwrap();
+        assert!(Arc::ptr_eq(&pool_sub, &klass));
+
+        // the field inherited by the referenced class
+        let (offset, width) = pool_base.layout.get(&("PoolBase", "value", "I")).unwrap();
+        match resolve_field(&mut context, &caller, 6) {
+            Ok(Some(Entry::Field(o, w))) => assert_eq!((*offset, *width), (o, w)),
+            _ => panic!("instance field unresolved"),
+        }
+        let count = pool_base.bytecode.as_ref().unwrap().get_field("count", "I");
+        match resolve_field(&mut context, &caller, 9) {
+            Ok(Some(Entry::Static(field))) => assert!(Arc::ptr_eq(&count.unwrap(), &field)),
+            _ => panic!("static field unresolved"),
+        }
+
+        // the method of java.lang.Object in its slot
+        let slot = pool_sub.get_vtable_slot("toString", "()Ljava/lang/String;");
+        match resolve_method(&mut context, &caller, 13) {
+            Ok(Some(Entry::Method(klass, method, s, slots))) => {
+                assert_eq!(Arc::as_ptr(&pool_sub), klass);
+                assert_eq!(slot, s);
+                assert_eq!(1, slots);
+                assert_eq!(Some(&method), pool_sub.vtable.get(slot.unwrap()));
+            }
+            _ => panic!("method unresolved"),
+        }
+        let icce = Err("java/lang/IncompatibleClassChangeError".to_owned());
+        assert_eq!(icce, resolve_method(&mut context, &caller, 17).map(|_| ()));
+
+        // the error is kept even when the class shows up later
+        let missing = Err("java/lang/NoClassDefFoundError".to_owned());
+        assert_eq!(
+            missing,
+            resolve_field(&mut context, &caller, 16).map(|_| ())
+        );
+        let pool_missing = class(
+            "PoolMissing",
+            "java/lang/Object",
+            vec![],
+            &[(0x0001, "value")],
+        );
+        define_class(Klass::new(
+            Arc::new(pool_missing),
+            0,
+            Some(define_object()),
+            vec![],
+        ));
+        assert_eq!(
+            missing,
+            resolve_field(&mut context, &caller, 16).map(|_| ())
+        );
+        assert_eq!(
+            missing,
+            resolve_class(&mut context, &caller, 15).map(|_| ())
+        );
+    }
+
+    #[test]
+    pub fn test_find_method() {
+        let object = define_object();
+        let method = |access_flag: U2| {
+            Arc::new(Method {
+                access_flag,
+                name: "id".to_owned(),
+                descriptor: "()I".to_owned(),
+                attributes: vec![],
+                decoded: RwLock::new(None),
+            })
+        };
+        let klass = |name: &str, interface: bool, methods, interfaces: Vec<&Arc<Klass>>| {
+            let mut class = class(name, "java/lang/Object", vec![], &[]);
+            if interface {
+                class.access_flag = 0x0601;
+            }
+            class.methods = methods;
+            Arc::new(Klass::new(
+                Arc::new(class),
+                0,
+                Some(Arc::clone(&object)),
+                interfaces.into_iter().cloned().collect(),
+            ))
+        };
+        // interface PoolNamed { default int id() }
+        // interface PoolLabeled extends PoolNamed { default int id() }
+        // class PoolShape implements PoolNamed, PoolLabeled {}
+        let named = klass("PoolNamed", true, vec![method(0x0001)], vec![]);
+        let labeled = klass("PoolLabeled", true, vec![method(0x0001)], vec![&named]);
+        let shape = klass("PoolShape", false, vec![], vec![&named, &labeled]);
+        let (declaring, _) = find_method(&shape, "id", "()I").unwrap();
+        assert_eq!("PoolLabeled", declaring.name);
+        // the abstract redeclaration is the only maximally-specific one
+        let rewound = klass("PoolRewound", true, vec![method(0x0401)], vec![&named]);
+        let partial = klass("PoolPartial", false, vec![], vec![&named, &rewound]);
+        let (declaring, found) = find_method(&partial, "id", "()I").unwrap();
+        assert_eq!("PoolRewound", declaring.name);
+        assert!(found.is_abstract());
+        // an interface inherits only the public instance methods of java.lang.Object
+        let (declaring, _) = find_method(&named, "hashCode", "()I").unwrap();
+        assert_eq!("java/lang/Object", declaring.name);
+        assert!(find_method(&named, "clone", "()Ljava/lang/Object;").is_none());
+        assert!(find_method(&shape, "clone", "()Ljava/lang/Object;").is_some());
+    }
+}
@@ -1,8 +1,11 @@
 use crate::{
     bytecode,
-    bytecode::{class::Class, method::Method},
-    mem::{klass::*, *},
+    bytecode::{class::Class, code::Code, method::Method},
+    mem::{klass::*, monitor::*, *},
 };
+use log::trace;
+use std::cell::Cell;
+use std::sync::Arc;
 
 const DEFAULT_STACK_LEN: usize = 128 * 1024;
 
@@ -10,6 +13,7 @@ pub struct JavaStack {
     data: Vec<u8>,
     frames: Vec<JavaFrame>,
     max_stack_size: usize,
+    thread: u32,
 }
 
 pub struct JavaFrame {
@@ -17,20 +21,28 @@ pub struct JavaFrame {
     operands: *mut u8,
     class: *const Class,
     method: *const Method,
+    code: *const Code,
     pc: usize,
     max_locals: usize,
     active_refs: Vec<*mut Ref>,
+    // the object of a synchronized method, a root the collector rewrites
+    locked: Option<Cell<Ref>>,
 }
 
 impl JavaStack {
-    pub fn new() -> Self {
+    pub fn new(thread: u32) -> Self {
         Self {
             data: vec![0u8; DEFAULT_STACK_LEN],
             frames: Vec::<JavaFrame>::with_capacity(256),
             max_stack_size: DEFAULT_STACK_LEN,
+            thread: thread,
         }
     }
 
+    pub fn thread(&self) -> u32 {
+        self.thread
+    }
+
     pub fn frame(&self) -> &JavaFrame {
         self.frames.last().expect("empty_stack")
     }
@@ -66,13 +78,7 @@ impl JavaStack {
     pub fn has_next(&self, pc: usize) -> bool {
         match self.frames.last() {
             None => false,
-            Some(ref f) => {
-                pc < unsafe { &*f.method }
-                    .get_code()
-                    .expect("null_code_attribute")
-                    .2
-                    .len()
-            }
+            Some(ref f) => pc < unsafe { &*f.code }.insns.len(),
         }
     }
 
@@ -92,29 +98,48 @@ impl JavaStack {
         self.frame().class
     }
 
+    pub fn code_ptr(&self) -> *const Code {
+        self.frame().code
+    }
+
     pub fn is_empty(&self) -> bool {
         self.frames.is_empty()
     }
 
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
     pub fn append_ref_to_roots(&mut self, ref_ptr: *mut Ref) {
         self.mut_frame().active_refs.push(ref_ptr);
     }
 
     pub fn collect_tracing_roots(&self) -> Vec<*mut Ref> {
-        self.frames
+        let mut roots = self
+            .frames
             .iter()
             .flat_map(|f| f.active_refs.iter())
             .map(|p| *p)
-            .collect::<Vec<_>>()
+            .collect::<Vec<_>>();
+        roots.extend(
+            self.frames
+                .iter()
+                .filter_map(|f| f.locked.as_ref().map(|this| this.as_ptr())),
+        );
+        roots
     }
 
+    /// pushes the frame of `method`, `mirror` is the class object a static synchronized method
+    /// locks, `safepoint` is called while a synchronized method waits for its monitor
     pub fn invoke(
         &mut self,
         class: *const Class,
         method: *const Method,
         pc: usize,
         locals: usize,
-    ) -> usize {
+        mirror: Option<Ref>,
+        safepoint: &dyn Fn(&JavaStack),
+    ) -> Result<usize, String> {
         let m = unsafe { &*method };
         let (_, desc, access_flag) = m.get_name_and_descriptor();
         let (params, slots, ret) = bytecode::resolve_method_descriptor(desc, access_flag);
@@ -126,9 +151,14 @@ impl JavaStack {
                 _ => 1,
             };
             self.downward(slots - ret);
-            return pc;
+            return Ok(pc);
         }
         // -------------------------------------------------------------
+        let constants = unsafe { &(*class).constant_pool };
+        let (max_locals, code) = match (m.get_code(), m.get_decoded(constants)) {
+            (Some((_, max_locals, _, _, _)), Some(code)) => (max_locals as usize, code?),
+            _ => panic!("AbstractMethod"),
+        };
         let locals = unsafe {
             if self.is_empty() {
                 self.data.as_mut_ptr().add(locals * PTR_SIZE)
@@ -152,59 +182,91 @@ impl JavaStack {
             }
         }
         let method_ref = unsafe { &*method };
-        match method_ref.get_code() {
-            None => panic!("AbstractMethod"),
-            Some((_, max_locals, _, _, _)) => self.frames.push(JavaFrame {
-                locals: locals,
-                operands: unsafe { locals.add(max_locals as usize * PTR_SIZE) },
-                class: class,
-                method: method,
-                pc: pc,
-                max_locals: max_locals as usize,
-                active_refs: active_refs,
-            }),
+        let locked = if !method_ref.is_synchronized() {
+            None
+        } else if method_ref.is_static() {
+            Some(Cell::new(mirror.expect("mirror_missing")))
+        } else {
+            Some(Cell::new(unsafe { *locals.cast::<Ref>() }))
+        };
+        self.frames.push(JavaFrame {
+            locals: locals,
+            operands: unsafe { locals.add(max_locals * PTR_SIZE) },
+            class: class,
+            method: method,
+            // kept alive by the method
+            code: Arc::as_ptr(&code),
+            pc: pc,
+            max_locals: max_locals,
+            active_refs: active_refs,
+            locked: locked,
+        });
+        // locked once the frame is pushed, a collection meanwhile sees its roots
+        if let Some(this) = &self.frame().locked {
+            monitor_enter(this.as_ptr(), self.thread, || safepoint(self));
         }
-        0
+        Ok(0)
     }
 
-    pub fn return_normal(&mut self) -> usize {
+    // releases the monitor of the current frame once, it is an error if the thread doesn't own
+    // it anymore
+    fn unlock(&mut self) -> Result<(), String> {
+        match self.mut_frame().locked.take() {
+            Some(this) if !monitor_exit(this.get(), self.thread) => {
+                Err("java/lang/IllegalMonitorStateException".to_owned())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// pops the current frame, the frame stays if its monitor was exited unbalanced and the error
+    /// is to be thrown by the return
+    pub fn return_normal(&mut self) -> Result<usize, String> {
+        self.unlock()?;
         let frame = self.frames.pop().expect("empty_stack");
         if !self.is_empty() {
-            let (_, descriptor, access_flag) = &self.method().get_name_and_descriptor();
-            let (_, _, ret) = bytecode::resolve_method_descriptor(descriptor, *access_flag);
+            let method = unsafe { &*frame.method };
+            let (_, descriptor, access_flag) = method.get_name_and_descriptor();
+            let (_, _, ret) = bytecode::resolve_method_descriptor(descriptor, access_flag);
             let slots: usize = match ret.as_ref() {
                 "D" | "J" => 2,
                 "V" => 0,
                 _ => 1,
             };
+            // the arguments are popped, the locals of the returning frame start where they were
+            self.update(frame.locals);
             unsafe {
                 let val = frame.operands.sub(slots * PTR_SIZE);
                 self.operands().copy_from(val, slots * PTR_SIZE);
                 self.update(self.operands().add(slots * PTR_SIZE));
             }
         }
-        frame.pc
+        Ok(frame.pc)
     }
 
-    pub fn fire_exception(&mut self) -> usize {
+    /// pops the current frame throwing to its caller, the frame stays if its monitor was exited
+    /// unbalanced and the error is to be thrown in place of the pending exception
+    pub fn fire_exception(&mut self) -> Result<usize, String> {
+        self.unlock()?;
         let frame = self.frames.pop().expect("empty_stack");
         if !self.is_empty() {
+            self.update(frame.locals);
             unsafe {
                 let error = frame.operands.sub(PTR_SIZE);
                 self.operands().copy_from(error, PTR_SIZE);
                 self.update(self.operands().add(PTR_SIZE));
             }
         }
-        frame.pc
+        Ok(frame.pc)
     }
 
     pub fn match_exception_table(&self, pc: usize, klass: &Klass) -> Option<usize> {
-        let exception_table = self.method().get_code().unwrap().3;
-        for handler in exception_table.as_slice() {
+        let code = unsafe { &*self.code_ptr() };
+        for handler in &code.handlers {
             if pc >= handler.start_pc as usize && pc < handler.end_pc as usize {
                 match &handler.catch_type {
                     Some(exception_type) => {
-                        if klass.is_superclass(&exception_type) {
+                        if klass.is_subtype_of(&exception_type) {
                             return Some(handler.handler_pc as usize);
                         }
                     }
@@ -215,8 +277,35 @@ impl JavaStack {
         None
     }
 
-    pub fn code_at(&self, pc: usize) -> u8 {
-        self.method().get_code().unwrap().2[pc]
+    /// the frames from the current one down as `java.lang.Throwable` prints them, `pc` is the
+    /// index of the current instruction
+    pub fn backtrace(&self, pc: usize) -> Vec<String> {
+        let mut pc = pc;
+        let mut frames = Vec::with_capacity(self.frames.len());
+        for frame in self.frames.iter().rev() {
+            let class = unsafe { &*frame.class };
+            let method = unsafe { &*frame.method };
+            // the line numbers are keyed by bytecode offset
+            let code = unsafe { &*frame.code };
+            let line = code
+                .pcs
+                .get(pc)
+                .and_then(|offset| method.line_for_pc(*offset));
+            let location = match (class.source_file(), line) {
+                (Some(file), Some(line)) => format!("{}:{}", file, line),
+                (Some(file), None) => file.to_owned(),
+                (None, _) => "Unknown Source".to_owned(),
+            };
+            frames.push(format!(
+                "{}.{}({})",
+                class.get_name().replace('/', "."),
+                method.name,
+                location
+            ));
+            // the caller is at the invocation, the frame keeps the pc following it
+            pc = frame.pc.saturating_sub(1);
+        }
+        frames
     }
 
     pub fn load(&mut self, offset: usize, count: usize) {
@@ -292,8 +381,8 @@ impl JavaStack {
     where
         F: Fn(Slot, Slot) -> Slot,
     {
-        let left = self.pop();
         let right = self.pop();
+        let left = self.pop();
         self.push(&f(left, right));
     }
 
@@ -301,8 +390,8 @@ impl JavaStack {
     where
         F: Fn(WideSlot, WideSlot) -> WideSlot,
     {
-        let left = self.pop_w();
         let right = self.pop_w();
+        let left = self.pop_w();
         self.push_w(&f(left, right));
     }
 
@@ -334,25 +423,25 @@ impl JavaStack {
         unsafe { &*self.operands().sub(PTR_SIZE * n).cast::<Slot>() }
     }
 
+    /// traces the current frame before the instruction at `pc` runs
     pub fn dump(&self, pc: usize) {
         let (name, descriptor, _) = self.method().get_name_and_descriptor();
-        println!("method layer: {}", self.frames.len() - 1);
-        println!("current class: {:?}", self.class().get_name());
-        println!("current method: {:?} {:?}", name, descriptor);
+        trace!("method layer: {}", self.frames.len() - 1);
+        trace!("current class: {:?}", self.class().get_name());
+        trace!("current method: {:?} {:?}", name, descriptor);
         let locals_offset = self.locals() as usize - self.data.as_ptr() as usize;
-        println!(
+        trace!(
             "locals: {:02x?}",
             &self.data[locals_offset..locals_offset + self.frame().max_locals * PTR_SIZE]
         );
         let operands_offset = self.operands() as usize - self.data.as_ptr() as usize;
-        println!(
+        trace!(
             "operands: {:02x?}",
             &self.data[locals_offset + self.frame().max_locals * PTR_SIZE..operands_offset]
         );
-        println!("pc: {:?}", pc);
-        println!("[pc]: {:02x?}", self.code_at(pc));
-        println!("code: {:02x?}", self.method().get_code().unwrap().2);
-        println!();
+        let code = unsafe { &*self.code_ptr() };
+        trace!("pc: {:?} ({:?})", pc, code.pc_of(pc));
+        trace!("[pc]: {:?}", code.insns[pc]);
     }
 }
 
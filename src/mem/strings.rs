@@ -1,10 +1,18 @@
 use crate::{
     interpreter::thread::ThreadContext,
-    mem::{heap::Heap, metaspace::ClassArena, Ref},
+    mem::{heap::Heap, klass::*, metaspace::ClassArena, Ref, PTR_SIZE},
 };
-use std::{collections::HashMap, sync::RwLock};
+use std::{collections::HashMap, sync::Arc, sync::RwLock};
 
-pub struct Strings(RwLock<HashMap<String, Ref>>);
+// `java.lang.String` keeps its content in `char[] value` up to JDK 8, the compact strings of
+// JDK 9 and later keep it in `byte[] value` with `coder` telling LATIN1 from UTF16.
+
+const LATIN1: u8 = 0;
+
+const UTF16: u8 = 1;
+
+// interned by the UTF-16 content, which a Rust string can't hold for an unpaired surrogate
+pub struct Strings(RwLock<HashMap<Vec<u16>, Ref>>);
 
 static mut STRINGS: Option<Strings> = None;
 
@@ -28,26 +36,208 @@ impl Strings {
     }
 
     pub fn get(constant: &str, context: &mut ThreadContext) -> Ref {
+        Self::intern(&constant.encode_utf16().collect::<Vec<_>>(), context)
+    }
+
+    /// the string constant of the UTF-16 `content`, the same one every time
+    pub fn intern(content: &[u16], context: &mut ThreadContext) -> Ref {
         {
             let constants = strings!().0.read().unwrap();
-            if constants.contains_key(constant) {
-                return *constants.get(constant).unwrap();
+            if let Some(obj) = constants.get(content) {
+                return *obj;
             }
         }
         let mut constants = strings!().0.write().unwrap();
-        if constants.contains_key(constant) {
-            return *constants.get(constant).unwrap();
+        if let Some(obj) = constants.get(content) {
+            return *obj;
         }
         let (klass, _) =
             ClassArena::load_class("java/lang/String", context).expect("jre_not_found");
-        let obj = Heap::allocate_object_directly(&klass);
-        // let bytearray = Heap::allocate_bytes_directly(constant);
-        // let (content_field, len) = klass.layout.get(&("java/lang/String", "value", "[B")).unwrap();
-        // unsafe {
-        //     let target = Heap::ptr(obj as usize + OBJ_HEADER_SIZE + *content_field);
-        //     target.copy_from(bytearray, *len);
-        // }
-        constants.insert(constant.to_owned(), obj);
+        let obj = Self::allocate(&klass, content, context, true).expect("OutOfMemoryError");
+        constants.insert(content.to_vec(), obj);
         obj
     }
+
+    /// a new string of the UTF-16 `content`, `None` if the young generation is exhausted
+    pub fn new_string(content: &[u16], context: &mut ThreadContext) -> Option<Ref> {
+        let (klass, _) =
+            ClassArena::load_class("java/lang/String", context).expect("jre_not_found");
+        Self::allocate(&klass, content, context, false)
+    }
+
+    // constants are allocated in the old generation
+    fn allocate(
+        klass: &Arc<Klass>,
+        content: &[u16],
+        context: &mut ThreadContext,
+        constant: bool,
+    ) -> Option<Ref> {
+        let field = |name: &str, descriptor: &str| {
+            klass
+                .layout
+                .get(&("java/lang/String", name, descriptor))
+                .map(|(offset, _)| *offset)
+        };
+        let utf16 = content
+            .iter()
+            .flat_map(|c| c.to_le_bytes().to_vec())
+            .collect::<Vec<_>>();
+        let (value, descriptor, bytes, coder) = match (field("value", "[C"), field("value", "[B")) {
+            (Some(value), _) => (value, "[C", utf16, None),
+            (None, Some(value)) if content.iter().all(|c| *c <= 0xff) => {
+                let latin1 = content.iter().map(|c| *c as u8).collect();
+                (value, "[B", latin1, Some(LATIN1))
+            }
+            (None, Some(value)) => (value, "[B", utf16, Some(UTF16)),
+            _ => panic!("jre_not_found"),
+        };
+        let (array_klass, _) = ClassArena::load_class(descriptor, context).expect("jre_not_found");
+        let len = (bytes.len() / array_klass.len) as u32;
+        let (array, obj) = if constant {
+            let array = Heap::allocate_array_directly(&array_klass, len)?;
+            (array, Heap::allocate_object_directly(klass))
+        } else {
+            let array = Heap::allocate_array(&array_klass, len)?;
+            (array, Heap::allocate_object(klass))
+        };
+        unsafe {
+            let fields = Heap::ptr(obj as usize + OBJ_HEADER_SIZE);
+            // the cached hash starts from zero
+            fields.write_bytes(0, klass.len);
+            fields
+                .add(value)
+                .copy_from(array.to_le_bytes().as_ptr(), PTR_SIZE);
+            if let (Some(coder), Some(offset)) = (coder, field("coder", "B")) {
+                *fields.add(offset) = coder;
+            }
+            Heap::ptr(array as usize + OBJ_HEADER_SIZE).copy_from(bytes.as_ptr(), bytes.len());
+        }
+        Some(obj)
+    }
+
+    /// the UTF-16 content of the string `obj`
+    pub fn content(obj: Ref) -> Vec<u16> {
+        let klass = unsafe { &*Heap::as_obj(obj).klass };
+        let field = |name: &str, descriptor: &str| {
+            klass
+                .layout
+                .get(&("java/lang/String", name, descriptor))
+                .map(|(offset, _)| Heap::ptr(obj as usize + OBJ_HEADER_SIZE + *offset))
+        };
+        let (value, utf16) = match (field("value", "[C"), field("value", "[B")) {
+            (Some(value), _) => (value, true),
+            (None, Some(value)) => {
+                let coder = field("coder", "B").map(|coder| unsafe { *coder });
+                (value, coder == Some(UTF16))
+            }
+            _ => return vec![],
+        };
+        let array = Ref::from_le_bytes(unsafe { *value.cast::<[u8; PTR_SIZE]>() });
+        if array == 0 {
+            return vec![];
+        }
+        let header = Heap::as_obj(array);
+        let bytes = unsafe {
+            std::slice::from_raw_parts(
+                Heap::ptr(array as usize + OBJ_HEADER_SIZE),
+                header.size.unwrap() as usize * (*header.klass).len,
+            )
+        };
+        if utf16 {
+            bytes
+                .chunks(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect()
+        } else {
+            bytes.iter().map(|b| *b as u16).collect()
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod test {
+
+    use super::*;
+    use crate::bytecode::{
+        class::Class,
+        constant_pool::{ConstantItem, ConstantPool},
+        field::Field,
+    };
+    use crate::mem::{
+        heap::test::reset_heap, klass::test::define_object, metaspace::test::define_class,
+        pool::RuntimeConstantPool,
+    };
+    use std::cell::Cell;
+    use std::sync::{mpsc::channel, Arc};
+
+    fn string_klass(fields: &[(&str, &str)]) -> Klass {
+        let object = define_object();
+        let class = Class {
+            constant_pool: ConstantPool::new(vec![ConstantItem::NIL]),
+            access_flag: 0x0031,
+            this_class_name: "java/lang/String".to_owned(),
+            super_class_name: "java/lang/Object".to_owned(),
+            interfaces: vec![],
+            fields: fields
+                .iter()
+                .map(|(name, descriptor)| {
+                    Arc::new(Field {
+                        access_flag: 0x0012,
+                        name: name.to_string(),
+                        descriptor: descriptor.to_string(),
+                        attributes: vec![],
+                        value: Cell::new(None),
+                    })
+                })
+                .collect(),
+            methods: vec![],
+            attributes: vec![],
+            runtime_pool: RuntimeConstantPool::new(),
+        };
+        Klass::new(Arc::new(class), 0, Some(object), vec![])
+    }
+
+    /// define `java/lang/String` the way JDK 8 lays it out
+    pub fn define_string() -> Arc<Klass> {
+        define_class(string_klass(&[("value", "[C"), ("hash", "I")]))
+    }
+
+    /// no string constants, they live on the heap reset by each test
+    pub fn init_strings() {
+        Strings::init();
+    }
+
+    #[test]
+    pub fn test_content() {
+        let _heap = reset_heap();
+        let (_, rx) = channel();
+        let mut context = ThreadContext::new(1, 0, rx, channel().0);
+        let jdk8 = define_string();
+        let compact = Arc::new(string_klass(&[
+            ("value", "[B"),
+            ("coder", "B"),
+            ("hash", "I"),
+        ]));
+        for content in &["", "h\u{e9}llo", "\u{65e5}\u{672c}", "\u{1f600}!"] {
+            let utf16 = content.encode_utf16().collect::<Vec<_>>();
+            for klass in &[&jdk8, &compact] {
+                let obj = Strings::allocate(klass, &utf16, &mut context, false).unwrap();
+                assert_eq!(utf16, Strings::content(obj));
+            }
+        }
+        // LATIN1 takes a byte per char
+        let utf16 = "h\u{e9}llo".encode_utf16().collect::<Vec<_>>();
+        let obj = Strings::allocate(&compact, &utf16, &mut context, true).unwrap();
+        let value = compact
+            .layout
+            .get(&("java/lang/String", "value", "[B"))
+            .unwrap()
+            .0;
+        let array = unsafe {
+            Ref::from_le_bytes(
+                *Heap::ptr(obj as usize + OBJ_HEADER_SIZE + value).cast::<[u8; PTR_SIZE]>(),
+            )
+        };
+        assert_eq!(Some(5), Heap::as_obj(array).size);
+    }
 }